@@ -0,0 +1,65 @@
+//! Throughput of each evaluation backend over the programs in
+//! `beaver_solver::corpus`, reported per sample. Run with `cargo bench`;
+//! add `--features simd` on nightly to get the `std::simd` lanes.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+use beaver_solver::corpus::{self, INPUTS};
+use beaver_solver::rng::Rng;
+use beaver_solver::ProgramF64;
+
+/// Samples evaluated per iteration.
+const SAMPLES: usize = 1024;
+
+fn columns() -> Vec<Vec<f64>> {
+    let mut rng = Rng::new(7);
+    (0..INPUTS).map(|_| (0..SAMPLES).map(|_| rng.next_f64()).collect()).collect()
+}
+
+/// Benchmarks `make(size)` for each size, labelled by node count.
+fn backends(c: &mut Criterion, family: &str, sizes: &[usize], make: impl Fn(usize) -> ProgramF64) {
+    let columns = columns();
+    let columns: Vec<&[f64]> = columns.iter().map(|column| &column[..]).collect();
+    let rows: Vec<[f64; INPUTS]> = (0..SAMPLES)
+        .map(|row| core::array::from_fn(|input| columns[input][row]))
+        .collect();
+
+    let mut group = c.benchmark_group(family);
+    group.throughput(Throughput::Elements(SAMPLES as u64));
+    for &size in sizes {
+        let nodes = make(size).nodes.len();
+        group.bench_with_input(BenchmarkId::new("vm", nodes), &rows, |b, rows| {
+            let mut program = make(size);
+            b.iter(|| rows.iter().map(|row| program.eval(row).unwrap()).sum::<f64>())
+        });
+        group.bench_with_input(BenchmarkId::new("verified", nodes), &rows, |b, rows| {
+            let mut verified = make(size).verify().unwrap();
+            b.iter(|| rows.iter().map(|row| verified.eval(row)).sum::<f64>())
+        });
+        group.bench_with_input(BenchmarkId::new("lanes", nodes), &columns, |b, columns| {
+            let mut lanes = make(size).lanes().unwrap();
+            b.iter(|| lanes.eval_columns(black_box(columns)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn sin(c: &mut Criterion) {
+    backends(c, "sin", &[1], |_| corpus::sin());
+}
+
+fn deep(c: &mut Criterion) {
+    backends(c, "deep", &[4, 8, 12], |depth| corpus::deep(depth, 1));
+}
+
+fn wide(c: &mut Criterion) {
+    backends(c, "wide", &[16, 256, 4096], |width| corpus::wide(width, 2));
+}
+
+fn lettuce(c: &mut Criterion) {
+    backends(c, "lettuce", &[16, 256, 4096], |count| corpus::lettuce(count, 3));
+}
+
+criterion_group!(benches, sin, deep, wide, lettuce);
+criterion_main!(benches);
@@ -0,0 +1,121 @@
+//! Checks shared by the fuzz targets. Each one panics on failure, which
+//! libFuzzer reports as a crash.
+
+// each target uses only some of these
+#![allow(dead_code)]
+
+use core::fmt::Debug;
+use core::str::FromStr;
+
+use beaver_solver::dot::{to_dot, DotOptions};
+use beaver_solver::infix::{to_infix, to_latex, LetStyle};
+use beaver_solver::parse::{parse_infix, parse_sexpr, Symbols};
+use beaver_solver::program::count_vars;
+use beaver_solver::{BinaryOp, Bits, Node, OpList, Program, TernaryOp};
+
+/// Reads three bytes per node: a kind, then a little endian index that
+/// also picks the op.
+pub fn nodes<BOP: OpList, TOP: OpList>(data: &[u8]) -> Vec<Node<BOP, TOP>> {
+    data.chunks_exact(3)
+        .map(|chunk| {
+            let index = u16::from_le_bytes([chunk[1], chunk[2]]);
+            match chunk[0] % 7 {
+                0 => Node::Input(index),
+                1 => Node::Constant(index),
+                2 => Node::Literal(index),
+                3 => Node::Local(index),
+                4 => Node::Lettuce,
+                5 => Node::BinaryOp(BOP::ALL[index as usize % BOP::ALL.len()]),
+                _ => Node::TernaryOp(TOP::ALL[index as usize % TOP::ALL.len()]),
+            }
+        })
+        .collect()
+}
+
+/// Whether every local in the subtree at `position` is read inside the
+/// body of its let, the only kind of read the parsers can express.
+fn is_lexical<BOP: Copy, TOP: Copy>(
+    nodes: &[Node<BOP, TOP>], position: &mut usize, scope: &mut Vec<usize>, bound: &mut usize,
+) -> bool {
+    let node = nodes[*position];
+    *position += 1;
+    match node {
+        Node::Local(index) => scope.contains(&(index as usize)),
+        Node::Lettuce => {
+            if !is_lexical(nodes, position, scope, bound) {
+                return false;
+            }
+            scope.push(*bound);
+            *bound += 1;
+            let body = is_lexical(nodes, position, scope, bound);
+            scope.pop();
+            body
+        }
+        node => (0..node.arity()).all(|_| is_lexical(nodes, position, scope, bound)),
+    }
+}
+
+/// Runs everything that takes arbitrary nodes, then evaluates and prints
+/// the program if they form one, and checks that the s-expression and
+/// infix forms parse back to the same text.
+pub fn check_nodes<T, BOP, TOP>(nodes: Vec<Node<BOP, TOP>>, values: &[T])
+    where T: Bits + Debug + PartialEq + FromStr,
+          BOP: Copy + Debug + OpList + BinaryOp<T>,
+          TOP: Copy + Debug + OpList + TernaryOp<T>,
+{
+    count_vars(&nodes);
+    if let Ok(mut program) = Program::with_literals(nodes, values.to_vec()) {
+        let _ = program.eval(values);
+        let _ = program.set_constants(values);
+        let _ = program.eval(values);
+        check_program(&program);
+        // a verified program must evaluate to what the checked evaluator
+        // returns, which cannot fail once the fallible ops are gone
+        let expected = program.eval(values);
+        if let Ok(mut verified) = program.verify() {
+            if values.len() >= verified.program().info().input_count {
+                let value = verified.eval(values);
+                assert!(expected.as_ref().map_or(false, |expected| expected.bits() == value.bits()),
+                    "verified eval returned {:?}, eval {:?}", value, expected);
+            }
+        }
+    }
+}
+
+pub fn check_program<T, BOP, TOP>(program: &Program<T, BOP, TOP>)
+    where T: Bits + Debug + PartialEq + FromStr,
+          BOP: Copy + Debug + OpList + BinaryOp<T>,
+          TOP: Copy + Debug + OpList + TernaryOp<T>,
+{
+    let sexpr = format!("{:?}", program);
+    let infix = to_infix(program, LetStyle::Inline);
+    let where_infix = to_infix(program, LetStyle::Where);
+    to_latex(program, LetStyle::Inline);
+    to_dot(program, &DotOptions::default());
+    // neither parser reads functions, see `parse`
+    if !program.functions().is_empty() {
+        return;
+    }
+
+    // `where` hoists every let, so any program reads back from it
+    let symbols = Symbols::default();
+    let parsed: Program<T, BOP, TOP> = parse_infix(&where_infix, &symbols)
+        .unwrap_or_else(|error| panic!("{} does not parse: {}", where_infix, error));
+    assert_eq!(to_infix(&parsed, LetStyle::Where), where_infix);
+
+    // later outputs may read the lets of the ones before
+    let (mut position, mut bound) = (0, 0);
+    let lexical = (0..program.output_count()).all(|_| {
+        let mut scope = (0..bound).collect();
+        is_lexical(&program.nodes, &mut position, &mut scope, &mut bound)
+    });
+    if !lexical {
+        return;
+    }
+    let parsed: Program<T, BOP, TOP> = parse_sexpr(&sexpr, &symbols)
+        .unwrap_or_else(|error| panic!("{} does not parse: {}", sexpr, error));
+    assert_eq!(format!("{:?}", parsed), sexpr);
+    let parsed: Program<T, BOP, TOP> = parse_infix(&infix, &symbols)
+        .unwrap_or_else(|error| panic!("{} does not parse: {}", infix, error));
+    assert_eq!(to_infix(&parsed, LetStyle::Inline), infix);
+}
@@ -0,0 +1,30 @@
+//! Untrusted bytes through `codec::decode`; whatever decodes must encode
+//! to bytes that decode to the same program.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use beaver_solver::codec::{decode, encode};
+use beaver_solver::{ProgramF64, ProgramI32};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut program) = decode::<f64, _, _>(data) {
+        let program: &mut ProgramF64 = &mut program;
+        let encoded = encode(program);
+        let decoded: ProgramF64 = decode(&encoded).expect("encoded program decodes");
+        assert_eq!(encode(&decoded), encoded);
+        let _ = program.eval_multi(&vec![0.5; program.info().input_count]);
+        common::check_program(program);
+    }
+    if let Ok(mut program) = decode::<i32, _, _>(data) {
+        let program: &mut ProgramI32 = &mut program;
+        let encoded = encode(program);
+        let decoded: ProgramI32 = decode(&encoded).expect("encoded program decodes");
+        assert_eq!(encode(&decoded), encoded);
+        let _ = program.eval_multi(&vec![-3; program.info().input_count]);
+        common::check_program(program);
+    }
+});
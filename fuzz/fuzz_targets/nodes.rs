@@ -0,0 +1,27 @@
+//! Arbitrary node vectors through `Program::with_literals`, `count_vars`,
+//! `eval` and the printers, for a float and an integer type.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use beaver_solver::{NodeF64, NodeI32};
+
+mod common;
+
+const F64_VALUES: [f64; 8] = [0.0, -0.0, 1.0, -1.5, f64::NAN, f64::INFINITY, f64::MIN_POSITIVE, f64::MAX];
+const I32_VALUES: [i32; 8] = [0, 1, -1, 31, 32, -7, i32::MIN, i32::MAX];
+
+fuzz_target!(|data: &[u8]| {
+    let (&kind, data) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    if kind % 2 == 0 {
+        let nodes: Vec<NodeF64> = common::nodes(data);
+        common::check_nodes(nodes, &F64_VALUES);
+    } else {
+        let nodes: Vec<NodeI32> = common::nodes(data);
+        common::check_nodes(nodes, &I32_VALUES);
+    }
+});
@@ -0,0 +1,29 @@
+//! Untrusted text through both parsers; whatever parses is evaluated and
+//! must print back to text that parses to the same program.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use beaver_solver::parse::{parse_infix, parse_sexpr, Symbols};
+use beaver_solver::{ProgramF64, ProgramI32};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    let symbols = Symbols::default();
+    for program in [parse_infix(text, &symbols), parse_sexpr(text, &symbols)].iter_mut().flatten() {
+        let program: &mut ProgramF64 = program;
+        let _ = program.eval(&vec![0.5; program.info().input_count]);
+        common::check_program(program);
+    }
+    for program in [parse_infix(text, &symbols), parse_sexpr(text, &symbols)].iter_mut().flatten() {
+        let program: &mut ProgramI32 = program;
+        let _ = program.eval(&vec![-3; program.info().input_count]);
+        common::check_program(program);
+    }
+});
@@ -0,0 +1,268 @@
+use core::fmt;
+use crate::double_double::DoubleDouble;
+use crate::program::{ProgramError, ProgramF64};
+use crate::rng::Rng;
+
+/// A reference result that an `f64` output can be compared against.
+/// Implemented for `f64` itself and for wider types, so the reference
+/// can carry more precision than the value under test.
+pub trait Reference: Copy {
+    /// Distance from `actual` to the reference, in units in the last
+    /// place of `f64` at the reference value.
+    fn ulp_error(self, actual: f64) -> f64;
+}
+
+/// Maps the bit pattern to an integer line where adjacent floats differ by 1.
+fn ordered_bits(x: f64) -> i64 {
+    let bits = x.to_bits() as i64;
+    if bits < 0 {
+        i64::MIN - bits
+    } else {
+        bits
+    }
+}
+
+/// Number of representable `f64`s between `a` and `b`.
+pub fn ulp_distance(a: f64, b: f64) -> u64 {
+    if a.is_nan() || b.is_nan() {
+        return if a.is_nan() && b.is_nan() { 0 } else { u64::MAX };
+    }
+    (ordered_bits(a) as i128 - ordered_bits(b) as i128).unsigned_abs() as u64
+}
+
+/// Spacing between `x` and the next `f64` away from zero.
+pub fn ulp(x: f64) -> f64 {
+    let x = x.abs();
+    if x.is_finite() {
+        if x == f64::MAX {
+            x - x.next_down()
+        } else {
+            x.next_up() - x
+        }
+    } else {
+        x
+    }
+}
+
+impl Reference for f64 {
+    fn ulp_error(self, actual: f64) -> f64 {
+        match ulp_distance(self, actual) {
+            u64::MAX => f64::INFINITY,
+            distance => distance as f64,
+        }
+    }
+}
+
+impl Reference for DoubleDouble {
+    fn ulp_error(self, actual: f64) -> f64 {
+        if self.is_nan() || actual.is_nan() || !self.hi.is_finite() || !actual.is_finite() {
+            return self.hi.ulp_error(actual);
+        }
+        (DoubleDouble::from(actual) - self).abs().to_f64() / ulp(self.hi)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AccuracyConfig {
+    pub lo: f64,
+    pub hi: f64,
+    /// Evenly spaced points over `[lo, hi]`.
+    pub uniform: usize,
+    /// Uniformly random points over `[lo, hi]`.
+    pub random: usize,
+    pub seed: u64,
+    /// Endpoints, signed zeros, subnormals and the neighbourhood of
+    /// every power of two inside the range.
+    pub adversarial: bool,
+    /// How many of the worst inputs to keep in the report.
+    pub worst: usize,
+}
+
+impl AccuracyConfig {
+    pub fn new(lo: f64, hi: f64) -> Self {
+        Self {
+            lo,
+            hi,
+            uniform: 10_000,
+            random: 10_000,
+            seed: 1,
+            adversarial: true,
+            worst: 8,
+        }
+    }
+}
+
+/// Number of histogram buckets, see `AccuracyReport::histogram`.
+pub const HISTOGRAM_BUCKETS: usize = 16;
+
+/// Upper bound (inclusive) of a histogram bucket, in ulps.
+pub fn bucket_bound(bucket: usize) -> f64 {
+    match bucket {
+        0 => 0.0,
+        b if b + 1 == HISTOGRAM_BUCKETS => f64::INFINITY,
+        b => 2.0_f64.powi(b as i32 - 2),
+    }
+}
+
+fn bucket_of(error: f64) -> usize {
+    (0..HISTOGRAM_BUCKETS)
+        .find(|&bucket| error <= bucket_bound(bucket))
+        .unwrap_or(HISTOGRAM_BUCKETS - 1)
+}
+
+#[derive(Debug, Clone)]
+pub struct AccuracyReport {
+    pub samples: usize,
+    pub max_ulp: f64,
+    pub mean_ulp: f64,
+    /// `(input, ulp error)` pairs, worst first.
+    pub worst: Vec<(f64, f64)>,
+    /// Bucket `i` counts errors in `(bucket_bound(i - 1), bucket_bound(i)]`:
+    /// exact, up to 0.5 ulp, up to 1 ulp, 2, 4, ... and everything else.
+    pub histogram: [usize; HISTOGRAM_BUCKETS],
+}
+
+fn adversarial_points(lo: f64, hi: f64, points: &mut Vec<f64>) {
+    let candidates = [
+        lo, lo.next_up(), hi, hi.next_down(), 0.0, -0.0,
+        f64::from_bits(1), -f64::from_bits(1),
+        f64::MIN_POSITIVE, -f64::MIN_POSITIVE,
+        lo + (hi - lo) * 0.5,
+    ];
+    points.extend(candidates.iter().copied());
+
+    for exp in -1074..f64::MAX_EXP {
+        let power = if exp < -1022 {
+            f64::from_bits(1 << (exp + 1074))
+        } else {
+            f64::from_bits(((exp + 1023) as u64) << 52)
+        };
+        for &p in &[power, -power] {
+            points.push(p.next_down());
+            points.push(p);
+            points.push(p.next_up());
+        }
+    }
+
+    points.retain(|&x| x >= lo && x <= hi);
+}
+
+/// Evaluates a single-input program and a reference function over the
+/// configured range and summarizes the error in ulps.
+pub fn measure<R, F>(program: &mut ProgramF64, mut reference: F, config: &AccuracyConfig)
+    -> Result<AccuracyReport, ProgramError>
+    where R: Reference,
+          F: FnMut(f64) -> R,
+{
+    let AccuracyConfig { lo, hi, .. } = *config;
+    let mut points = Vec::with_capacity(config.uniform + config.random);
+    if config.uniform == 1 {
+        points.push(lo);
+    } else {
+        let step = (hi - lo) / ((config.uniform.max(2) - 1) as f64);
+        points.extend((0..config.uniform).map(|ii| lo + step * (ii as f64)));
+    }
+    let mut rng = Rng::new(config.seed);
+    points.extend((0..config.random).map(|_| lo + (hi - lo) * rng.next_f64()));
+    if config.adversarial {
+        adversarial_points(lo, hi, &mut points);
+    }
+
+    let mut report = AccuracyReport {
+        samples: points.len(),
+        max_ulp: 0.0,
+        mean_ulp: 0.0,
+        worst: Vec::with_capacity(config.worst + 1),
+        histogram: [0; HISTOGRAM_BUCKETS],
+    };
+    let mut total = 0.0;
+
+    for x in points {
+        let actual = program.eval(&[x])?;
+        let error = reference(x).ulp_error(actual);
+        report.histogram[bucket_of(error)] += 1;
+        report.max_ulp = report.max_ulp.max(error);
+        total += error;
+
+        let worst = &mut report.worst;
+        if worst.len() < config.worst || worst.last().is_some_and(|w| w.1 < error) {
+            let at = worst.iter().position(|w| w.1 < error).unwrap_or(worst.len());
+            worst.insert(at, (x, error));
+            worst.truncate(config.worst);
+        }
+    }
+
+    if report.samples > 0 {
+        report.mean_ulp = total / (report.samples as f64);
+    }
+    Ok(report)
+}
+
+impl fmt::Display for AccuracyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "samples: {}, max: {:.3} ulp, mean: {:.3} ulp",
+            self.samples, self.max_ulp, self.mean_ulp)?;
+        for (x, error) in &self.worst {
+            writeln!(f, "  worst: x={:e} ({:.3} ulp)", x, error)?;
+        }
+        for (bucket, &count) in self.histogram.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let bound = bucket_bound(bucket);
+            if bound.is_finite() {
+                writeln!(f, "  <= {:>6} ulp: {}", bound, count)?;
+            } else {
+                writeln!(f, "   > {:>6} ulp: {}", bucket_bound(bucket - 1), count)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_op::BinaryOpF64;
+    use crate::program::Node;
+
+    #[test]
+    fn distances() {
+        assert_eq!(ulp_distance(1.0, 1.0), 0);
+        assert_eq!(ulp_distance(1.0, 1.0_f64.next_up()), 1);
+        assert_eq!(ulp_distance(-0.0, 0.0), 0);
+        assert_eq!(ulp_distance(f64::from_bits(1), -f64::from_bits(1)), 2);
+        assert_eq!(ulp_distance(f64::NAN, 1.0), u64::MAX);
+        assert_eq!(ulp(1.0), f64::EPSILON);
+    }
+
+    #[test]
+    fn buckets() {
+        assert_eq!(bucket_of(0.0), 0);
+        assert_eq!(bucket_of(0.25), 1);
+        assert_eq!(bucket_of(1.0), 2);
+        assert_eq!(bucket_of(1.5), 3);
+        assert_eq!(bucket_of(f64::INFINITY), HISTOGRAM_BUCKETS - 1);
+    }
+
+    #[test]
+    fn measure_square() {
+        let mut program = ProgramF64::new(vec![
+            Node::BinaryOp(BinaryOpF64::Mul),
+            Node::Input(0),
+            Node::Input(0),
+        ]).unwrap();
+
+        let config = AccuracyConfig::new(-2.0, 2.0);
+        let exact = measure(&mut program, |x| x * x, &config).unwrap();
+        assert_eq!(exact.max_ulp, 0.0);
+        assert_eq!(exact.histogram[0], exact.samples);
+
+        let off = measure(&mut program, |x| (x * x).next_up(), &config).unwrap();
+        assert_eq!(off.max_ulp, 1.0);
+        assert_eq!(off.worst.len(), config.worst);
+
+        let half = measure(&mut program, |x| DoubleDouble::from(x * x) + DoubleDouble::from(ulp(x * x) / 2.0), &config).unwrap();
+        assert_eq!(half.max_ulp, 0.5);
+    }
+}
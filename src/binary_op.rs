@@ -1,21 +1,59 @@
 pub trait BinaryOp<T> {
     fn run(&self, lhs: T, rhs: T) -> T;
+    /// Like `run`, but `None` when the op is undefined for these operands.
+    /// Only the checked integer ops ever fail.
+    fn try_run(&self, lhs: T, rhs: T) -> Option<T> {
+        Some(self.run(lhs, rhs))
+    }
+    /// Whether `try_run` can return `None` for some operands.
+    fn is_fallible(&self) -> bool {
+        false
+    }
     fn repr(&self) -> &'static str;
 }
 
+/// Every variant of an op enum in a fixed order. The position of an op in
+/// `ALL` is its opcode in the binary program format, so new variants go
+/// at the end.
+pub trait OpList: Sized + Copy + PartialEq + 'static {
+    const ALL: &'static [Self];
+}
+
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOpF32 {
+    #[cfg_attr(feature = "serde", serde(rename = "+"))]
     Add,
+    #[cfg_attr(feature = "serde", serde(rename = "-"))]
     Sub,
+    #[cfg_attr(feature = "serde", serde(rename = "*"))]
     Mul,
+    #[cfg_attr(feature = "serde", serde(rename = "/"))]
     Div,
+    #[cfg_attr(feature = "serde", serde(rename = "min"))]
     Min,
+    #[cfg_attr(feature = "serde", serde(rename = "max"))]
     Max,
+    #[cfg_attr(feature = "serde", serde(rename = "pow"))]
     Pow,
+    #[cfg_attr(feature = "serde", serde(rename = "hypot"))]
     Hypot,
 }
 
+impl OpList for BinaryOpF32 {
+    const ALL: &'static [Self] = &[
+        BinaryOpF32::Add,
+        BinaryOpF32::Sub,
+        BinaryOpF32::Mul,
+        BinaryOpF32::Div,
+        BinaryOpF32::Min,
+        BinaryOpF32::Max,
+        BinaryOpF32::Pow,
+        BinaryOpF32::Hypot,
+    ];
+}
+
 impl BinaryOp<f32> for BinaryOpF32 {
     fn run(&self, lhs: f32, rhs: f32) -> f32 {
         match self {
@@ -44,18 +82,40 @@ impl BinaryOp<f32> for BinaryOpF32 {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOpF64 {
+    #[cfg_attr(feature = "serde", serde(rename = "+"))]
     Add,
+    #[cfg_attr(feature = "serde", serde(rename = "-"))]
     Sub,
+    #[cfg_attr(feature = "serde", serde(rename = "*"))]
     Mul,
+    #[cfg_attr(feature = "serde", serde(rename = "/"))]
     Div,
+    #[cfg_attr(feature = "serde", serde(rename = "min"))]
     Min,
+    #[cfg_attr(feature = "serde", serde(rename = "max"))]
     Max,
+    #[cfg_attr(feature = "serde", serde(rename = "pow"))]
     Pow,
+    #[cfg_attr(feature = "serde", serde(rename = "hypot"))]
     Hypot
 }
 
+impl OpList for BinaryOpF64 {
+    const ALL: &'static [Self] = &[
+        BinaryOpF64::Add,
+        BinaryOpF64::Sub,
+        BinaryOpF64::Mul,
+        BinaryOpF64::Div,
+        BinaryOpF64::Min,
+        BinaryOpF64::Max,
+        BinaryOpF64::Pow,
+        BinaryOpF64::Hypot,
+    ];
+}
+
 impl BinaryOp<f64> for BinaryOpF64 {
     fn run(&self, lhs: f64, rhs: f64) -> f64 {
         match self {
@@ -83,46 +143,245 @@ impl BinaryOp<f64> for BinaryOpF64 {
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
-pub enum BinaryOpI32 {
-    Add,
-    Sub,
-    Mul,
-    Div,
-    Xor,
-    And,
-    Or,
-    Shl,
-    Shr,
-}
+macro_rules! integer_binary_op {
+    ($name:ident, $t:ty, $unsigned:ty) => {
+        /// Integer operations are total: the plain arithmetic ops wrap on
+        /// overflow, division by zero gives 0, remainder by zero gives the
+        /// dividend, and shift amounts are taken modulo the bit width.
+        /// `Shr` shifts like Rust's `>>` on the type, arithmetically for
+        /// signed and logically for unsigned types; `LogicalShr` is always
+        /// logical.
+        ///
+        /// The `Checked*` ops fail evaluation with
+        /// `ErrorKind::ArithmeticError` on overflow or division by zero
+        /// (`run` alone yields 0 for them), the `Saturating*` ops clamp to
+        /// the bounds of the type. Comparisons return 1 for true, 0 for false.
+        #[allow(dead_code)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $name {
+            #[cfg_attr(feature = "serde", serde(rename = "+"))]
+            Add,
+            #[cfg_attr(feature = "serde", serde(rename = "-"))]
+            Sub,
+            #[cfg_attr(feature = "serde", serde(rename = "*"))]
+            Mul,
+            #[cfg_attr(feature = "serde", serde(rename = "/"))]
+            Div,
+            #[cfg_attr(feature = "serde", serde(rename = "%"))]
+            Rem,
+            #[cfg_attr(feature = "serde", serde(rename = "min"))]
+            Min,
+            #[cfg_attr(feature = "serde", serde(rename = "max"))]
+            Max,
+            #[cfg_attr(feature = "serde", serde(rename = "^"))]
+            Xor,
+            #[cfg_attr(feature = "serde", serde(rename = "&"))]
+            And,
+            #[cfg_attr(feature = "serde", serde(rename = "|"))]
+            Or,
+            #[cfg_attr(feature = "serde", serde(rename = "<<"))]
+            Shl,
+            #[cfg_attr(feature = "serde", serde(rename = ">>"))]
+            Shr,
+            #[cfg_attr(feature = "serde", serde(rename = "checked_add"))]
+            CheckedAdd,
+            #[cfg_attr(feature = "serde", serde(rename = "checked_sub"))]
+            CheckedSub,
+            #[cfg_attr(feature = "serde", serde(rename = "checked_mul"))]
+            CheckedMul,
+            #[cfg_attr(feature = "serde", serde(rename = "checked_div"))]
+            CheckedDiv,
+            #[cfg_attr(feature = "serde", serde(rename = "checked_rem"))]
+            CheckedRem,
+            #[cfg_attr(feature = "serde", serde(rename = "saturating_add"))]
+            SaturatingAdd,
+            #[cfg_attr(feature = "serde", serde(rename = "saturating_sub"))]
+            SaturatingSub,
+            #[cfg_attr(feature = "serde", serde(rename = "saturating_mul"))]
+            SaturatingMul,
+            #[cfg_attr(feature = "serde", serde(rename = "=="))]
+            Eq,
+            #[cfg_attr(feature = "serde", serde(rename = "!="))]
+            Ne,
+            #[cfg_attr(feature = "serde", serde(rename = "<"))]
+            Lt,
+            #[cfg_attr(feature = "serde", serde(rename = "<="))]
+            Le,
+            #[cfg_attr(feature = "serde", serde(rename = ">"))]
+            Gt,
+            #[cfg_attr(feature = "serde", serde(rename = ">="))]
+            Ge,
+            #[cfg_attr(feature = "serde", serde(rename = ">>>"))]
+            LogicalShr,
+        }
 
-impl BinaryOp<i32> for BinaryOpI32 {
-    fn run(&self, lhs: i32, rhs: i32) -> i32 {
-        match self {
-            BinaryOpI32::Add => lhs + rhs,
-            BinaryOpI32::Sub => lhs - rhs,
-            BinaryOpI32::Mul => lhs * rhs,
-            BinaryOpI32::Div => lhs.checked_div(rhs).unwrap_or(0),
-            BinaryOpI32::Xor => lhs ^ rhs,
-            BinaryOpI32::And => lhs & rhs,
-            BinaryOpI32::Shl => lhs << rhs,
-            BinaryOpI32::Shr => lhs >> rhs,
-            BinaryOpI32::Or  => lhs | rhs,
+        impl OpList for $name {
+            const ALL: &'static [Self] = &[
+                $name::Add,
+                $name::Sub,
+                $name::Mul,
+                $name::Div,
+                $name::Rem,
+                $name::Min,
+                $name::Max,
+                $name::Xor,
+                $name::And,
+                $name::Or,
+                $name::Shl,
+                $name::Shr,
+                $name::CheckedAdd,
+                $name::CheckedSub,
+                $name::CheckedMul,
+                $name::CheckedDiv,
+                $name::CheckedRem,
+                $name::SaturatingAdd,
+                $name::SaturatingSub,
+                $name::SaturatingMul,
+                $name::Eq,
+                $name::Ne,
+                $name::Lt,
+                $name::Le,
+                $name::Gt,
+                $name::Ge,
+                $name::LogicalShr,
+            ];
         }
-    }
 
-    fn repr(&self) -> &'static str {
-        match self {
-            BinaryOpI32::Add => "+",
-            BinaryOpI32::Sub => "-",
-            BinaryOpI32::Mul => "*",
-            BinaryOpI32::Div => "/",
-            BinaryOpI32::Xor => "^",
-            BinaryOpI32::And => "&",
-            BinaryOpI32::Or  => "|",
-            BinaryOpI32::Shl => "<<",
-            BinaryOpI32::Shr => ">>",
+        impl BinaryOp<$t> for $name {
+            fn run(&self, lhs: $t, rhs: $t) -> $t {
+                match self {
+                    $name::Add => lhs.wrapping_add(rhs),
+                    $name::Sub => lhs.wrapping_sub(rhs),
+                    $name::Mul => lhs.wrapping_mul(rhs),
+                    $name::Div => if rhs == 0 { 0 } else { lhs.wrapping_div(rhs) },
+                    $name::Rem => if rhs == 0 { lhs } else { lhs.wrapping_rem(rhs) },
+                    $name::Min => lhs.min(rhs),
+                    $name::Max => lhs.max(rhs),
+                    $name::Xor => lhs ^ rhs,
+                    $name::And => lhs & rhs,
+                    $name::Or  => lhs | rhs,
+                    $name::Shl => lhs.wrapping_shl(rhs as u32),
+                    $name::Shr => lhs.wrapping_shr(rhs as u32),
+                    $name::LogicalShr => (lhs as $unsigned).wrapping_shr(rhs as u32) as $t,
+                    $name::SaturatingAdd => lhs.saturating_add(rhs),
+                    $name::SaturatingSub => lhs.saturating_sub(rhs),
+                    $name::SaturatingMul => lhs.saturating_mul(rhs),
+                    $name::Eq => (lhs == rhs) as $t,
+                    $name::Ne => (lhs != rhs) as $t,
+                    $name::Lt => (lhs < rhs) as $t,
+                    $name::Le => (lhs <= rhs) as $t,
+                    $name::Gt => (lhs > rhs) as $t,
+                    $name::Ge => (lhs >= rhs) as $t,
+                    $name::CheckedAdd | $name::CheckedSub | $name::CheckedMul |
+                    $name::CheckedDiv | $name::CheckedRem => {
+                        self.try_run(lhs, rhs).unwrap_or(0)
+                    }
+                }
+            }
+
+            fn try_run(&self, lhs: $t, rhs: $t) -> Option<$t> {
+                match self {
+                    $name::CheckedAdd => lhs.checked_add(rhs),
+                    $name::CheckedSub => lhs.checked_sub(rhs),
+                    $name::CheckedMul => lhs.checked_mul(rhs),
+                    $name::CheckedDiv => lhs.checked_div(rhs),
+                    $name::CheckedRem => lhs.checked_rem(rhs),
+                    _ => Some(self.run(lhs, rhs)),
+                }
+            }
+
+            fn is_fallible(&self) -> bool {
+                matches!(self,
+                    $name::CheckedAdd | $name::CheckedSub | $name::CheckedMul |
+                    $name::CheckedDiv | $name::CheckedRem)
+            }
+
+            fn repr(&self) -> &'static str {
+                match self {
+                    $name::Add => "+",
+                    $name::Sub => "-",
+                    $name::Mul => "*",
+                    $name::Div => "/",
+                    $name::Rem => "%",
+                    $name::Min => "min",
+                    $name::Max => "max",
+                    $name::Xor => "^",
+                    $name::And => "&",
+                    $name::Or  => "|",
+                    $name::Shl => "<<",
+                    $name::Shr => ">>",
+                    $name::LogicalShr => ">>>",
+                    $name::CheckedAdd => "checked_add",
+                    $name::CheckedSub => "checked_sub",
+                    $name::CheckedMul => "checked_mul",
+                    $name::CheckedDiv => "checked_div",
+                    $name::CheckedRem => "checked_rem",
+                    $name::SaturatingAdd => "saturating_add",
+                    $name::SaturatingSub => "saturating_sub",
+                    $name::SaturatingMul => "saturating_mul",
+                    $name::Eq => "==",
+                    $name::Ne => "!=",
+                    $name::Lt => "<",
+                    $name::Le => "<=",
+                    $name::Gt => ">",
+                    $name::Ge => ">=",
+                }
+            }
         }
+    };
+}
+
+integer_binary_op!(BinaryOpI32, i32, u32);
+integer_binary_op!(BinaryOpU32, u32, u32);
+integer_binary_op!(BinaryOpI64, i64, u64);
+integer_binary_op!(BinaryOpU64, u64, u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer_semantics() {
+        use BinaryOpI32::*;
+        assert_eq!(Add.run(i32::MAX, 1), i32::MIN);
+        assert_eq!(Mul.run(i32::MAX, 2), -2);
+        assert_eq!(Div.run(i32::MIN, -1), i32::MIN);
+        assert_eq!(Div.run(7, 0), 0);
+        assert_eq!(Rem.run(7, 0), 7);
+        assert_eq!(Rem.run(i32::MIN, -1), 0);
+        assert_eq!(Shl.run(1, 33), 2);
+        assert_eq!(LogicalShr.run(-1, 28), 0xf);
+        assert_eq!(Shr.run(-16, 2), -4);
+        assert_eq!(Shr.run(-16, -1), -1);
+        assert_eq!(SaturatingAdd.run(i32::MAX, 1), i32::MAX);
+        assert_eq!(SaturatingMul.run(i32::MIN, 2), i32::MIN);
+        assert_eq!(CheckedAdd.try_run(i32::MAX, 1), None);
+        assert_eq!(CheckedDiv.try_run(1, 0), None);
+        assert_eq!(CheckedDiv.try_run(i32::MIN, -1), None);
+        assert_eq!(CheckedMul.try_run(3, 4), Some(12));
+        assert_eq!(CheckedAdd.run(i32::MAX, 1), 0);
+        assert_eq!(Add.try_run(i32::MAX, 1), Some(i32::MIN));
+    }
+
+    #[test]
+    fn unsigned_semantics() {
+        assert_eq!(BinaryOpU32::Sub.run(0, 1), u32::MAX);
+        assert_eq!(BinaryOpU32::Shr.run(0x8000_0000, 4), 0x0800_0000);
+        assert_eq!(BinaryOpU64::Shr.run(u64::MAX, 60), 0xf);
+        assert_eq!(BinaryOpU32::LogicalShr.run(0x8000_0000, 4), 0x0800_0000);
+        assert_eq!(BinaryOpU64::SaturatingSub.run(1, 2), 0);
+        assert_eq!(BinaryOpI64::Shl.run(1, 64), 1);
+        assert_eq!(BinaryOpI64::Min.run(-3, 2), -3);
+        assert_eq!(BinaryOpU64::Max.run(3, u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(BinaryOpI32::Lt.run(-1, 0), 1);
+        assert_eq!(BinaryOpU32::Lt.run(u32::MAX, 0), 0);
+        assert_eq!(BinaryOpI64::Ge.run(2, 2), 1);
+        assert_eq!(BinaryOpU64::Ne.run(2, 2), 0);
+        assert_eq!(BinaryOpI32::Eq.run(5, 5), 1);
     }
 }
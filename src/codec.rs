@@ -0,0 +1,514 @@
+//! Compact binary encoding of programs.
+//!
+//! ```text
+//! magic     b"BVRP"
+//! version   u8, `VERSION`
+//! type      u8, see `Numeric::TAG`
+//! outputs   varint, the number of trees in the nodes
+//! nodes     varint count, then per node a one-byte opcode:
+//!           0..=3 input, local, constant, literal, followed by a varint index
+//!           4 let, 0x10 + n binary op n, 0x80 + n ternary op n
+//!           (n is the op's position in `OpList::ALL`)
+//!           5 call, followed by a varint function index and an arity byte
+//! constants varint count, then little-endian values
+//! literals  varint count, then little-endian values
+//! functions only if any: varint count, then per function a varint
+//!           length and UTF-8 name, a varint arity up to 255 and nodes
+//!           as above
+//! checksum  CRC-32 of everything above, little-endian u32
+//! ```
+
+use core::fmt;
+use crate::binary_op::{BinaryOp, OpList};
+use crate::ternary_op::TernaryOp;
+use crate::program::{check_names, Function, Index, Node, Program, ProgramError};
+
+pub const MAGIC: &[u8; 4] = b"BVRP";
+pub const VERSION: u8 = 1;
+
+const OP_INPUT: u8 = 0;
+const OP_LOCAL: u8 = 1;
+const OP_CONSTANT: u8 = 2;
+const OP_LITERAL: u8 = 3;
+const OP_LETTUCE: u8 = 4;
+const OP_CALL: u8 = 5;
+const OP_BINARY: u8 = 0x10;
+const OP_TERNARY: u8 = 0x80;
+
+/// Value types that can be stored in the binary format.
+pub trait Numeric: Copy {
+    const TAG: u8;
+    const NAME: &'static str;
+    const SIZE: usize;
+    fn write(self, out: &mut Vec<u8>);
+    /// `bytes` is exactly `SIZE` long.
+    fn read(bytes: &[u8]) -> Self;
+}
+
+macro_rules! numeric {
+    ($t:ty, $tag:expr) => {
+        impl Numeric for $t {
+            const TAG: u8 = $tag;
+            const NAME: &'static str = stringify!($t);
+            const SIZE: usize = core::mem::size_of::<$t>();
+            fn write(self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+            fn read(bytes: &[u8]) -> Self {
+                let mut raw = [0; core::mem::size_of::<$t>()];
+                raw.copy_from_slice(bytes);
+                <$t>::from_le_bytes(raw)
+            }
+        }
+    };
+}
+
+numeric!(f32, 1);
+numeric!(f64, 2);
+numeric!(i32, 3);
+numeric!(u32, 4);
+numeric!(i64, 5);
+numeric!(u64, 6);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    TypeMismatch { expected: u8, found: u8 },
+    ChecksumMismatch { expected: u32, found: u32 },
+    Truncated,
+    BadVarint { offset: usize },
+    BadOpcode { offset: usize, opcode: u8 },
+    BadName { offset: usize },
+    OutputCount { expected: usize, found: usize },
+    TrailingBytes { offset: usize },
+    Program(ProgramError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => f.write_str("not an encoded program"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported format version {}", version)
+            }
+            DecodeError::TypeMismatch { expected, found } => {
+                write!(f, "program has type tag {}, expected {}", found, expected)
+            }
+            DecodeError::ChecksumMismatch { expected, found } => {
+                write!(f, "checksum mismatch: stored {:08x}, computed {:08x}", expected, found)
+            }
+            DecodeError::Truncated => f.write_str("data ends unexpectedly"),
+            DecodeError::BadVarint { offset } => write!(f, "invalid varint at byte {}", offset),
+            DecodeError::BadOpcode { offset, opcode } => {
+                write!(f, "invalid opcode {:#04x} at byte {}", opcode, offset)
+            }
+            DecodeError::BadName { offset } => write!(f, "invalid function name at byte {}", offset),
+            DecodeError::OutputCount { expected, found } => {
+                write!(f, "header says {} outputs, the nodes hold {}", expected, found)
+            }
+            DecodeError::TrailingBytes { offset } => {
+                write!(f, "unexpected data after the program at byte {}", offset)
+            }
+            DecodeError::Program(error) => write!(f, "invalid program: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<ProgramError> for DecodeError {
+    fn from(error: ProgramError) -> Self {
+        DecodeError::Program(error)
+    }
+}
+
+/// CRC-32 (IEEE 802.3, reflected).
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self.bytes.get(self.offset).ok_or(DecodeError::Truncated)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self.offset.checked_add(len).ok_or(DecodeError::Truncated)?;
+        let slice = self.bytes.get(self.offset..end).ok_or(DecodeError::Truncated)?;
+        self.offset = end;
+        Ok(slice)
+    }
+
+    fn varint(&mut self) -> Result<usize, DecodeError> {
+        let start = self.offset;
+        let mut value = 0usize;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7f) as usize;
+            if shift >= usize::BITS || (bits << shift) >> shift != bits {
+                return Err(DecodeError::BadVarint { offset: start });
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::BadVarint { offset: start })
+    }
+
+    fn index(&mut self) -> Result<Index, DecodeError> {
+        let start = self.offset;
+        let value = self.varint()?;
+        if value > Index::MAX as usize {
+            return Err(DecodeError::BadVarint { offset: start });
+        }
+        Ok(value as Index)
+    }
+
+    fn nodes<BOP: OpList, TOP: OpList>(&mut self) -> Result<Vec<Node<BOP, TOP>>, DecodeError> {
+        let count = self.varint()?;
+        // every node takes at least a byte, don't trust the count beyond that
+        let mut nodes = Vec::with_capacity(count.min(self.bytes.len()));
+        for _ in 0..count {
+            let offset = self.offset;
+            let node = match self.byte()? {
+                OP_INPUT => Node::Input(self.index()?),
+                OP_LOCAL => Node::Local(self.index()?),
+                OP_CONSTANT => Node::Constant(self.index()?),
+                OP_LITERAL => Node::Literal(self.index()?),
+                OP_LETTUCE => Node::Lettuce,
+                OP_CALL => Node::Call(self.index()?, self.byte()?),
+                opcode => {
+                    let op = if opcode >= OP_TERNARY {
+                        TOP::ALL.get((opcode - OP_TERNARY) as usize).map(|&op| Node::TernaryOp(op))
+                    } else if opcode >= OP_BINARY {
+                        BOP::ALL.get((opcode - OP_BINARY) as usize).map(|&op| Node::BinaryOp(op))
+                    } else {
+                        None
+                    };
+                    op.ok_or(DecodeError::BadOpcode { offset, opcode })?
+                }
+            };
+            nodes.push(node);
+        }
+        Ok(nodes)
+    }
+
+    fn values<T: Numeric>(&mut self) -> Result<Vec<T>, DecodeError> {
+        let count = self.varint()?;
+        let bytes = self.take(count.checked_mul(T::SIZE).ok_or(DecodeError::Truncated)?)?;
+        Ok(bytes.chunks_exact(T::SIZE).map(T::read).collect())
+    }
+}
+
+pub fn encode<T, BOP, TOP>(program: &Program<T, BOP, TOP>) -> Vec<u8>
+    where T: Numeric + fmt::Debug,
+          BOP: OpList + BinaryOp<T>,
+          TOP: OpList + TernaryOp<T>,
+{
+    let mut out = Vec::with_capacity(16 + program.nodes.len());
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(T::TAG);
+
+    write_varint(&mut out, program.output_count());
+    write_nodes(&mut out, &program.nodes);
+    for values in &[program.constants(), program.literals()] {
+        write_varint(&mut out, values.len());
+        for &value in values.iter() {
+            value.write(&mut out);
+        }
+    }
+    if !program.functions().is_empty() {
+        write_varint(&mut out, program.functions().len());
+        for function in program.functions() {
+            write_varint(&mut out, function.name.len());
+            out.extend_from_slice(function.name.as_bytes());
+            write_varint(&mut out, function.arity);
+            write_nodes(&mut out, &function.nodes);
+        }
+    }
+
+    let checksum = crc32(&out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+fn write_nodes<BOP: OpList, TOP: OpList>(out: &mut Vec<u8>, nodes: &[Node<BOP, TOP>]) {
+    write_varint(out, nodes.len());
+    for node in nodes {
+        let (opcode, index) = match *node {
+            Node::Input(index) => (OP_INPUT, Some(index)),
+            Node::Local(index) => (OP_LOCAL, Some(index)),
+            Node::Constant(index) => (OP_CONSTANT, Some(index)),
+            Node::Literal(index) => (OP_LITERAL, Some(index)),
+            Node::Lettuce => (OP_LETTUCE, None),
+            Node::BinaryOp(op) => (OP_BINARY + position(BOP::ALL, op), None),
+            Node::TernaryOp(op) => (OP_TERNARY + position(TOP::ALL, op), None),
+            Node::Call(index, arity) => {
+                out.push(OP_CALL);
+                write_varint(out, index as usize);
+                out.push(arity);
+                continue;
+            }
+        };
+        out.push(opcode);
+        if let Some(index) = index {
+            write_varint(out, index as usize);
+        }
+    }
+}
+
+fn position<OP: OpList>(all: &[OP], op: OP) -> u8 {
+    all.iter().position(|&known| known == op)
+        .expect("op missing from OpList::ALL") as u8
+}
+
+/// Decodes a program encoded by `encode` for the same numeric type. The
+/// trees are validated as in `Program::multi` and must number as many as
+/// the header says, and any functions are checked as in
+/// `Program::with_functions`.
+pub fn decode<T, BOP, TOP>(bytes: &[u8]) -> Result<Program<T, BOP, TOP>, DecodeError>
+    where T: Numeric + fmt::Debug,
+          BOP: OpList + BinaryOp<T>,
+          TOP: OpList + TernaryOp<T>,
+{
+    let header = bytes.get(..6).ok_or(DecodeError::Truncated)?;
+    if &header[..4] != MAGIC {
+        return Err(DecodeError::BadMagic);
+    }
+    if header[4] != VERSION {
+        return Err(DecodeError::UnsupportedVersion(header[4]));
+    }
+    if header[5] != T::TAG {
+        return Err(DecodeError::TypeMismatch { expected: T::TAG, found: header[5] });
+    }
+    if bytes.len() < 10 {
+        return Err(DecodeError::Truncated);
+    }
+    let (body, stored) = bytes.split_at(bytes.len() - 4);
+    let stored = u32::from_le_bytes([stored[0], stored[1], stored[2], stored[3]]);
+    let computed = crc32(body);
+    if stored != computed {
+        return Err(DecodeError::ChecksumMismatch { expected: stored, found: computed });
+    }
+
+    let mut reader = Reader { bytes: body, offset: 6 };
+    let outputs = reader.varint()?;
+    let nodes = reader.nodes()?;
+    let constants = reader.values::<T>()?;
+    let literals = reader.values::<T>()?;
+    let mut functions = Vec::new();
+    if reader.offset != body.len() {
+        let count = reader.varint()?;
+        if count == 0 {
+            return Err(DecodeError::TrailingBytes { offset: reader.offset - 1 });
+        }
+        for _ in 0..count {
+            let len = reader.varint()?;
+            let offset = reader.offset;
+            let name = core::str::from_utf8(reader.take(len)?)
+                .map_err(|_| DecodeError::BadName { offset })?
+                .to_string();
+            let start = reader.offset;
+            let arity = reader.varint()?;
+            if arity > u8::MAX as usize {
+                return Err(DecodeError::BadVarint { offset: start });
+            }
+            let nodes = reader.nodes()?;
+            functions.push(Function { name, arity, nodes });
+        }
+    }
+    if reader.offset != body.len() {
+        return Err(DecodeError::TrailingBytes { offset: reader.offset });
+    }
+
+    check_names(&functions)?;
+    let mut program = Program::build(nodes, literals, outputs != 1, functions)?;
+    if program.output_count() != outputs {
+        return Err(DecodeError::OutputCount { expected: outputs, found: program.output_count() });
+    }
+    if !constants.is_empty() {
+        program.set_constants(&constants)?;
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_op::{BinaryOpF64, BinaryOpI32};
+    use crate::program::{ErrorKind, ProgramF64, ProgramI32};
+    use crate::ternary_op::{TernaryOpF64, TernaryOpI32};
+
+    fn sample() -> ProgramF64 {
+        let mut program = ProgramF64::with_literals(vec![
+            Node::Lettuce,
+            Node::BinaryOp(BinaryOpF64::Mul),
+            Node::Input(0),
+            Node::Input(300),
+            Node::TernaryOp(TernaryOpF64::MulAdd),
+            Node::Local(0),
+            Node::Constant(0),
+            Node::Literal(0),
+        ], vec![0.5]).unwrap();
+        program.set_constants(&[-0.16666666666666632]).unwrap();
+        program
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = sample();
+        let bytes = encode(&program);
+        // header 6, outputs 1, nodes 1 + 8 + 6, constants 9, literals 9,
+        // checksum 4
+        assert_eq!(bytes.len(), 44);
+
+        let mut decoded: ProgramF64 = decode(&bytes).unwrap();
+        assert_eq!(decoded.nodes, program.nodes);
+        assert_eq!(decoded.constants(), program.constants());
+        assert_eq!(decoded.literals(), program.literals());
+        let inputs = vec![1.5; 301];
+        assert_eq!(decoded.eval(&inputs), sample().eval(&inputs));
+
+        let multi = ProgramF64::multi(vec![Node::Input(1), Node::Input(0)], Vec::new()).unwrap();
+        let mut decoded: ProgramF64 = decode(&encode(&multi)).unwrap();
+        assert_eq!(decoded.eval_multi(&[1.0, 2.0]).unwrap(), &[2.0, 1.0]);
+
+        let mut body = [b'B', b'V', b'R', b'P', VERSION, 2, 3].to_vec();
+        body.extend_from_slice(&[2, OP_INPUT, 1, OP_INPUT, 0, 0, 0]);
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body)).err(),
+            Some(DecodeError::OutputCount { expected: 3, found: 2 }));
+    }
+
+    #[test]
+    fn functions() {
+        let square = Function { name: "double_square".to_string(), arity: 1, nodes: vec![
+            Node::Lettuce, Node::Input(0),
+            Node::BinaryOp(BinaryOpF64::Mul), Node::Local(0), Node::BinaryOp(BinaryOpF64::Mul),
+            Node::Local(0), Node::Literal(0),
+        ] };
+        let program = ProgramF64::with_functions(vec![Node::Call(0, 1), Node::Input(0)], vec![2.0], vec![square])
+            .unwrap();
+        let bytes = encode(&program);
+        let mut decoded: ProgramF64 = decode(&bytes).unwrap();
+        assert_eq!(decoded.nodes, program.nodes);
+        assert_eq!(decoded.functions(), program.functions());
+        assert_eq!(decoded.eval(&[3.0]).unwrap(), 18.0);
+
+        let mut body = [b'B', b'V', b'R', b'P', VERSION, 2, 1].to_vec();
+        body.extend_from_slice(&[2, OP_CALL, 0, 1, OP_INPUT, 0, 0, 0, 1, 1, 0xff, 1, 1, OP_INPUT, 0]);
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body.clone())).err(),
+            Some(DecodeError::BadName { offset: 17 }));
+        body[17] = b'f';
+        body[18] = 2;
+        match decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body.clone())) {
+            Err(DecodeError::Program(error)) => assert_eq!(error.kind, ErrorKind::WrongArity),
+            other => panic!("unexpected {:?}", other.map(|p| p.nodes)),
+        }
+        // no call can pass more arguments than fit in a byte
+        body.splice(18..19, [0x80, 0x02]);
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body)).err(),
+            Some(DecodeError::BadVarint { offset: 18 }));
+    }
+
+    #[test]
+    fn integer_ops() {
+        let program = ProgramI32::new(vec![
+            Node::TernaryOp(TernaryOpI32::IfGe),
+            Node::Input(0),
+            Node::BinaryOp(BinaryOpI32::Ge),
+            Node::Input(0),
+            Node::Input(1),
+            Node::BinaryOp(BinaryOpI32::CheckedRem),
+            Node::Input(0),
+            Node::Input(1),
+        ]).unwrap();
+        let decoded: ProgramI32 = decode(&encode(&program)).unwrap();
+        assert_eq!(decoded.nodes, program.nodes);
+    }
+
+    #[test]
+    fn rejects_corrupt() {
+        let bytes = encode(&sample());
+
+        let mut flipped = bytes.clone();
+        flipped[9] ^= 0x04;
+        assert!(matches!(decode::<f64, BinaryOpF64, TernaryOpF64>(&flipped),
+            Err(DecodeError::ChecksumMismatch { .. })));
+
+        assert_eq!(decode::<i32, BinaryOpI32, TernaryOpI32>(&bytes).err(),
+            Some(DecodeError::TypeMismatch { expected: 3, found: 2 }));
+
+        let mut version = bytes.clone();
+        version[4] = 9;
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&version).err(),
+            Some(DecodeError::UnsupportedVersion(9)));
+
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&bytes[..5]).err(),
+            Some(DecodeError::Truncated));
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(b"JUNKJUNKJUNK").err(),
+            Some(DecodeError::BadMagic));
+    }
+
+    fn seal(mut body: Vec<u8>) -> Vec<u8> {
+        let checksum = crc32(&body);
+        body.extend_from_slice(&checksum.to_le_bytes());
+        body
+    }
+
+    #[test]
+    fn rejects_bad_contents() {
+        let header = [b'B', b'V', b'R', b'P', VERSION, 2, 1];
+
+        let mut body = header.to_vec();
+        body.extend_from_slice(&[1, 0x7f, 0, 0]);
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body)).err(),
+            Some(DecodeError::BadOpcode { offset: 8, opcode: 0x7f }));
+
+        let mut body = header.to_vec();
+        body.extend_from_slice(&[2, OP_BINARY, OP_INPUT, 0, 0, 0]);
+        match decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body)) {
+            Err(DecodeError::Program(error)) => assert_eq!(error.kind, ErrorKind::TooFewNodes),
+            other => panic!("unexpected {:?}", other.map(|p| p.nodes)),
+        }
+
+        let mut body = header.to_vec();
+        body.extend_from_slice(&[1, OP_INPUT, 0xff, 0xff, 0x7f, 0, 0]);
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body)).err(),
+            Some(DecodeError::BadVarint { offset: 9 }));
+
+        let mut body = header.to_vec();
+        body.extend_from_slice(&[1, OP_INPUT, 0, 0, 0, 0]);
+        assert_eq!(decode::<f64, BinaryOpF64, TernaryOpF64>(&seal(body)).err(),
+            Some(DecodeError::TrailingBytes { offset: 12 }));
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -1,93 +1,219 @@
-use crate::program::{count_vars, NodeF64, ProgramError};
+//! x86-64 assembly for `f64` programs, NASM syntax, AVX and FMA3.
+//! The generated function follows the win64 calling convention:
+//! `extern "win64" fn(a0: f64, .., constants: *const f64) -> f64`, with
+//! at most three inputs so that all arguments travel in registers.
+
+use crate::program::{ErrorKind, NodeF64, ProgramF64, ProgramError};
 use crate::binary_op::{BinaryOpF64};
 use crate::ternary_op::{TernaryOpF64};
 
 use core::fmt::{Write};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RegState {
+    Free,
+    Input,
+    Local,
+    Temp,
+}
+
 #[derive(Debug)]
 struct Compiler {
     program: String,
     position: usize,
+    registers: [RegState; REG_COUNT],
+    /// One past the highest register ever allocated.
+    used: usize,
+    inputs: Vec<usize>,
+    locals: Vec<usize>,
+    constants: &'static str,
 }
 
 const REG_COUNT: usize = 16;
+/// Integer argument registers by position; the constants pointer
+/// follows the inputs.
+const ARG_REGS: [&str; 4] = ["rcx", "rdx", "r8", "r9"];
+/// xmm6 and up must be preserved across calls.
+const FIRST_SAVED: usize = 6;
 
 impl Compiler {
     fn new() -> Self {
         Self {
             program: String::new(),
             position: 0,
+            registers: [RegState::Free; REG_COUNT],
+            used: 0,
+            inputs: Vec::new(),
+            locals: Vec::new(),
+            constants: ARG_REGS[0],
+        }
+    }
+
+    fn init(&mut self, program: &ProgramF64) -> Result<(), ProgramError> {
+        let input_count = program.info().input_count;
+        if input_count >= ARG_REGS.len() {
+            return Err(ProgramError::new(ErrorKind::TooFewRegisters).counts(ARG_REGS.len() - 1, input_count));
+        }
+        for _ in 0..input_count {
+            let register = self.alloc(RegState::Input)?;
+            self.inputs.push(register);
+        }
+        self.constants = ARG_REGS[input_count];
+        Ok(())
+    }
+
+    fn alloc(&mut self, state: RegState) -> Result<usize, ProgramError> {
+        let register = self.registers.iter().position(|&reg| reg == RegState::Free)
+            .ok_or_else(|| ProgramError::new(ErrorKind::TooFewRegisters).at(self.position))?;
+        self.registers[register] = state;
+        self.used = self.used.max(register + 1);
+        Ok(register)
+    }
+
+    fn free(&mut self, register: usize) -> Result<(), ProgramError> {
+        if self.registers[register] == RegState::Free {
+            return Err(ProgramError::new(ErrorKind::RegisterDoubleFree).index(register));
         }
+        self.registers[register] = RegState::Free;
+        Ok(())
     }
+
+    /// Compiles the subtree at `position` and returns the register that
+    /// holds its value: `target` itself, or the register of an input or
+    /// local, which is never written after it is bound.
     fn compile(&mut self, target: usize, program: &[NodeF64]) -> Result<usize, ProgramError> {
-        let node = program[self.position];
-        // println!("p={}, n={:?} t={}", self.position, node, target);
+        let at = self.position;
+        let node = program[at];
         self.position += 1;
         match node {
             NodeF64::Local(index) => {
                 self.locals.get(index as usize).copied()
-                    .ok_or(ProgramError::NonExistentLocal)
+                    .ok_or_else(|| ProgramError::new(ErrorKind::NonExistentLocal).at(at))
             }
             NodeF64::Input(index) => {
                 self.inputs.get(index as usize).copied()
-                    .ok_or(ProgramError::NonExistentInput)
+                    .ok_or_else(|| ProgramError::new(ErrorKind::NonExistentInput).at(at))
             }
             NodeF64::Constant(index) => {
                 self.emit_load(target, index as usize);
                 Ok(target)
             }
+            NodeF64::Literal(index) => {
+                self.emit_literal(target, index as usize);
+                Ok(target)
+            }
             NodeF64::Lettuce => {
                 let register = self.alloc(RegState::Local)?;
-                // writeln!(&mut self.program, "; local={}", register);
-                self.locals.push(register);
-                self.compile(register, program)?;
-                // writeln!(&mut self.program, "; in...");
+                let value = self.compile(register, program)?;
+                if value != register {
+                    // the local is just another name for an input or local
+                    self.free(register)?;
+                }
+                self.locals.push(value);
                 self.compile(target, program)
             }
             NodeF64::BinaryOp(op) => {
                 let lhs = self.compile(target, program)?;
                 let tmp = self.alloc(RegState::Temp)?;
                 let rhs = self.compile(tmp, program)?;
-                self.emit_binary(op, target, lhs, rhs);
+                self.emit_binary(op, target, lhs, rhs).map_err(|err| err.at(at))?;
                 self.free(tmp)?;
-                Ok(lhs)
+                Ok(target)
             }
             NodeF64::TernaryOp(op) => {
-                let mut a = self.compile(target, program)?;
-                let mut tmp_a = 0;
-                if a != target {
-                    tmp_a = self.alloc(RegState::Temp)?;
-                    self.emit_mov(tmp_a, a);
-                    a = tmp_a;
-                }
+                let a = self.compile(target, program)?;
                 let tmp_b = self.alloc(RegState::Temp)?;
                 let b = self.compile(tmp_b, program)?;
                 let tmp_c = self.alloc(RegState::Temp)?;
                 let c = self.compile(tmp_c, program)?;
-                self.emit_ternary(op, target, a, b, c);
-                if tmp_a != 0 {
-                    self.emit_mov(target, a);
-                }
-                self.free(tmp_a)?;
+                self.emit_ternary(op, target, a, b, c).map_err(|err| err.at(at))?;
                 self.free(tmp_b)?;
                 self.free(tmp_c)?;
-                Ok(a)
+                Ok(target)
             }
+            NodeF64::Call(..) => unreachable!("calls are inlined first"),
+        }
+    }
+
+    fn emit_load(&mut self, target: usize, index: usize) {
+        writeln!(self.program, "    vmovsd xmm{}, qword [{} + {}]", target, self.constants, index * 8).unwrap();
+    }
+
+    fn emit_literal(&mut self, target: usize, index: usize) {
+        writeln!(self.program, "    vmovsd xmm{}, qword [rel lit{}]", target, index).unwrap();
+    }
+
+    fn emit_mov(&mut self, target: usize, source: usize) {
+        if target != source {
+            writeln!(self.program, "    vmovapd xmm{}, xmm{}", target, source).unwrap();
+        }
+    }
+
+    /// Only ops whose instruction rounds exactly like `BinaryOp::run`.
+    fn emit_binary(&mut self, op: BinaryOpF64, target: usize, lhs: usize, rhs: usize) -> Result<(), ProgramError> {
+        let instr = match op {
+            BinaryOpF64::Add => "vaddsd",
+            BinaryOpF64::Sub => "vsubsd",
+            BinaryOpF64::Mul => "vmulsd",
+            BinaryOpF64::Div => "vdivsd",
+            _ => return Err(ProgramError::new(ErrorKind::UnsupportedOp)),
+        };
+        writeln!(self.program, "    {} xmm{}, xmm{}, xmm{}", instr, target, lhs, rhs).unwrap();
+        Ok(())
+    }
+
+    fn emit_ternary(&mut self, op: TernaryOpF64, target: usize, a: usize, b: usize, c: usize) -> Result<(), ProgramError> {
+        match op {
+            TernaryOpF64::MulAdd => {
+                self.emit_mov(target, a);
+                writeln!(self.program, "    vfmadd213sd xmm{}, xmm{}, xmm{}", target, b, c).unwrap();
+                Ok(())
+            }
+            _ => Err(ProgramError::new(ErrorKind::UnsupportedOp)),
+        }
+    }
+
+    fn emit_ret(&mut self, result: usize, literals: &[f64]) {
+        self.emit_mov(0, result);
+        let saved = FIRST_SAVED..self.used.max(FIRST_SAVED);
+        let frame = saved.len() * 16;
+        let mut prologue = String::new();
+        if frame > 0 {
+            writeln!(prologue, "    sub rsp, {}", frame).unwrap();
+            for (slot, register) in saved.clone().enumerate() {
+                writeln!(prologue, "    vmovdqu [rsp + {}], xmm{}", slot * 16, register).unwrap();
+                writeln!(self.program, "    vmovdqu xmm{}, [rsp + {}]", register, slot * 16).unwrap();
+            }
+            writeln!(self.program, "    add rsp, {}", frame).unwrap();
+        }
+        self.program.insert_str(0, &prologue);
+        self.program.push_str("    ret\n");
+        for (index, literal) in literals.iter().enumerate() {
+            writeln!(self.program, "lit{}: dq 0x{:016x} ; {:?}", index, literal.to_bits(), literal).unwrap();
         }
     }
 }
 
-pub fn compile(program: &[NodeF64]) -> Result<String, ProgramError> {
+/// Compiles the program's only output, inlining any calls first.
+pub fn compile(program: &ProgramF64) -> Result<String, ProgramError> {
+    program.single_output()?;
+    if !program.functions().is_empty() {
+        return compile(&program.inline_calls()?);
+    }
     let mut compiler = Compiler::new();
     compiler.init(program)?;
-    let dst = compiler.compile(0, program)?;
-    compiler.emit_ret();
+    let result = compiler.alloc(RegState::Temp)?;
+    let dst = compiler.compile(result, &program.nodes)
+        .map_err(|err| err.with_source(&program.nodes, program.literals()))?;
+    compiler.emit_ret(dst, program.literals());
     Ok(compiler.program)
 }
 
-pub fn to_fn() -> (fn(x: f64, consts: *const f64) -> f64) {
+/// Hand-assembled `k_sin` kernel, loaded into executable memory.
+#[cfg(windows)]
+pub fn to_fn() -> extern "win64" fn(x: f64, consts: *const f64) -> f64 {
     let code = &[
-        0x0F, 0x28, 0xD8, 0xF2, 0x0F, 0x59, 0xDB, 0x0F, 0x28, 0xE3, 0xF2, 0x0F, 0x59, 0xE4, 0xF2, 0x0F, 0x10, 0x52, 0x28, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xCA, 0xF2, 0x0F, 0x10, 0x52, 0x20, 0xF2, 0x0F, 0x58, 0xD1, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xCC, 0xF2, 0x0F, 0x10, 0x62, 0x18, 0x0F, 0x28, 0xEB, 0xF2, 0x0F, 0x59, 0xEC, 0xF2, 0x0F, 0x10, 0x62, 0x10, 0xF2, 0x0F, 0x58, 0xE5, 0xF2, 0x0F, 0x59, 0xCC, 0xF2, 0x0F, 0x58, 0xD1, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xCA, 0xF2, 0x0F, 0x10, 0x52, 0x08, 0xF2, 0x0F, 0x58, 0xD1, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xC8, 0xF2, 0x0F, 0x59, 0xDA, 0xF2, 0x0F, 0x10, 0x12, 0xF2, 0x0F, 0x58, 0xD3, 0xF2, 0x0F, 0x59, 0xCA, 0xF2, 0x0F, 0x58, 0xC1, 0xC3 
+        0x0F, 0x28, 0xD8, 0xF2, 0x0F, 0x59, 0xDB, 0x0F, 0x28, 0xE3, 0xF2, 0x0F, 0x59, 0xE4, 0xF2, 0x0F, 0x10, 0x52, 0x28, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xCA, 0xF2, 0x0F, 0x10, 0x52, 0x20, 0xF2, 0x0F, 0x58, 0xD1, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xCC, 0xF2, 0x0F, 0x10, 0x62, 0x18, 0x0F, 0x28, 0xEB, 0xF2, 0x0F, 0x59, 0xEC, 0xF2, 0x0F, 0x10, 0x62, 0x10, 0xF2, 0x0F, 0x58, 0xE5, 0xF2, 0x0F, 0x59, 0xCC, 0xF2, 0x0F, 0x58, 0xD1, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xCA, 0xF2, 0x0F, 0x10, 0x52, 0x08, 0xF2, 0x0F, 0x58, 0xD1, 0x0F, 0x28, 0xCB, 0xF2, 0x0F, 0x59, 0xC8, 0xF2, 0x0F, 0x59, 0xDA, 0xF2, 0x0F, 0x10, 0x12, 0xF2, 0x0F, 0x58, 0xD3, 0xF2, 0x0F, 0x59, 0xCA, 0xF2, 0x0F, 0x58, 0xC1, 0xC3
     ];
     unsafe {
         use winapi::um::memoryapi::{VirtualAlloc};
@@ -103,3 +229,37 @@ pub fn to_fn() -> (fn(x: f64, consts: *const f64) -> f64) {
         core::mem::transmute::<_, _>(raw_addr)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Node::*;
+
+    #[test]
+    fn listing() {
+        let program = ProgramF64::with_literals(vec![
+            Lettuce, BinaryOp(BinaryOpF64::Mul), Input(0), Input(0),
+            TernaryOp(TernaryOpF64::MulAdd), Local(0), Constant(1),
+            BinaryOp(BinaryOpF64::Sub), Local(0), Literal(0),
+        ], vec![0.5]).unwrap();
+        assert_eq!(compile(&program).unwrap(), "    vmulsd xmm2, xmm0, xmm0
+    vmovsd xmm3, qword [rdx + 8]
+    vmovsd xmm5, qword [rel lit0]
+    vsubsd xmm4, xmm2, xmm5
+    vmovapd xmm1, xmm2
+    vfmadd213sd xmm1, xmm3, xmm4
+    vmovapd xmm0, xmm1
+    ret
+lit0: dq 0x3fe0000000000000 ; 0.5
+");
+    }
+
+    #[test]
+    fn unsupported() {
+        let program = ProgramF64::new(vec![
+            BinaryOp(BinaryOpF64::Add), Input(0), BinaryOp(BinaryOpF64::Pow), Input(0), Input(1),
+        ]).unwrap();
+        let err = compile(&program).unwrap_err();
+        assert_eq!((err.kind, err.position), (ErrorKind::UnsupportedOp, Some(2)));
+    }
+}
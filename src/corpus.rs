@@ -0,0 +1,203 @@
+//! Programs of known shapes for tests and benchmarks.
+//!
+//! Every generated program is a valid, infallible `f64` program reading
+//! inputs below `INPUTS` and no constants, so it runs on every backend.
+
+use crate::binary_op::BinaryOpF64::{self, Add, Max, Min, Mul, Sub};
+use crate::ternary_op::TernaryOpF64::{self, MulAdd};
+use crate::program::{Index, Node::*, NodeF64, ProgramF64};
+use crate::rng::Rng;
+
+/// Number of inputs the generated programs read from.
+pub const INPUTS: usize = 4;
+
+const BINARY: &[BinaryOpF64] = &[Add, Sub, Mul, Min, Max];
+const TERNARY: &[TernaryOpF64] = &[MulAdd, TernaryOpF64::Select];
+const LITERALS: usize = 8;
+
+const SIN_CONSTANTS: [f64; 6] = [
+    -0.16666666666666632,
+    0.00833333333332249,
+    -0.0001984126982985795,
+    2.7557313707070068e-6,
+    -2.5050760253406863e-8,
+    1.58969099521155e-10,
+];
+
+const COS_CONSTANTS: [f64; 6] = [
+    0.0416666666666666,
+    -0.001388888888887411,
+    2.480158728947673e-5,
+    -2.7557314351390663e-7,
+    2.087572321298175e-9,
+    -1.1359647557788195e-11,
+];
+
+fn sin_nodes() -> Vec<NodeF64> {
+    vec![
+        Lettuce, BinaryOp(Mul), Input(0), Input(0),
+        Lettuce, BinaryOp(Mul), Local(0), Local(0),
+        Lettuce,
+        TernaryOp(MulAdd),
+        BinaryOp(Mul), Local(0), Local(1),
+        TernaryOp(MulAdd), Local(0), Constant(5), Constant(4),
+        TernaryOp(MulAdd), Local(0),
+        TernaryOp(MulAdd), Local(0), Constant(3), Constant(2),
+        Constant(1),
+        Lettuce, BinaryOp(Mul), Local(0), Input(0),
+        TernaryOp(MulAdd), Local(3),
+        TernaryOp(MulAdd), Local(0), Local(2), Constant(0),
+        Input(0),
+    ]
+}
+
+/// The `k_sin` kernel from the `sin_bench` example, with its coefficients set.
+pub fn sin() -> ProgramF64 {
+    let mut sin = ProgramF64::new(sin_nodes()).unwrap();
+    sin.set_constants(&SIN_CONSTANTS).unwrap();
+    sin
+}
+
+/// `k_sin` and a matching cosine kernel as two outputs. The cosine reads
+/// `x^2` and `x^4` from the locals of the sine, so they are computed once.
+pub fn sin_cos() -> ProgramF64 {
+    let mut nodes = sin_nodes();
+    nodes.extend([
+        TernaryOp(MulAdd), Local(1),
+        TernaryOp(MulAdd), Local(0),
+        TernaryOp(MulAdd), Local(0),
+        TernaryOp(MulAdd), Local(0),
+        TernaryOp(MulAdd), Local(0),
+        TernaryOp(MulAdd), Local(0), Constant(11), Constant(10),
+        Constant(9), Constant(8), Constant(7), Constant(6),
+        TernaryOp(MulAdd), Local(0), Literal(0), Literal(1),
+    ]);
+    let mut sin_cos = ProgramF64::multi(nodes, vec![-0.5, 1.0]).unwrap();
+    sin_cos.set_constants(&[SIN_CONSTANTS, COS_CONSTANTS].concat()).unwrap();
+    sin_cos
+}
+
+fn literals(rng: &mut Rng) -> Vec<f64> {
+    (0..LITERALS).map(|_| 2.0 * rng.next_f64() - 1.0).collect()
+}
+
+fn leaf(rng: &mut Rng) -> NodeF64 {
+    if rng.below(4) == 0 {
+        Literal(rng.below(LITERALS) as Index)
+    } else {
+        Input(rng.below(INPUTS) as Index)
+    }
+}
+
+fn full_tree(rng: &mut Rng, depth: usize, nodes: &mut Vec<NodeF64>) {
+    if depth == 0 {
+        nodes.push(leaf(rng));
+        return;
+    }
+    let arity = if rng.below(4) == 0 {
+        nodes.push(TernaryOp(TERNARY[rng.below(TERNARY.len())]));
+        3
+    } else {
+        nodes.push(BinaryOp(BINARY[rng.below(BINARY.len())]));
+        2
+    };
+    for _ in 0..arity {
+        full_tree(rng, depth - 1, nodes);
+    }
+}
+
+/// A random tree with ops on every level down to `depth`, so with at
+/// least `2^depth` leaves.
+pub fn deep(depth: usize, seed: u64) -> ProgramF64 {
+    let mut rng = Rng::new(seed);
+    let literals = literals(&mut rng);
+    let mut nodes = Vec::new();
+    full_tree(&mut rng, depth, &mut nodes);
+    ProgramF64::with_literals(nodes, literals).unwrap()
+}
+
+fn balanced_sum(rng: &mut Rng, width: usize, nodes: &mut Vec<NodeF64>) {
+    if width == 1 {
+        nodes.extend([BinaryOp(Mul), Input(rng.below(INPUTS) as Index), Literal(rng.below(LITERALS) as Index)]);
+        return;
+    }
+    nodes.push(BinaryOp(Add));
+    balanced_sum(rng, width / 2, nodes);
+    balanced_sum(rng, width - width / 2, nodes);
+}
+
+/// A balanced sum of `width` products of an input and a literal, so about
+/// `log2(width)` levels deep.
+pub fn wide(width: usize, seed: u64) -> ProgramF64 {
+    let mut rng = Rng::new(seed);
+    let literals = literals(&mut rng);
+    let mut nodes = Vec::new();
+    balanced_sum(&mut rng, width.max(1), &mut nodes);
+    ProgramF64::with_literals(nodes, literals).unwrap()
+}
+
+/// `count` nested lets, each binding a random op over the inputs and the
+/// two previous locals, with the last local as the body.
+pub fn lettuce(count: usize, seed: u64) -> ProgramF64 {
+    let mut rng = Rng::new(seed);
+    let literals = literals(&mut rng);
+    let mut nodes = Vec::new();
+    let operand = |rng: &mut Rng, bound: usize| match rng.below(3) {
+        0 if bound > 0 => Local((bound - 1 - rng.below(bound.min(2))) as Index),
+        1 => Literal(rng.below(LITERALS) as Index),
+        _ => Input(rng.below(INPUTS) as Index),
+    };
+    for bound in 0..count {
+        nodes.push(Lettuce);
+        if rng.below(2) == 0 {
+            nodes.push(TernaryOp(MulAdd));
+            for _ in 0..3 {
+                nodes.push(operand(&mut rng, bound));
+            }
+        } else {
+            nodes.push(BinaryOp(BINARY[rng.below(BINARY.len())]));
+            for _ in 0..2 {
+                nodes.push(operand(&mut rng, bound));
+            }
+        }
+    }
+    nodes.push(if count > 0 { Local((count - 1) as Index) } else { Input(0) });
+    ProgramF64::with_literals(nodes, literals).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shapes() {
+        let programs = vec![deep(6, 1), wide(100, 2), lettuce(200, 3)];
+        assert!(programs[0].nodes.len() >= 127);
+        assert_eq!(programs[1].nodes.len(), 399);
+        assert_eq!(programs[1].info().max_depth, 9);
+        assert_eq!(programs[2].info().local_count, 200);
+        for program in programs {
+            let info = program.info();
+            assert!(info.input_count <= INPUTS);
+            assert_eq!(info.constant_count, 0);
+            let mut lanes = program.lanes().unwrap();
+            lanes.eval_columns(&[&[0.5][..]; INPUTS]).unwrap();
+        }
+        assert_eq!(format!("{:?}", deep(8, 5)), format!("{:?}", deep(8, 5)));
+    }
+
+    #[test]
+    fn sin_cos_shares_locals() {
+        let mut sin = sin();
+        let mut sin_cos = sin_cos();
+        assert_eq!(sin_cos.output_count(), 2);
+        assert_eq!(sin_cos.info().local_count, 4);
+        let mut x = -0.78;
+        while x < 0.78 {
+            let outputs = sin_cos.eval_multi(&[x]).unwrap();
+            assert_eq!(outputs[0].to_bits(), sin.eval(&[x]).unwrap().to_bits());
+            assert!((outputs[1] - x.cos()).abs() < 1e-15, "{}: {} != {}", x, outputs[1], x.cos());
+            x += 1.0 / 256.0;
+        }
+    }
+}
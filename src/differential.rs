@@ -0,0 +1,187 @@
+//! Differential tests: random valid programs must give the same result on
+//! `VerifiedProgram` and `LaneProgram` (the `std::simd` lanes with the
+//! `simd` feature) as on `Program::eval`; the `compile` listing is not
+//! executable here and is left out. Floats compare bitwise except
+//! that any two NaNs are equal, since SIMD units need not keep payloads.
+//!
+//! Programs are generated as trees whose leaves and ops are indices wrapped
+//! into range when flattened, so every tree proptest shrinks to is still
+//! a valid program and failures are reported as minimal node vectors.
+
+use core::fmt::Debug;
+use proptest::prelude::*;
+
+use crate::binary_op::{BinaryOpF32, BinaryOpF64, BinaryOpI32, BinaryOpI64, BinaryOpU32, BinaryOpU64, OpList};
+use crate::ternary_op::{TernaryOpF32, TernaryOpF64, TernaryOpI32, TernaryOpI64, TernaryOpU32, TernaryOpU64};
+use crate::lanes::{BinaryLaneOp, Element, TernaryLaneOp, LANES};
+use crate::program::{Index, Node, Program};
+
+const INPUTS: usize = 3;
+const CONSTANTS: usize = 3;
+const LITERALS: usize = 3;
+
+#[derive(Debug, Clone)]
+enum Tree {
+    Input(Index),
+    Constant(Index),
+    Literal(Index),
+    Local(Index),
+    Let(Box<Tree>, Box<Tree>),
+    Binary(usize, Box<Tree>, Box<Tree>),
+    Ternary(usize, Box<Tree>, Box<Tree>, Box<Tree>),
+}
+
+fn tree() -> impl Strategy<Value = Tree> {
+    let leaf = prop_oneof![
+        any::<Index>().prop_map(Tree::Input),
+        any::<Index>().prop_map(Tree::Constant),
+        any::<Index>().prop_map(Tree::Literal),
+        any::<Index>().prop_map(Tree::Local),
+    ];
+    leaf.prop_recursive(8, 96, 3, |inner| prop_oneof![
+        (inner.clone(), inner.clone()).prop_map(|(value, body)| Tree::Let(Box::new(value), Box::new(body))),
+        (any::<usize>(), inner.clone(), inner.clone())
+            .prop_map(|(op, a, b)| Tree::Binary(op, Box::new(a), Box::new(b))),
+        (any::<usize>(), inner.clone(), inner.clone(), inner)
+            .prop_map(|(op, a, b, c)| Tree::Ternary(op, Box::new(a), Box::new(b), Box::new(c))),
+    ])
+}
+
+/// Appends the nodes of `tree`; `bound` counts the lets evaluated so far,
+/// which are the locals that may be read.
+fn flatten<BOP: Copy, TOP: Copy>(
+    tree: &Tree, binary: &[BOP], ternary: &[TOP], bound: &mut usize, nodes: &mut Vec<Node<BOP, TOP>>,
+) {
+    match tree {
+        Tree::Input(index) => nodes.push(Node::Input(index % INPUTS as Index)),
+        Tree::Constant(index) => nodes.push(Node::Constant(index % CONSTANTS as Index)),
+        Tree::Literal(index) => nodes.push(Node::Literal(index % LITERALS as Index)),
+        Tree::Local(index) if *bound > 0 => nodes.push(Node::Local(index % *bound as Index)),
+        Tree::Local(index) => nodes.push(Node::Input(index % INPUTS as Index)),
+        Tree::Let(value, body) => {
+            nodes.push(Node::Lettuce);
+            flatten(value, binary, ternary, bound, nodes);
+            *bound += 1;
+            flatten(body, binary, ternary, bound, nodes);
+        }
+        Tree::Binary(op, a, b) => {
+            nodes.push(Node::BinaryOp(binary[op % binary.len()]));
+            flatten(a, binary, ternary, bound, nodes);
+            flatten(b, binary, ternary, bound, nodes);
+        }
+        Tree::Ternary(op, a, b, c) => {
+            nodes.push(Node::TernaryOp(ternary[op % ternary.len()]));
+            flatten(a, binary, ternary, bound, nodes);
+            flatten(b, binary, ternary, bound, nodes);
+            flatten(c, binary, ternary, bound, nodes);
+        }
+    }
+}
+
+trait Same: Copy + Debug {
+    fn same(self, other: Self) -> bool;
+}
+
+macro_rules! same_float {
+    ($($t:ty),*) => {$(
+        impl Same for $t {
+            fn same(self, other: Self) -> bool {
+                self.to_bits() == other.to_bits() || (self.is_nan() && other.is_nan())
+            }
+        }
+    )*};
+}
+
+macro_rules! same_integer {
+    ($($t:ty),*) => {$(
+        impl Same for $t {
+            fn same(self, other: Self) -> bool {
+                self == other
+            }
+        }
+    )*};
+}
+
+same_float!(f32, f64);
+same_integer!(i32, u32, i64, u64);
+
+#[derive(Debug, Clone)]
+struct Values<T> {
+    literals: [T; LITERALS],
+    constants: [T; CONSTANTS],
+    /// Input rows, more than one chunk of lanes so the padding is used too.
+    rows: Vec<[T; INPUTS]>,
+}
+
+fn values<T: Arbitrary + Copy>() -> impl Strategy<Value = Values<T>> {
+    (any::<[T; LITERALS]>(), any::<[T; CONSTANTS]>(), prop::collection::vec(any::<[T; INPUTS]>(), 1..2 * LANES + 2))
+        .prop_map(|(literals, constants, rows)| Values { literals, constants, rows })
+}
+
+/// Checks every backend against `Program::eval`. Fallible ops are left out
+/// since only `Program::eval` accepts them.
+fn check<T, BOP, TOP>(tree: &Tree, values: &Values<T>) -> Result<(), TestCaseError>
+    where T: Element + Same,
+          BOP: Copy + Debug + OpList + BinaryLaneOp<T>,
+          TOP: Copy + Debug + OpList + TernaryLaneOp<T>,
+{
+    let binary: Vec<BOP> = BOP::ALL.iter().copied().filter(|op| !op.is_fallible()).collect();
+    let mut nodes = Vec::new();
+    flatten(tree, &binary, TOP::ALL, &mut 0, &mut nodes);
+    let program = || {
+        let mut program = Program::<T, BOP, TOP>::with_literals(nodes.clone(), values.literals.to_vec()).unwrap();
+        program.set_constants(&values.constants).unwrap();
+        program
+    };
+
+    let mut reference = program();
+    let expected: Vec<T> = values.rows.iter().map(|row| reference.eval(row).unwrap()).collect();
+
+    let mut verified = program().verify().unwrap();
+    for (row, &expected) in values.rows.iter().zip(&expected) {
+        let actual = verified.eval(row);
+        prop_assert!(actual.same(expected), "verified: {:?} != {:?} at {:?} for {:?}", actual, expected, row, nodes);
+    }
+
+    let columns: Vec<Vec<T>> = (0..INPUTS).map(|input| values.rows.iter().map(|row| row[input]).collect()).collect();
+    let columns: Vec<&[T]> = columns.iter().map(|column| &column[..]).collect();
+    let actual = program().lanes().unwrap().eval_columns(&columns).unwrap();
+    for ((row, &expected), &actual) in values.rows.iter().zip(&expected).zip(&actual) {
+        prop_assert!(actual.same(expected), "lanes: {:?} != {:?} at {:?} for {:?}", actual, expected, row, nodes);
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(512))]
+
+    #[test]
+    fn f32_backends_agree(tree in tree(), values in values::<f32>()) {
+        check::<f32, BinaryOpF32, TernaryOpF32>(&tree, &values)?;
+    }
+
+    #[test]
+    fn f64_backends_agree(tree in tree(), values in values::<f64>()) {
+        check::<f64, BinaryOpF64, TernaryOpF64>(&tree, &values)?;
+    }
+
+    #[test]
+    fn i32_backends_agree(tree in tree(), values in values::<i32>()) {
+        check::<i32, BinaryOpI32, TernaryOpI32>(&tree, &values)?;
+    }
+
+    #[test]
+    fn u32_backends_agree(tree in tree(), values in values::<u32>()) {
+        check::<u32, BinaryOpU32, TernaryOpU32>(&tree, &values)?;
+    }
+
+    #[test]
+    fn i64_backends_agree(tree in tree(), values in values::<i64>()) {
+        check::<i64, BinaryOpI64, TernaryOpI64>(&tree, &values)?;
+    }
+
+    #[test]
+    fn u64_backends_agree(tree in tree(), values in values::<u64>()) {
+        check::<u64, BinaryOpU64, TernaryOpU64>(&tree, &values)?;
+    }
+}
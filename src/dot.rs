@@ -0,0 +1,295 @@
+//! Graphviz export. Lets are drawn as shared nodes: the value of a
+//! `Lettuce` gets one edge from every node that reads the local, and the
+//! let itself disappears into its body.
+
+use core::fmt::{self, Write};
+use crate::binary_op::BinaryOp;
+use crate::ternary_op::TernaryOp;
+use crate::program::{ErrorKind, Node, Program, ProgramError, subtree_end};
+
+#[derive(Debug, Clone, Default)]
+pub struct DotOptions {
+    /// Extra label line for each node position, e.g. formatted from
+    /// `node_values` or `value_ranges`. Missing entries are skipped.
+    pub annotations: Vec<String>,
+    /// Position of a node whose subtree is highlighted.
+    pub highlight: Option<usize>,
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+struct Graph<'a, T, BOP, TOP>
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>,
+{
+    program: &'a Program<T, BOP, TOP>,
+    position: usize,
+    /// Position of the drawn node that holds each local.
+    locals: Vec<usize>,
+    /// `(position, label, local slots)` of every drawn node.
+    nodes: Vec<(usize, String, Vec<usize>)>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl<'a, T, BOP, TOP> Graph<'a, T, BOP, TOP>
+    where T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    /// Walks one subtree and returns the position of the drawn node
+    /// that stands for its value.
+    fn walk(&mut self) -> usize {
+        let position = self.position;
+        self.position += 1;
+        let label = match self.program.nodes[position] {
+            Node::Input(index) => format!("a{}", index),
+            Node::Constant(index) => format!("c{}", index),
+            Node::Literal(index) => format!("{:?}", self.program.literals()[index as usize]),
+            Node::Local(index) => return self.locals[index as usize],
+            Node::Lettuce => {
+                let value = self.walk();
+                let slot = self.locals.len();
+                self.locals.push(value);
+                if let Some(node) = self.nodes.iter_mut().find(|node| node.0 == value) {
+                    node.2.push(slot);
+                }
+                return self.walk();
+            }
+            Node::BinaryOp(op) => op.repr().to_string(),
+            Node::TernaryOp(op) => op.repr().to_string(),
+            Node::Call(index, _) => self.program.functions()[index as usize].name.clone(),
+        };
+        self.nodes.push((position, label, Vec::new()));
+        for _ in 0..self.program.nodes[position].arity() {
+            let child = self.walk();
+            self.edges.push((position, child));
+        }
+        position
+    }
+}
+
+/// Renders the program as a Graphviz `digraph`, roots at the top and
+/// operands in order from left to right. The outputs of a multi-output
+/// program share the nodes of their common locals.
+pub fn to_dot<T, BOP, TOP>(program: &Program<T, BOP, TOP>, options: &DotOptions) -> String
+    where T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    let mut graph = Graph { program, position: 0, locals: Vec::new(), nodes: Vec::new(), edges: Vec::new() };
+    for _ in 0..program.output_count() {
+        graph.walk();
+    }
+
+    let highlighted = |position: usize| match options.highlight {
+        Some(root) => position >= root && position < subtree_end(&program.nodes, root),
+        None => false,
+    };
+
+    let mut out = String::from("digraph program {\n    ordering=out;\n    node [fontname=\"monospace\"];\n");
+    graph.nodes.sort_by_key(|node| node.0);
+    for (position, label, slots) in &graph.nodes {
+        let mut label = label.clone();
+        if !slots.is_empty() {
+            let names: Vec<String> = slots.iter().map(|slot| format!("l{}", slot)).collect();
+            label = format!("{} = {}", names.join(" = "), label);
+        }
+        if let Some(annotation) = options.annotations.get(*position) {
+            label = format!("{}\n{}", label, annotation);
+        }
+        let mut attrs = format!("label=\"{}\"", escape(&label).replace('\n', "\\n"));
+        if program.nodes[*position].arity() == 0 {
+            attrs.push_str(", shape=box");
+        }
+        if !slots.is_empty() {
+            attrs.push_str(", peripheries=2");
+        }
+        if highlighted(*position) {
+            attrs.push_str(", style=filled, fillcolor=\"#ffd966\"");
+        }
+        writeln!(out, "    n{} [{}];", position, attrs).unwrap();
+    }
+    for &(from, to) in &graph.edges {
+        if highlighted(from) {
+            writeln!(out, "    n{} -> n{} [color=\"#cc0000\", penwidth=2];", from, to).unwrap();
+        } else {
+            writeln!(out, "    n{} -> n{};", from, to).unwrap();
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn record<T, BOP, TOP>(program: &Program<T, BOP, TOP>, inputs: &[T], position: &mut usize,
+    locals: &mut Vec<T>, values: &mut Vec<Option<T>>) -> Result<T, ProgramError>
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>,
+{
+    let at = *position;
+    *position += 1;
+    let value = match program.nodes[at] {
+        Node::Input(index) => inputs[index as usize],
+        Node::Constant(index) => program.constants[index as usize],
+        Node::Literal(index) => program.literals[index as usize],
+        Node::Local(index) => locals[index as usize],
+        Node::Lettuce => {
+            let value = record(program, inputs, position, locals, values)?;
+            locals.push(value);
+            record(program, inputs, position, locals, values)?
+        }
+        Node::BinaryOp(op) => {
+            let lhs = record(program, inputs, position, locals, values)?;
+            let rhs = record(program, inputs, position, locals, values)?;
+            op.try_run(lhs, rhs)
+                .ok_or_else(|| ProgramError::new(ErrorKind::ArithmeticError).at(at))?
+        }
+        Node::TernaryOp(op) => {
+            let a = record(program, inputs, position, locals, values)?;
+            let b = record(program, inputs, position, locals, values)?;
+            let c = record(program, inputs, position, locals, values)?;
+            op.run(a, b, c)
+        }
+        Node::Call(index, arity) => {
+            let args = (0..arity)
+                .map(|_| record(program, inputs, position, locals, values))
+                .collect::<Result<Vec<T>, ProgramError>>()?;
+            program.call_function(index as usize, &args).map_err(|error| error.at(at))?
+        }
+    };
+    values[at] = Some(value);
+    Ok(value)
+}
+
+/// The value every node produces when the program runs on `inputs`,
+/// indexed by node position. A `Lettuce` produces the value of its body.
+pub fn node_values<T, BOP, TOP>(program: &Program<T, BOP, TOP>, inputs: &[T]) -> Result<Vec<T>, ProgramError>
+    where T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    let info = program.info();
+    if inputs.len() < info.input_count {
+        return Err(ProgramError::new(ErrorKind::TooFewInputs).counts(info.input_count, inputs.len()));
+    }
+    if program.constants().len() < info.constant_count {
+        return Err(ProgramError::new(ErrorKind::TooFewConstants)
+            .counts(info.constant_count, program.constants().len()));
+    }
+    let mut values = vec![None; program.nodes.len()];
+    let (mut position, mut locals) = (0, Vec::new());
+    for _ in 0..program.output_count() {
+        record(program, inputs, &mut position, &mut locals, &mut values)?;
+    }
+    Ok(values.into_iter().map(|value| value.expect("every node is evaluated")).collect())
+}
+
+/// Smallest and largest value of every node over `samples`, ignoring
+/// NaNs; `None` where a node never produced a comparable value.
+pub fn value_ranges<T, BOP, TOP>(program: &Program<T, BOP, TOP>, samples: &[Vec<T>])
+    -> Result<Vec<Option<(T, T)>>, ProgramError>
+    where T: Copy + fmt::Debug + PartialOrd,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    let mut ranges: Vec<Option<(T, T)>> = vec![None; program.nodes.len()];
+    for inputs in samples {
+        for (range, value) in ranges.iter_mut().zip(node_values(program, inputs)?) {
+            if value.partial_cmp(&value).is_none() {
+                continue;
+            }
+            *range = match *range {
+                Some((lo, hi)) => Some((
+                    if value < lo { value } else { lo },
+                    if value > hi { value } else { hi },
+                )),
+                None => Some((value, value)),
+            };
+        }
+    }
+    Ok(ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_op::BinaryOpF64;
+    use crate::ternary_op::TernaryOpF64;
+    use crate::program::ProgramF64;
+
+    fn program() -> ProgramF64 {
+        use Node::*;
+        use BinaryOpF64::*;
+        let mut program = ProgramF64::with_literals(vec![
+            Lettuce, BinaryOp(Mul), Input(0), Input(0),
+            TernaryOp(TernaryOpF64::MulAdd), Local(0), Constant(0),
+            BinaryOp(Sub), Local(0), Literal(0),
+        ], vec![0.5]).unwrap();
+        program.set_constants(&[3.0]).unwrap();
+        program
+    }
+
+    #[test]
+    fn shared_lets() {
+        assert_eq!(to_dot(&program(), &DotOptions::default()), "\
+digraph program {
+    ordering=out;
+    node [fontname=\"monospace\"];
+    n1 [label=\"l0 = *\", peripheries=2];
+    n2 [label=\"a0\", shape=box];
+    n3 [label=\"a0\", shape=box];
+    n4 [label=\"mul_add\"];
+    n6 [label=\"c0\", shape=box];
+    n7 [label=\"-\"];
+    n9 [label=\"0.5\", shape=box];
+    n1 -> n2;
+    n1 -> n3;
+    n4 -> n1;
+    n4 -> n6;
+    n7 -> n1;
+    n7 -> n9;
+    n4 -> n7;
+}
+");
+    }
+
+    #[test]
+    fn annotations_and_highlight() {
+        let program = program();
+        let values = node_values(&program, &[2.0]).unwrap();
+        assert_eq!(values, vec![15.5, 4.0, 2.0, 2.0, 15.5, 4.0, 3.0, 3.5, 4.0, 0.5]);
+
+        let ranges = value_ranges(&program, &[vec![2.0], vec![-1.0], vec![f64::NAN]]).unwrap();
+        assert_eq!(ranges[1], Some((1.0, 4.0)));
+        assert_eq!(ranges[7], Some((0.5, 3.5)));
+
+        let options = DotOptions {
+            annotations: ranges.iter()
+                .map(|range| range.map_or(String::new(), |(lo, hi)| format!("[{}, {}]", lo, hi)))
+                .collect(),
+            highlight: Some(7),
+        };
+        let dot = to_dot(&program, &options);
+        assert!(dot.contains("n7 [label=\"-\\n[0.5, 3.5]\", style=filled, fillcolor=\"#ffd966\"];"), "{}", dot);
+        assert!(dot.contains("n9 [label=\"0.5\\n[0.5, 0.5]\", shape=box, style=filled"), "{}", dot);
+        assert!(dot.contains("n1 [label=\"l0 = *\\n[1, 4]\", peripheries=2];"), "{}", dot);
+        // the local read inside the subtree points out of it
+        assert!(dot.contains("n7 -> n1 [color=\"#cc0000\", penwidth=2];"), "{}", dot);
+        assert!(dot.contains("    n4 -> n1;\n"), "{}", dot);
+    }
+
+    #[test]
+    fn multiple_outputs() {
+        use Node::*;
+        let program = ProgramF64::multi(vec![
+            Lettuce, BinaryOp(BinaryOpF64::Mul), Input(0), Input(0), Local(0),
+            BinaryOp(BinaryOpF64::Add), Local(0), Input(0),
+        ], Vec::new()).unwrap();
+        assert_eq!(node_values(&program, &[3.0]).unwrap(), vec![9.0, 9.0, 3.0, 3.0, 9.0, 12.0, 9.0, 3.0]);
+        let dot = to_dot(&program, &DotOptions::default());
+        assert!(dot.contains("    n5 -> n1;\n"), "{}", dot);
+    }
+}
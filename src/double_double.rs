@@ -0,0 +1,352 @@
+use core::fmt;
+use core::ops::{Add, Div, Mul, Neg, Sub};
+use crate::binary_op::{BinaryOp, BinaryOpF64};
+use crate::ternary_op::{TernaryOp, TernaryOpF64};
+use crate::program::{Bits, Program};
+
+/// Unevaluated sum `hi + lo` of two `f64`s with `|lo| <= ulp(hi) / 2`,
+/// giving roughly 106 bits of significand. Used as a higher precision
+/// reference for `f64` programs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd)]
+pub struct DoubleDouble {
+    pub hi: f64,
+    pub lo: f64,
+}
+
+pub type ProgramDD = Program<DoubleDouble, BinaryOpF64, TernaryOpF64>;
+
+const LN2: DoubleDouble = DoubleDouble { hi: core::f64::consts::LN_2, lo: 2.3190468138462996e-17 };
+
+fn two_sum(a: f64, b: f64) -> DoubleDouble {
+    let hi = a + b;
+    let v = hi - a;
+    let lo = (a - (hi - v)) + (b - v);
+    DoubleDouble { hi, lo }
+}
+
+fn quick_two_sum(a: f64, b: f64) -> DoubleDouble {
+    let hi = a + b;
+    let lo = b - (hi - a);
+    DoubleDouble { hi, lo }
+}
+
+fn two_prod(a: f64, b: f64) -> DoubleDouble {
+    let hi = a * b;
+    let lo = a.mul_add(b, -hi);
+    DoubleDouble { hi, lo }
+}
+
+impl DoubleDouble {
+    pub const ZERO: Self = Self { hi: 0.0, lo: 0.0 };
+    pub const ONE: Self = Self { hi: 1.0, lo: 0.0 };
+    pub const NAN: Self = Self { hi: f64::NAN, lo: f64::NAN };
+
+    pub fn new(hi: f64, lo: f64) -> Self {
+        quick_two_sum(hi, lo)
+    }
+
+    /// Rounds to the nearest `f64`.
+    pub fn to_f64(self) -> f64 {
+        self.hi + self.lo
+    }
+
+    pub fn is_nan(self) -> bool {
+        self.hi.is_nan()
+    }
+
+    /// Non-finite results propagate through `hi` alone, `lo` would
+    /// otherwise turn into NaN.
+    fn finite_or(self, hi: f64) -> Self {
+        if hi.is_finite() {
+            self
+        } else {
+            Self { hi, lo: 0.0 }
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        if self.hi < 0.0 { -self } else { self }
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        if self.is_nan() || other < self { other } else { self }
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        if self.is_nan() || other > self { other } else { self }
+    }
+
+    pub fn mul_add(self, b: Self, c: Self) -> Self {
+        self * b + c
+    }
+
+    /// Clamps like `TernaryOpF64::Clamp`, see `TernaryOp`.
+    pub fn clamp(self, min: Self, max: Self) -> Self {
+        let raised = if self < min { min } else { self };
+        if raised > max { max } else { raised }
+    }
+
+    pub fn sqrt(self) -> Self {
+        if self.hi <= 0.0 || !self.hi.is_finite() {
+            return Self::from(self.hi.sqrt());
+        }
+        // one Newton step from the f64 estimate doubles the precision
+        let s = self.hi.sqrt();
+        let residual = self - two_prod(s, s);
+        two_sum(s, residual.hi / (2.0 * s))
+    }
+
+    pub fn hypot(self, other: Self) -> Self {
+        let scale = self.hi.abs().max(other.hi.abs());
+        if scale == 0.0 || !scale.is_finite() {
+            return Self::from(self.hi.hypot(other.hi));
+        }
+        let a = self / scale;
+        let b = other / scale;
+        (a * a + b * b).sqrt() * scale
+    }
+
+    pub fn powi(self, mut n: i64) -> Self {
+        let invert = n < 0;
+        n = n.abs();
+        let mut base = self;
+        let mut result = Self::ONE;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base;
+            }
+            base = base * base;
+            n >>= 1;
+        }
+        if invert { Self::ONE / result } else { result }
+    }
+
+    pub fn exp(self) -> Self {
+        if self.hi > 709.8 {
+            return Self::from(f64::INFINITY);
+        }
+        if self.hi < -745.2 {
+            return Self::ZERO;
+        }
+        if !self.hi.is_finite() {
+            return Self::from(self.hi.exp());
+        }
+        // exp(x) = 2^k * exp(r)^512, |r| <= ln(2) / 1024
+        let k = (self.hi / LN2.hi).round();
+        let r = (self - LN2 * k) * (1.0 / 512.0);
+        let mut sum = Self::ZERO;
+        let mut term = Self::ONE;
+        for ii in 1..=14 {
+            sum = sum + term;
+            term = term * r / (ii as f64);
+        }
+        for _ in 0..9 {
+            sum = sum * sum;
+        }
+        // split the scale so 2^k itself cannot overflow near the limits
+        let k = k as i32;
+        for &scale in &[2.0_f64.powi(k / 2), 2.0_f64.powi(k - k / 2)] {
+            sum = Self { hi: sum.hi * scale, lo: sum.lo * scale };
+        }
+        sum
+    }
+
+    pub fn ln(self) -> Self {
+        if self.hi <= 0.0 || !self.hi.is_finite() {
+            return Self::from(self.hi.ln());
+        }
+        // Newton iteration on exp(y) = x
+        let mut y = Self::from(self.hi.ln());
+        for _ in 0..2 {
+            y = y + self * (-y).exp() - Self::ONE;
+        }
+        y
+    }
+
+    /// Integer exponents are computed by repeated squaring and stay
+    /// accurate for negative bases, the rest goes through `exp(b * ln(a))`.
+    pub fn powf(self, exponent: Self) -> Self {
+        let integral = exponent.hi.fract() == 0.0 && exponent.lo == 0.0;
+        if integral && exponent.hi.abs() < (1u64 << 53) as f64 {
+            return self.powi(exponent.hi as i64);
+        }
+        if self.hi < 0.0 {
+            return Self::NAN;
+        }
+        if self.hi == 0.0 || !self.hi.is_finite() || !exponent.hi.is_finite() {
+            return Self::from(self.hi.powf(exponent.hi));
+        }
+        (exponent * self.ln()).exp()
+    }
+}
+
+impl Bits for DoubleDouble {
+    fn bits(self) -> u128 {
+        ((self.hi.to_bits() as u128) << 64) | self.lo.to_bits() as u128
+    }
+}
+
+impl From<f64> for DoubleDouble {
+    fn from(value: f64) -> Self {
+        Self { hi: value, lo: 0.0 }
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self { hi: -self.hi, lo: -self.lo }
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        let s = two_sum(self.hi, rhs.hi);
+        let t = two_sum(self.lo, rhs.lo);
+        let s = quick_two_sum(s.hi, s.lo + t.hi);
+        quick_two_sum(s.hi, s.lo + t.lo).finite_or(self.hi + rhs.hi)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        let p = two_prod(self.hi, rhs.hi);
+        let lo = p.lo + (self.hi * rhs.lo + self.lo * rhs.hi);
+        quick_two_sum(p.hi, lo).finite_or(p.hi)
+    }
+}
+
+impl Mul<f64> for DoubleDouble {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self {
+        self * Self::from(rhs)
+    }
+}
+
+impl Div for DoubleDouble {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        let q1 = self.hi / rhs.hi;
+        if !q1.is_finite() || q1 == 0.0 {
+            return Self::from(q1);
+        }
+        let r = self - rhs * q1;
+        let q2 = r.hi / rhs.hi;
+        let r = r - rhs * q2;
+        let q3 = r.hi / rhs.hi;
+        quick_two_sum(q1, q2) + Self::from(q3)
+    }
+}
+
+impl Div<f64> for DoubleDouble {
+    type Output = Self;
+    fn div(self, rhs: f64) -> Self {
+        self / Self::from(rhs)
+    }
+}
+
+impl fmt::Display for DoubleDouble {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:e} + {:e}", self.hi, self.lo)
+    }
+}
+
+impl BinaryOp<DoubleDouble> for BinaryOpF64 {
+    fn run(&self, lhs: DoubleDouble, rhs: DoubleDouble) -> DoubleDouble {
+        match self {
+            BinaryOpF64::Add => lhs + rhs,
+            BinaryOpF64::Sub => lhs - rhs,
+            BinaryOpF64::Mul => lhs * rhs,
+            BinaryOpF64::Div => lhs / rhs,
+            BinaryOpF64::Min => lhs.min(rhs),
+            BinaryOpF64::Max => lhs.max(rhs),
+            BinaryOpF64::Pow => lhs.powf(rhs),
+            BinaryOpF64::Hypot => lhs.hypot(rhs),
+        }
+    }
+    fn repr(&self) -> &'static str {
+        BinaryOp::<f64>::repr(self)
+    }
+}
+
+impl TernaryOp<DoubleDouble> for TernaryOpF64 {
+    fn run(&self, a: DoubleDouble, b: DoubleDouble, c: DoubleDouble) -> DoubleDouble {
+        match self {
+            TernaryOpF64::MulAdd => a.mul_add(b, c),
+            TernaryOpF64::Clamp => a.clamp(b, c),
+            TernaryOpF64::Select => if a.hi != 0.0 { b } else { c },
+            TernaryOpF64::IfLt => if a.hi < 0.0 { b } else { c },
+            TernaryOpF64::IfGe => if a.hi >= 0.0 { b } else { c },
+        }
+    }
+    fn repr(&self) -> &'static str {
+        TernaryOp::<f64>::repr(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{Node, ProgramF64};
+
+    fn close(a: DoubleDouble, b: DoubleDouble, tolerance: f64) -> bool {
+        (a - b).abs().to_f64() <= tolerance * b.abs().to_f64()
+    }
+
+    #[test]
+    fn arithmetic() {
+        let tiny = DoubleDouble::from(1e-20);
+        let sum = DoubleDouble::ONE + tiny;
+        assert_eq!(sum.hi, 1.0);
+        assert_eq!(sum.lo, 1e-20);
+        assert_eq!((sum - DoubleDouble::ONE).to_f64(), 1e-20);
+
+        let third = DoubleDouble::ONE / DoubleDouble::from(3.0);
+        assert!(close(third * 3.0, DoubleDouble::ONE, 1e-31));
+
+        let two = DoubleDouble::from(2.0);
+        let root = two.sqrt();
+        assert!(close(root * root, two, 1e-31));
+        assert!(close(two.hypot(two), root * 2.0, 1e-31));
+    }
+
+    #[test]
+    fn transcendental() {
+        let x = DoubleDouble::new(1.5, 1e-17);
+        assert!(close(x.ln().exp(), x, 1e-28));
+        assert!(close(DoubleDouble::ONE.exp().ln(), DoubleDouble::ONE, 1e-28));
+        assert!(close(x.powf(DoubleDouble::from(2.5)), x * x * x.sqrt(), 1e-28));
+        assert_eq!(DoubleDouble::from(-2.0).powf(DoubleDouble::from(3.0)).to_f64(), -8.0);
+        assert_eq!(DoubleDouble::from(1000.0).exp().to_f64(), f64::INFINITY);
+    }
+
+    #[test]
+    fn same_nodes_both_precisions() {
+        let nodes = vec![
+            Node::TernaryOp(TernaryOpF64::MulAdd),
+            Node::Input(0),
+            Node::Constant(0),
+            Node::BinaryOp(BinaryOpF64::Div),
+            Node::Input(1),
+            Node::Constant(1),
+        ];
+        let mut single = ProgramF64::new(nodes.clone()).unwrap();
+        let mut double = ProgramDD::new(nodes).unwrap();
+        single.set_constants(&[0.1, 3.0]).unwrap();
+        double.set_constants(&[0.1.into(), 3.0.into()]).unwrap();
+
+        let a = single.eval(&[3.0, 1.0]).unwrap();
+        let b = double.eval(&[3.0.into(), 1.0.into()]).unwrap();
+        assert!((a - b.to_f64()).abs() <= f64::EPSILON);
+        assert_ne!(b.lo, 0.0);
+    }
+}
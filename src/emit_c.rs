@@ -0,0 +1,651 @@
+//! Exports `ProgramF32`, `ProgramF64` and `ProgramI32` as C99 functions.
+//!
+//! Float ops map to `<math.h>` (`fma`, `fmin`, `fmax`, `pow`, `hypot`),
+//! integer ops go through small `static inline` helpers that reproduce the
+//! wrapping, shift and division rules of `BinaryOp::run` without signed
+//! overflow or out of range shifts. The output matches `Program::eval` bit
+//! for bit as long as the compiler does not contract `a * b + c` into an
+//! fma. Float units start with `#pragma STDC FP_CONTRACT OFF` for that.
+//! GCC does not implement the pragma, so it is hidden from GCC, which only
+//! stops contracting in ISO mode (`-std=c99`) or with `-ffp-contract=off`.
+//! The one exception is which zero `min` and `max` return for `0.0` and
+//! `-0.0`: Rust leaves it unspecified and it differs between toolchains.
+//!
+//! Programs with fallible ops become `bool name(..., T *out)`, returning
+//! false where `eval` reports an `ArithmeticError`. Multi-output programs
+//! always write through `out`, one element per output, and return `void`
+//! unless they are fallible.
+
+use core::fmt::{self, Write};
+use crate::binary_op::*;
+use crate::ternary_op::*;
+use crate::program::{ErrorKind, Node, Program, ProgramError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CExpr {
+    pub text: String,
+    /// Can be used as an operand without parentheses.
+    pub atomic: bool,
+}
+
+impl CExpr {
+    pub fn atom(text: String) -> Self {
+        Self { text, atomic: true }
+    }
+
+    fn operand(&self) -> String {
+        if self.atomic {
+            self.text.clone()
+        } else {
+            format!("({})", self.text)
+        }
+    }
+
+    pub fn call(name: &str, args: &[&CExpr]) -> Self {
+        let args: Vec<&str> = args.iter().map(|arg| arg.text.as_str()).collect();
+        Self::atom(format!("{}({})", name, args.join(", ")))
+    }
+
+    pub fn infix(&self, op: &str, rhs: &CExpr) -> Self {
+        Self { text: format!("{} {} {}", self.operand(), op, rhs.operand()), atomic: false }
+    }
+
+    /// `cond ? then : otherwise`
+    pub fn branch(cond: CExpr, then: &CExpr, otherwise: &CExpr) -> Self {
+        let text = format!("{} ? {} : {}", cond.operand(), then.operand(), otherwise.operand());
+        Self { text, atomic: false }
+    }
+}
+
+/// Keeps `a * b + c` from becoming an fma; GCC warns about the pragma and
+/// ignores it.
+const FP_CONTRACT_OFF: &str = "\
+#if !defined(__GNUC__) || defined(__clang__)
+#pragma STDC FP_CONTRACT OFF
+#endif
+";
+
+/// `static inline` helpers as `(name, dependencies, definition)`.
+const HELPERS: &[(&str, &[&str], &str)] = &[
+    ("bs_f32_clamp", &[], "static inline float bs_f32_clamp(float a, float lo, float hi) { a = a < lo ? lo : a; return a > hi ? hi : a; }"),
+    ("bs_f64_clamp", &[], "static inline double bs_f64_clamp(double a, double lo, double hi) { a = a < lo ? lo : a; return a > hi ? hi : a; }"),
+    ("bs_i32_from_u32", &[], "static inline int32_t bs_i32_from_u32(uint32_t r) { return r <= INT32_MAX ? (int32_t)r : (int32_t)(r - 0x80000000u) - INT32_MAX - 1; }"),
+    ("bs_i32_saturate", &[], "static inline int32_t bs_i32_saturate(int64_t r) { return r > INT32_MAX ? INT32_MAX : r < INT32_MIN ? INT32_MIN : (int32_t)r; }"),
+    ("bs_i32_add", &["bs_i32_from_u32"], "static inline int32_t bs_i32_add(int32_t a, int32_t b) { return bs_i32_from_u32((uint32_t)a + (uint32_t)b); }"),
+    ("bs_i32_sub", &["bs_i32_from_u32"], "static inline int32_t bs_i32_sub(int32_t a, int32_t b) { return bs_i32_from_u32((uint32_t)a - (uint32_t)b); }"),
+    ("bs_i32_mul", &["bs_i32_from_u32"], "static inline int32_t bs_i32_mul(int32_t a, int32_t b) { return bs_i32_from_u32((uint32_t)((uint64_t)(uint32_t)a * (uint32_t)b)); }"),
+    ("bs_i32_div", &[], "static inline int32_t bs_i32_div(int32_t a, int32_t b) { return b == 0 ? 0 : (a == INT32_MIN && b == -1) ? INT32_MIN : a / b; }"),
+    ("bs_i32_rem", &[], "static inline int32_t bs_i32_rem(int32_t a, int32_t b) { return b == 0 ? a : b == -1 ? 0 : a % b; }"),
+    ("bs_i32_min", &[], "static inline int32_t bs_i32_min(int32_t a, int32_t b) { return a < b ? a : b; }"),
+    ("bs_i32_max", &[], "static inline int32_t bs_i32_max(int32_t a, int32_t b) { return a > b ? a : b; }"),
+    ("bs_i32_shl", &["bs_i32_from_u32"], "static inline int32_t bs_i32_shl(int32_t a, int32_t b) { return bs_i32_from_u32((uint32_t)a << (b & 31)); }"),
+    ("bs_i32_shr", &["bs_i32_from_u32"], "static inline int32_t bs_i32_shr(int32_t a, int32_t b) { return bs_i32_from_u32((uint32_t)a >> (b & 31)); }"),
+    ("bs_i32_sar", &[], "static inline int32_t bs_i32_sar(int32_t a, int32_t b) { return a < 0 ? ~(~a >> (b & 31)) : a >> (b & 31); }"),
+    ("bs_i32_checked_add", &[], "static inline bool bs_i32_checked_add(int32_t a, int32_t b, int32_t *r) { if (b > 0 ? a > INT32_MAX - b : a < INT32_MIN - b) return false; *r = a + b; return true; }"),
+    ("bs_i32_checked_sub", &[], "static inline bool bs_i32_checked_sub(int32_t a, int32_t b, int32_t *r) { if (b < 0 ? a > INT32_MAX + b : a < INT32_MIN + b) return false; *r = a - b; return true; }"),
+    ("bs_i32_checked_mul", &[], "static inline bool bs_i32_checked_mul(int32_t a, int32_t b, int32_t *r) { int64_t p = (int64_t)a * b; if (p < INT32_MIN || p > INT32_MAX) return false; *r = (int32_t)p; return true; }"),
+    ("bs_i32_checked_div", &[], "static inline bool bs_i32_checked_div(int32_t a, int32_t b, int32_t *r) { if (b == 0 || (a == INT32_MIN && b == -1)) return false; *r = a / b; return true; }"),
+    ("bs_i32_checked_rem", &[], "static inline bool bs_i32_checked_rem(int32_t a, int32_t b, int32_t *r) { if (b == 0 || (a == INT32_MIN && b == -1)) return false; *r = a % b; return true; }"),
+    ("bs_i32_saturating_add", &["bs_i32_saturate"], "static inline int32_t bs_i32_saturating_add(int32_t a, int32_t b) { return bs_i32_saturate((int64_t)a + b); }"),
+    ("bs_i32_saturating_sub", &["bs_i32_saturate"], "static inline int32_t bs_i32_saturating_sub(int32_t a, int32_t b) { return bs_i32_saturate((int64_t)a - b); }"),
+    ("bs_i32_saturating_mul", &["bs_i32_saturate"], "static inline int32_t bs_i32_saturating_mul(int32_t a, int32_t b) { return bs_i32_saturate((int64_t)a * b); }"),
+    ("bs_i32_clamp", &["bs_i32_min", "bs_i32_max"], "static inline int32_t bs_i32_clamp(int32_t a, int32_t lo, int32_t hi) { return bs_i32_min(bs_i32_max(a, lo), hi); }"),
+];
+
+/// Helper definitions used so far, in dependency order.
+#[derive(Debug, Default)]
+pub struct Helpers {
+    used: Vec<&'static str>,
+}
+
+impl Helpers {
+    /// Calls the helper `name`, pulling in its definition.
+    pub fn call(&mut self, name: &str, args: &[&CExpr]) -> CExpr {
+        self.add(name);
+        CExpr::call(name, args)
+    }
+
+    fn add(&mut self, name: &str) {
+        let &(_, deps, definition) = HELPERS.iter().find(|helper| helper.0 == name)
+            .expect("unknown C helper");
+        for dep in deps {
+            self.add(dep);
+        }
+        if !self.used.contains(&definition) {
+            self.used.push(definition);
+        }
+    }
+}
+
+pub trait CType: Copy {
+    const NAME: &'static str;
+    /// Float types need `<math.h>`.
+    const FLOAT: bool;
+    /// A literal that evaluates to exactly `self`, NaN payloads aside.
+    fn c_literal(self) -> CExpr;
+}
+
+macro_rules! c_float {
+    ($t:ident, $name:expr, $suffix:expr) => {
+        impl CType for $t {
+            const NAME: &'static str = $name;
+            const FLOAT: bool = true;
+            fn c_literal(self) -> CExpr {
+                let text = if self.is_nan() {
+                    "NAN".to_string()
+                } else if self.is_infinite() {
+                    if self > 0.0 { "INFINITY" } else { "-INFINITY" }.to_string()
+                } else {
+                    // `{:?}` is the shortest representation that round-trips
+                    format!("{:?}{}", self, $suffix)
+                };
+                CExpr { atomic: !text.starts_with('-'), text }
+            }
+        }
+    };
+}
+
+c_float!(f32, "float", "f");
+c_float!(f64, "double", "");
+
+impl CType for i32 {
+    const NAME: &'static str = "int32_t";
+    const FLOAT: bool = false;
+    fn c_literal(self) -> CExpr {
+        if self == i32::MIN {
+            CExpr::atom("INT32_MIN".to_string())
+        } else {
+            CExpr { text: self.to_string(), atomic: self >= 0 }
+        }
+    }
+}
+
+/// Spelling of an op applied to already emitted operands.
+pub trait COp {
+    /// Fallible ops get an extra pointer to the result and produce a
+    /// `bool` that is false on failure.
+    fn c(&self, args: &[CExpr], helpers: &mut Helpers) -> CExpr;
+}
+
+macro_rules! c_float_ops {
+    ($bop:ident, $top:ident, $suffix:expr, $clamp:expr) => {
+        impl COp for $bop {
+            fn c(&self, args: &[CExpr], _helpers: &mut Helpers) -> CExpr {
+                let (a, b) = (&args[0], &args[1]);
+                match self {
+                    $bop::Add => a.infix("+", b),
+                    $bop::Sub => a.infix("-", b),
+                    $bop::Mul => a.infix("*", b),
+                    $bop::Div => a.infix("/", b),
+                    $bop::Min => CExpr::call(concat!("fmin", $suffix), &[a, b]),
+                    $bop::Max => CExpr::call(concat!("fmax", $suffix), &[a, b]),
+                    $bop::Pow => CExpr::call(concat!("pow", $suffix), &[a, b]),
+                    $bop::Hypot => CExpr::call(concat!("hypot", $suffix), &[a, b]),
+                }
+            }
+        }
+
+        impl COp for $top {
+            fn c(&self, args: &[CExpr], helpers: &mut Helpers) -> CExpr {
+                let (a, b, c) = (&args[0], &args[1], &args[2]);
+                let zero = CExpr::atom("0".to_string());
+                match self {
+                    $top::MulAdd => CExpr::call(concat!("fma", $suffix), &[a, b, c]),
+                    $top::Clamp => helpers.call($clamp, &[a, b, c]),
+                    $top::Select => CExpr::branch(a.infix("!=", &zero), b, c),
+                    $top::IfLt => CExpr::branch(a.infix("<", &zero), b, c),
+                    $top::IfGe => CExpr::branch(a.infix(">=", &zero), b, c),
+                }
+            }
+        }
+    };
+}
+
+c_float_ops!(BinaryOpF32, TernaryOpF32, "f", "bs_f32_clamp");
+c_float_ops!(BinaryOpF64, TernaryOpF64, "", "bs_f64_clamp");
+
+impl COp for BinaryOpI32 {
+    fn c(&self, args: &[CExpr], helpers: &mut Helpers) -> CExpr {
+        let args: Vec<&CExpr> = args.iter().collect();
+        let (a, b) = (args[0], args[1]);
+        let helper = match self {
+            BinaryOpI32::Xor => return a.infix("^", b),
+            BinaryOpI32::And => return a.infix("&", b),
+            BinaryOpI32::Or => return a.infix("|", b),
+            BinaryOpI32::Eq => return a.infix("==", b),
+            BinaryOpI32::Ne => return a.infix("!=", b),
+            BinaryOpI32::Lt => return a.infix("<", b),
+            BinaryOpI32::Le => return a.infix("<=", b),
+            BinaryOpI32::Gt => return a.infix(">", b),
+            BinaryOpI32::Ge => return a.infix(">=", b),
+            BinaryOpI32::Add => "bs_i32_add",
+            BinaryOpI32::Sub => "bs_i32_sub",
+            BinaryOpI32::Mul => "bs_i32_mul",
+            BinaryOpI32::Div => "bs_i32_div",
+            BinaryOpI32::Rem => "bs_i32_rem",
+            BinaryOpI32::Min => "bs_i32_min",
+            BinaryOpI32::Max => "bs_i32_max",
+            BinaryOpI32::Shl => "bs_i32_shl",
+            BinaryOpI32::Shr => "bs_i32_sar",
+            BinaryOpI32::LogicalShr => "bs_i32_shr",
+            BinaryOpI32::CheckedAdd => "bs_i32_checked_add",
+            BinaryOpI32::CheckedSub => "bs_i32_checked_sub",
+            BinaryOpI32::CheckedMul => "bs_i32_checked_mul",
+            BinaryOpI32::CheckedDiv => "bs_i32_checked_div",
+            BinaryOpI32::CheckedRem => "bs_i32_checked_rem",
+            BinaryOpI32::SaturatingAdd => "bs_i32_saturating_add",
+            BinaryOpI32::SaturatingSub => "bs_i32_saturating_sub",
+            BinaryOpI32::SaturatingMul => "bs_i32_saturating_mul",
+        };
+        helpers.call(helper, &args)
+    }
+}
+
+impl COp for TernaryOpI32 {
+    fn c(&self, args: &[CExpr], helpers: &mut Helpers) -> CExpr {
+        let (a, b, c) = (&args[0], &args[1], &args[2]);
+        let zero = CExpr::atom("0".to_string());
+        match self {
+            TernaryOpI32::Clamp => helpers.call("bs_i32_clamp", &[a, b, c]),
+            TernaryOpI32::Select => CExpr::branch(a.infix("!=", &zero), b, c),
+            TernaryOpI32::IfLt => CExpr::branch(a.infix("<", &zero), b, c),
+            TernaryOpI32::IfGe => CExpr::branch(a.infix(">=", &zero), b, c),
+        }
+    }
+}
+
+struct Emitter<'a, T, BOP, TOP>
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>,
+{
+    program: &'a Program<T, BOP, TOP>,
+    position: usize,
+    lets: usize,
+    temps: usize,
+    helpers: Helpers,
+    body: String,
+}
+
+impl<'a, T, BOP, TOP> Emitter<'a, T, BOP, TOP>
+    where T: CType + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T> + COp,
+          TOP: Copy + PartialEq + TernaryOp<T> + COp,
+{
+    fn expr(&mut self) -> Result<CExpr, fmt::Error> {
+        let node = self.program.nodes[self.position];
+        self.position += 1;
+        Ok(match node {
+            Node::Input(index) => CExpr::atom(format!("a{}", index)),
+            Node::Local(index) => CExpr::atom(format!("l{}", index)),
+            Node::Constant(index) => self.program.constants()[index as usize].c_literal(),
+            Node::Literal(index) => self.program.literals()[index as usize].c_literal(),
+            Node::Lettuce => {
+                let value = self.expr()?;
+                writeln!(self.body, "    const {} l{} = {};", T::NAME, self.lets, value.text)?;
+                self.lets += 1;
+                self.expr()?
+            }
+            Node::BinaryOp(op) => {
+                let mut args = vec![self.expr()?, self.expr()?];
+                if op.is_fallible() {
+                    let temp = format!("t{}", self.temps);
+                    self.temps += 1;
+                    args.push(CExpr::atom(format!("&{}", temp)));
+                    let call = op.c(&args, &mut self.helpers);
+                    writeln!(self.body, "    {} {};", T::NAME, temp)?;
+                    writeln!(self.body, "    if (!{}) return false;", call.text)?;
+                    CExpr::atom(temp)
+                } else {
+                    op.c(&args, &mut self.helpers)
+                }
+            }
+            Node::TernaryOp(op) => {
+                let args = [self.expr()?, self.expr()?, self.expr()?];
+                op.c(&args, &mut self.helpers)
+            }
+            Node::Call(..) => unreachable!("calls are inlined first"),
+        })
+    }
+}
+
+/// Renders `program` as a C99 translation unit with the includes and
+/// helpers it needs, followed by `T name(T a0, ...)` with any calls
+/// inlined. Fails if the program reads constants that were not set.
+pub fn emit_c<T, BOP, TOP>(program: &Program<T, BOP, TOP>, name: &str) -> Result<String, ProgramError>
+    where T: CType + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T> + COp,
+          TOP: Copy + PartialEq + TernaryOp<T> + COp,
+{
+    if !program.functions().is_empty() {
+        return emit_c(&program.inline_calls()?, name);
+    }
+    let info = program.info();
+    if program.constants().len() < info.constant_count {
+        return Err(ProgramError::new(ErrorKind::TooFewConstants)
+            .counts(info.constant_count, program.constants().len()));
+    }
+    let fallible = info.binary_ops.iter().any(|(op, _)| op.is_fallible());
+
+    let mut emitter = Emitter {
+        program, position: 0, lets: 0, temps: 0, helpers: Helpers::default(), body: String::new(),
+    };
+    let results: Vec<CExpr> = (0..program.output_count())
+        .map(|_| emitter.expr().expect("writing to a String cannot fail"))
+        .collect();
+
+    let mut out = String::new();
+    if T::FLOAT {
+        out.push_str(FP_CONTRACT_OFF);
+        out.push_str("#include <math.h>\n");
+    }
+    if fallible {
+        out.push_str("#include <stdbool.h>\n");
+    }
+    out.push_str("#include <stdint.h>\n\n");
+    for definition in &emitter.helpers.used {
+        out.push_str(definition);
+        out.push('\n');
+    }
+    if !emitter.helpers.used.is_empty() {
+        out.push('\n');
+    }
+
+    let mut params: Vec<String> = (0..info.input_count)
+        .map(|index| format!("{} a{}", T::NAME, index))
+        .collect();
+    if fallible || results.len() > 1 {
+        params.push(format!("{} *out", T::NAME));
+        let ret = if fallible { "bool" } else { "void" };
+        out.push_str(&format!("{} {}({}) {{\n{}", ret, name, params.join(", "), emitter.body));
+        if results.len() == 1 {
+            out.push_str(&format!("    *out = {};\n", results[0].text));
+        } else {
+            for (index, result) in results.iter().enumerate() {
+                out.push_str(&format!("    out[{}] = {};\n", index, result.text));
+            }
+        }
+        if fallible {
+            out.push_str("    return true;\n");
+        }
+        out.push_str("}\n");
+    } else {
+        if params.is_empty() {
+            params.push("void".to_string());
+        }
+        out.push_str(&format!("{} {}({}) {{\n{}", T::NAME, name, params.join(", "), emitter.body));
+        out.push_str(&format!("    return {};\n}}\n", results[0].text));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use crate::program::{ProgramF32, ProgramF64, ProgramI32};
+    use crate::rng::Rng;
+
+    /// Compiles `source` with the system C compiler and returns what the
+    /// program prints, or `None` when there is no compiler.
+    fn compile_and_run(source: &str, name: &str) -> Option<String> {
+        let dir = std::env::temp_dir().join(format!("beaver_emit_c_{}_{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let c_file = dir.join("test.c");
+        let binary = dir.join("test");
+        std::fs::write(&c_file, source).unwrap();
+        let compiled = Command::new("cc")
+            .args(["-std=c99", "-O2", "-Wall", "-Werror", "-o"])
+            .arg(&binary)
+            .arg(&c_file)
+            .arg("-lm")
+            .output();
+        let compiled = match compiled {
+            Ok(output) => output,
+            Err(_) => {
+                eprintln!("no C compiler, skipping");
+                return None;
+            }
+        };
+        assert!(compiled.status.success(), "cc failed:\n{}\n{}",
+            String::from_utf8_lossy(&compiled.stderr), source);
+        let run = Command::new(&binary).output().unwrap();
+        assert!(run.status.success());
+        std::fs::remove_dir_all(&dir).ok();
+        Some(String::from_utf8(run.stdout).unwrap())
+    }
+
+    /// Appends a `main` that calls `f` on every row of `inputs` and prints
+    /// one line per call using `print`, which formats the local `r`.
+    fn harness<T: CType>(source: &str, inputs: &[Vec<T>], call: &str, print: &str) -> String {
+        let mut out = format!("{}\n#include <stdio.h>\n#include <string.h>\n\n", source);
+        let arity = inputs[0].len();
+        writeln!(out, "static const {} inputs[][{}] = {{", T::NAME, arity).unwrap();
+        for row in inputs {
+            let row: Vec<String> = row.iter().map(|x| x.c_literal().text).collect();
+            writeln!(out, "    {{ {} }},", row.join(", ")).unwrap();
+        }
+        out.push_str("};\n\nint main(void) {\n");
+        out.push_str("    for (size_t i = 0; i < sizeof(inputs) / sizeof(inputs[0]); i++) {\n");
+        let args: Vec<String> = (0..arity).map(|ii| format!("inputs[i][{}]", ii)).collect();
+        writeln!(out, "        {}", call.replace("ARGS", &args.join(", "))).unwrap();
+        writeln!(out, "        {}", print).unwrap();
+        out.push_str("    }\n    return 0;\n}\n");
+        out
+    }
+
+    fn float_inputs(rng: &mut Rng, arity: usize) -> Vec<Vec<f64>> {
+        let special = [0.0, -0.0, 1.0, -1.0, 0.5, 2.0, -3.5, 1e300, -1e-300, f64::INFINITY, f64::NEG_INFINITY, f64::NAN];
+        (0..500).map(|_| (0..arity).map(|_| {
+            if rng.below(4) == 0 {
+                special[rng.below(special.len())]
+            } else {
+                (rng.next_f64() - 0.5) * 10.0
+            }
+        }).collect()).collect()
+    }
+
+    fn float_program() -> Vec<Node<BinaryOpF64, TernaryOpF64>> {
+        use Node::*;
+        use BinaryOpF64::*;
+        use TernaryOpF64::*;
+        vec![
+            Lettuce, TernaryOp(MulAdd), Input(0), Literal(0), Input(1),
+            TernaryOp(Select), BinaryOp(Sub), Local(0), Input(2),
+            BinaryOp(Add),
+            BinaryOp(Pow), BinaryOp(Hypot), Local(0), Input(1), Literal(1),
+            TernaryOp(IfLt), Input(2),
+            BinaryOp(Min), Local(0), Input(0),
+            BinaryOp(Max), BinaryOp(Div), Input(1), Local(0), Constant(0),
+            TernaryOp(IfGe), Input(0),
+            TernaryOp(Clamp), Input(1), Literal(0), Constant(0),
+            BinaryOp(Mul), Input(2), Literal(1),
+        ]
+    }
+
+    fn same_f64(a: f64, b: f64) -> bool {
+        a.to_bits() == b.to_bits() || (a.is_nan() && b.is_nan())
+    }
+
+    #[test]
+    fn f64_matches_eval() {
+        let mut program = ProgramF64::with_literals(float_program(), vec![-0.75, 1.5]).unwrap();
+        program.set_constants(&[3.25]).unwrap();
+        let source = emit_c(&program, "f").unwrap();
+        assert!(source.starts_with("#if !defined(__GNUC__) || defined(__clang__)\n\
+            #pragma STDC FP_CONTRACT OFF\n#endif\n#include <math.h>\n#include <stdint.h>\n\n\
+            static inline double bs_f64_clamp"), "{}", source);
+        assert!(source.contains("const double l0 = fma(a0, -0.75, a1);\n"), "{}", source);
+
+        let inputs = float_inputs(&mut Rng::new(7), 3);
+        let c = harness(&source, &inputs, "double r = f(ARGS);",
+            "uint64_t bits; memcpy(&bits, &r, sizeof r); printf(\"%016llx\\n\", (unsigned long long)bits);");
+        let output = match compile_and_run(&c, "f64") {
+            Some(output) => output,
+            None => return,
+        };
+        for (row, line) in inputs.iter().zip(output.lines()) {
+            let expected = program.eval(row).unwrap();
+            let actual = f64::from_bits(u64::from_str_radix(line, 16).unwrap());
+            assert!(same_f64(actual, expected), "{:?}: {} != {}", row, actual, expected);
+        }
+        assert_eq!(output.lines().count(), inputs.len());
+    }
+
+    #[test]
+    fn signed_zeros() {
+        use Node::*;
+        let mut program = ProgramF64::multi(vec![
+            BinaryOp(BinaryOpF64::Min), Input(0), Input(1),
+            BinaryOp(BinaryOpF64::Max), Input(0), Input(1),
+        ], Vec::new()).unwrap();
+        let source = emit_c(&program, "f").unwrap();
+        let values = [0.0, -0.0, 1.0, f64::NAN];
+        let inputs: Vec<Vec<f64>> = values.iter()
+            .flat_map(|&a| values.iter().map(move |&b| vec![a, b]))
+            .collect();
+        let c = harness(&source, &inputs, "double r[2]; f(ARGS, r);",
+            "uint64_t bits[2]; memcpy(bits, r, sizeof r); \
+            printf(\"%016llx %016llx\\n\", (unsigned long long)bits[0], (unsigned long long)bits[1]);");
+        let output = match compile_and_run(&c, "zeros") {
+            Some(output) => output,
+            None => return,
+        };
+        // either zero may come back from a tie, NaN only when both are
+        for (row, line) in inputs.iter().zip(output.lines()) {
+            let expected = program.eval_multi(row).unwrap();
+            for (&expected, actual) in expected.iter().zip(line.split_whitespace()) {
+                let actual = f64::from_bits(u64::from_str_radix(actual, 16).unwrap());
+                assert!(actual == expected || (actual.is_nan() && expected.is_nan()),
+                    "{:?}: {} != {}", row, actual, expected);
+            }
+        }
+        assert_eq!(output.lines().count(), inputs.len());
+    }
+
+    #[test]
+    fn multiple_outputs() {
+        let mut program = crate::corpus::sin_cos();
+        let source = emit_c(&program, "sin_cos").unwrap();
+        assert!(source.contains("void sin_cos(double a0, double *out) {\n"), "{}", source);
+        assert!(source.contains("    out[0] = fma(l3, fma(l0, l2, -0.16666666666666632), a0);\n    out[1] = fma(l1, "),
+            "{}", source);
+        assert!(source.ends_with(", fma(l0, -0.5, 1.0));\n}\n"), "{}", source);
+
+        let inputs: Vec<Vec<f64>> = float_inputs(&mut Rng::new(10), 1);
+        let c = harness(&source, &inputs, "double r[2]; sin_cos(ARGS, r);",
+            "uint64_t bits[2]; memcpy(bits, r, sizeof r); \
+            printf(\"%016llx %016llx\\n\", (unsigned long long)bits[0], (unsigned long long)bits[1]);");
+        let output = match compile_and_run(&c, "multi") {
+            Some(output) => output,
+            None => return,
+        };
+        for (row, line) in inputs.iter().zip(output.lines()) {
+            let expected = program.eval_multi(row).unwrap();
+            for (&expected, actual) in expected.iter().zip(line.split_whitespace()) {
+                let actual = f64::from_bits(u64::from_str_radix(actual, 16).unwrap());
+                assert!(same_f64(actual, expected), "{:?}: {} != {}", row, actual, expected);
+            }
+        }
+        assert_eq!(output.lines().count(), inputs.len());
+    }
+
+    #[test]
+    fn f32_matches_eval() {
+        use Node::*;
+        let nodes = float_program().into_iter().map(|node| match node {
+            BinaryOp(op) => BinaryOp(BinaryOpF32::ALL[BinaryOpF64::ALL.iter().position(|&x| x == op).unwrap()]),
+            TernaryOp(op) => TernaryOp(TernaryOpF32::ALL[TernaryOpF64::ALL.iter().position(|&x| x == op).unwrap()]),
+            Input(i) => Input(i),
+            Local(i) => Local(i),
+            Constant(i) => Constant(i),
+            Literal(i) => Literal(i),
+            Lettuce => Lettuce,
+            Call(f, arity) => Call(f, arity),
+        }).collect();
+        let mut program = ProgramF32::with_literals(nodes, vec![-0.75, 0.1]).unwrap();
+        program.set_constants(&[3.25]).unwrap();
+        let source = emit_c(&program, "f").unwrap();
+        assert!(source.contains("fmaf(a0, -0.75f, a1)"), "{}", source);
+
+        let inputs: Vec<Vec<f32>> = float_inputs(&mut Rng::new(8), 3).into_iter()
+            .map(|row| row.into_iter().map(|x| x as f32).collect())
+            .collect();
+        let c = harness(&source, &inputs, "float r = f(ARGS);",
+            "uint32_t bits; memcpy(&bits, &r, sizeof r); printf(\"%08lx\\n\", (unsigned long)bits);");
+        let output = match compile_and_run(&c, "f32") {
+            Some(output) => output,
+            None => return,
+        };
+        for (row, line) in inputs.iter().zip(output.lines()) {
+            let expected = program.eval(row).unwrap();
+            let actual = f32::from_bits(u32::from_str_radix(line, 16).unwrap());
+            assert!(actual.to_bits() == expected.to_bits() || (actual.is_nan() && expected.is_nan()),
+                "{:?}: {} != {}", row, actual, expected);
+        }
+        assert_eq!(output.lines().count(), inputs.len());
+    }
+
+    #[test]
+    fn i32_matches_eval() {
+        use Node::*;
+        let mut rng = Rng::new(9);
+        let special = [0, 1, -1, 2, 31, 32, 33, -32, i32::MIN, i32::MAX, i32::MIN + 1, 1 << 16];
+        let inputs: Vec<Vec<i32>> = (0..400).map(|_| (0..2).map(|_| {
+            if rng.below(2) == 0 {
+                special[rng.below(special.len())]
+            } else {
+                rng.next_u64() as i32 >> rng.below(32)
+            }
+        }).collect()).collect();
+
+        // every op on (a0, a1) and on (a1, a0), summed with Xor
+        let mut programs = vec![];
+        for &op in BinaryOpI32::ALL {
+            for &(x, y) in &[(0, 1), (1, 0)] {
+                programs.push(vec![BinaryOp(op), Input(x), Input(y)]);
+            }
+        }
+        for &op in TernaryOpI32::ALL {
+            programs.push(vec![TernaryOp(op), Input(0), Input(1), Literal(0)]);
+            programs.push(vec![TernaryOp(op), Input(1), Literal(0), Input(0)]);
+        }
+
+        let mut source = String::from("#include <stdbool.h>\n#include <stdint.h>\n");
+        let mut calls = String::new();
+        let mut compiled = vec![];
+        for (index, nodes) in programs.into_iter().enumerate() {
+            let program = ProgramI32::with_literals(nodes, vec![-5]).unwrap();
+            let name = format!("f{}", index);
+            let unit = emit_c(&program, &name).unwrap();
+            // helpers are shared between the functions
+            for line in unit.lines() {
+                if !line.starts_with('#') && (!line.starts_with("static") || !source.contains(line)) {
+                    source.push_str(line);
+                    source.push('\n');
+                }
+            }
+            if program.info().binary_ops.iter().any(|(op, _)| op.is_fallible()) {
+                write!(calls, "if ({}(ARGS, &r)) printf(\"%ld \", (long)r); else printf(\"none \"); ", name).unwrap();
+            } else {
+                write!(calls, "r = {}(ARGS); printf(\"%ld \", (long)r); ", name).unwrap();
+            }
+            compiled.push(program);
+        }
+        let c = harness(&source, &inputs, &format!("int32_t r; {}", calls), "printf(\"\\n\");");
+        let output = match compile_and_run(&c, "i32") {
+            Some(output) => output,
+            None => return,
+        };
+        for (row, line) in inputs.iter().zip(output.lines()) {
+            for (program, actual) in compiled.iter_mut().zip(line.split_whitespace()) {
+                let expected = match program.eval(row) {
+                    Ok(value) => value.to_string(),
+                    Err(_) => "none".to_string(),
+                };
+                assert_eq!(actual, expected, "{:?} {:?}", program, row);
+            }
+        }
+        assert_eq!(output.lines().count(), inputs.len());
+    }
+}
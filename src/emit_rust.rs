@@ -0,0 +1,574 @@
+//! Exports a program as a standalone Rust function.
+//!
+//! Every op is spelled with the method that `BinaryOp::run` or
+//! `TernaryOp::run` calls, so the function returns exactly what
+//! `Program::eval` does; float `clamp` is written out as the comparisons
+//! `run` makes. Constants and literals are inlined, lets become
+//! `let` statements in evaluation order. Programs with fallible ops
+//! return `Option<T>`, `None` where `eval` reports an `ArithmeticError`.
+//! Multi-output programs return a tuple with one element per output, in
+//! the order of `Program::eval_multi`.
+
+use core::fmt;
+use crate::binary_op::*;
+use crate::ternary_op::*;
+use crate::program::{ErrorKind, Node, Program, ProgramError};
+
+/// Binding strength of a Rust expression, used to add only the
+/// parentheses that are needed.
+pub type Prec = u8;
+pub const PREC_BLOCK: Prec = 0;
+pub const PREC_CMP: Prec = 1;
+pub const PREC_BIT_OR: Prec = 2;
+pub const PREC_BIT_XOR: Prec = 3;
+pub const PREC_BIT_AND: Prec = 4;
+pub const PREC_ADD: Prec = 5;
+pub const PREC_MUL: Prec = 6;
+pub const PREC_CAST: Prec = 7;
+pub const PREC_UNARY: Prec = 8;
+pub const PREC_ATOM: Prec = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expr {
+    pub text: String,
+    pub prec: Prec,
+}
+
+impl Expr {
+    pub fn atom(text: String) -> Self {
+        Self { text, prec: PREC_ATOM }
+    }
+
+    fn wrapped(&self, parens: bool) -> String {
+        if parens {
+            format!("({})", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+
+    /// `self.name(args)`
+    pub fn method(&self, name: &str, args: &[&Expr]) -> Expr {
+        let args: Vec<&str> = args.iter().map(|arg| arg.text.as_str()).collect();
+        Expr::atom(format!("{}.{}({})", self.wrapped(self.prec < PREC_ATOM), name, args.join(", ")))
+    }
+
+    /// Left associative binary operator, comparisons don't chain. A cast
+    /// followed by `<` would parse as generic arguments.
+    pub fn infix(&self, op: &str, rhs: &Expr, prec: Prec) -> Expr {
+        let lhs = self.wrapped(self.prec < prec || (prec == PREC_CMP && self.prec == prec)
+            || (self.prec == PREC_CAST && op.starts_with('<')));
+        let text = format!("{} {} {}", lhs, op, rhs.wrapped(rhs.prec <= prec));
+        Expr { text, prec }
+    }
+
+    pub fn cast(&self, ty: &str) -> Expr {
+        Expr { text: format!("{} as {}", self.wrapped(self.prec < PREC_CAST), ty), prec: PREC_CAST }
+    }
+
+    /// `if cond { then } else { otherwise }`
+    pub fn branch(cond: Expr, then: &Expr, otherwise: &Expr) -> Expr {
+        let text = format!("if {} {{ {} }} else {{ {} }}", cond.text, then.text, otherwise.text);
+        Expr { text, prec: PREC_BLOCK }
+    }
+}
+
+pub trait RustType: Copy {
+    const NAME: &'static str;
+    /// A literal that evaluates to exactly `self`.
+    fn rust_literal(self) -> Expr;
+}
+
+macro_rules! rust_float {
+    ($t:ident) => {
+        impl RustType for $t {
+            const NAME: &'static str = stringify!($t);
+            fn rust_literal(self) -> Expr {
+                let text = if self.is_nan() {
+                    format!("{}::from_bits({:#x})", stringify!($t), self.to_bits())
+                } else if self.is_infinite() {
+                    let name = if self > 0.0 { "INFINITY" } else { "NEG_INFINITY" };
+                    format!("{}::{}", stringify!($t), name)
+                } else {
+                    // `{:?}` is the shortest representation that round-trips
+                    format!("{:?}_{}", self, stringify!($t))
+                };
+                let prec = if text.starts_with('-') { PREC_UNARY } else { PREC_ATOM };
+                Expr { text, prec }
+            }
+        }
+    };
+}
+
+macro_rules! rust_integer {
+    ($t:ident) => {
+        impl RustType for $t {
+            const NAME: &'static str = stringify!($t);
+            fn rust_literal(self) -> Expr {
+                let text = format!("{}_{}", self, stringify!($t));
+                let prec = if text.starts_with('-') { PREC_UNARY } else { PREC_ATOM };
+                Expr { text, prec }
+            }
+        }
+    };
+}
+
+rust_float!(f32);
+rust_float!(f64);
+rust_integer!(i32);
+rust_integer!(u32);
+rust_integer!(i64);
+rust_integer!(u64);
+
+/// Spelling of an op applied to already emitted operands.
+pub trait RustOp {
+    /// Ops that can fail produce an `Option`, the emitter binds them to
+    /// a temporary with `?`.
+    fn rust(&self, args: &[Expr]) -> Expr;
+
+    /// Whether `rust` leaves out the operand at `index`, whose value
+    /// cannot change the result.
+    fn drops(&self, _index: usize) -> bool {
+        false
+    }
+}
+
+macro_rules! rust_float_ops {
+    ($bop:ident, $top:ident) => {
+        impl RustOp for $bop {
+            fn rust(&self, args: &[Expr]) -> Expr {
+                let (a, b) = (&args[0], &args[1]);
+                match self {
+                    $bop::Add => a.infix("+", b, PREC_ADD),
+                    $bop::Sub => a.infix("-", b, PREC_ADD),
+                    $bop::Mul => a.infix("*", b, PREC_MUL),
+                    $bop::Div => a.infix("/", b, PREC_MUL),
+                    $bop::Min => a.method("min", &[b]),
+                    $bop::Max => a.method("max", &[b]),
+                    $bop::Pow => a.method("powf", &[b]),
+                    $bop::Hypot => a.method("hypot", &[b]),
+                }
+            }
+        }
+
+        impl RustOp for $top {
+            fn rust(&self, args: &[Expr]) -> Expr {
+                let (a, b, c) = (&args[0], &args[1], &args[2]);
+                let zero = Expr::atom("0.0".to_string());
+                match self {
+                    $top::MulAdd => a.method("mul_add", &[b, c]),
+                    $top::Clamp => {
+                        let text = format!("{{ let (x, lo, hi) = ({}, {}, {}); let x = if x < lo {{ lo }} else {{ x }}; if x > hi {{ hi }} else {{ x }} }}",
+                            a.text, b.text, c.text);
+                        Expr { text, prec: PREC_BLOCK }
+                    }
+                    $top::Select => Expr::branch(a.infix("!=", &zero, PREC_CMP), b, c),
+                    $top::IfLt => Expr::branch(a.infix("<", &zero, PREC_CMP), b, c),
+                    $top::IfGe => Expr::branch(a.infix(">=", &zero, PREC_CMP), b, c),
+                }
+            }
+        }
+    };
+}
+
+rust_float_ops!(BinaryOpF32, TernaryOpF32);
+rust_float_ops!(BinaryOpF64, TernaryOpF64);
+
+macro_rules! rust_integer_ops {
+    ($bop:ident, $top:ident, $t:ident, $signed:ident, $unsigned:ident) => {
+        impl RustOp for $bop {
+            fn rust(&self, args: &[Expr]) -> Expr {
+                let (a, b) = (&args[0], &args[1]);
+                let t = stringify!($t);
+                // shift amounts and reinterpretations skip no-op casts
+                let to = |e: &Expr, ty: &str| if ty == t { e.clone() } else { e.cast(ty) };
+                let from = |e: Expr, ty: &str| if ty == t { e } else { e.cast(t) };
+                let amount = b.cast("u32");
+                let divide = |method: &str, by_zero: &Expr| {
+                    let text = format!("match {} {{ 0 => {}, d => {} }}", b.text, by_zero.text,
+                        a.method(method, &[&Expr::atom("d".to_string())]).text);
+                    Expr { text, prec: PREC_BLOCK }
+                };
+                match self {
+                    $bop::Add => a.method("wrapping_add", &[b]),
+                    $bop::Sub => a.method("wrapping_sub", &[b]),
+                    $bop::Mul => a.method("wrapping_mul", &[b]),
+                    $bop::Div => divide("wrapping_div", &Expr::atom("0".to_string())),
+                    $bop::Rem => divide("wrapping_rem", a),
+                    $bop::Min => a.method("min", &[b]),
+                    $bop::Max => a.method("max", &[b]),
+                    $bop::Xor => a.infix("^", b, PREC_BIT_XOR),
+                    $bop::And => a.infix("&", b, PREC_BIT_AND),
+                    $bop::Or => a.infix("|", b, PREC_BIT_OR),
+                    $bop::Shl => a.method("wrapping_shl", &[&amount]),
+                    $bop::Shr => a.method("wrapping_shr", &[&amount]),
+                    $bop::LogicalShr => from(to(a, stringify!($unsigned)).method("wrapping_shr", &[&amount]),
+                        stringify!($unsigned)),
+                    $bop::CheckedAdd => a.method("checked_add", &[b]),
+                    $bop::CheckedSub => a.method("checked_sub", &[b]),
+                    $bop::CheckedMul => a.method("checked_mul", &[b]),
+                    $bop::CheckedDiv => a.method("checked_div", &[b]),
+                    $bop::CheckedRem => a.method("checked_rem", &[b]),
+                    $bop::SaturatingAdd => a.method("saturating_add", &[b]),
+                    $bop::SaturatingSub => a.method("saturating_sub", &[b]),
+                    $bop::SaturatingMul => a.method("saturating_mul", &[b]),
+                    $bop::Eq => a.infix("==", b, PREC_CMP).cast(t),
+                    $bop::Ne => a.infix("!=", b, PREC_CMP).cast(t),
+                    $bop::Lt => a.infix("<", b, PREC_CMP).cast(t),
+                    $bop::Le => a.infix("<=", b, PREC_CMP).cast(t),
+                    $bop::Gt => a.infix(">", b, PREC_CMP).cast(t),
+                    $bop::Ge => a.infix(">=", b, PREC_CMP).cast(t),
+                }
+            }
+        }
+
+        impl RustOp for $top {
+            fn rust(&self, args: &[Expr]) -> Expr {
+                let (a, b, c) = (&args[0], &args[1], &args[2]);
+                let zero = Expr::atom("0".to_string());
+                let signed = stringify!($t) == stringify!($signed);
+                match self {
+                    $top::Clamp => a.method("max", &[b]).method("min", &[c]),
+                    $top::Select => Expr::branch(a.infix("!=", &zero, PREC_CMP), b, c),
+                    // the comparison is constant for unsigned types
+                    $top::IfLt if !signed => c.clone(),
+                    $top::IfGe if !signed => b.clone(),
+                    $top::IfLt => Expr::branch(a.infix("<", &zero, PREC_CMP), b, c),
+                    $top::IfGe => Expr::branch(a.infix(">=", &zero, PREC_CMP), b, c),
+                }
+            }
+
+            fn drops(&self, index: usize) -> bool {
+                let signed = stringify!($t) == stringify!($signed);
+                match self {
+                    $top::IfLt if !signed => index != 2,
+                    $top::IfGe if !signed => index != 1,
+                    _ => false,
+                }
+            }
+        }
+    };
+}
+
+rust_integer_ops!(BinaryOpI32, TernaryOpI32, i32, i32, u32);
+rust_integer_ops!(BinaryOpU32, TernaryOpU32, u32, i32, u32);
+rust_integer_ops!(BinaryOpI64, TernaryOpI64, i64, i64, u64);
+rust_integer_ops!(BinaryOpU64, TernaryOpU64, u64, i64, u64);
+
+struct Emitter<'a, T, BOP, TOP>
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>,
+{
+    program: &'a Program<T, BOP, TOP>,
+    position: usize,
+    /// `let` statements in order, as the slot they bind and the value.
+    bindings: Vec<(Binding, String)>,
+    /// Which inputs and lets the emitted code reads.
+    inputs_read: Vec<bool>,
+    lets_read: Vec<bool>,
+    lets: usize,
+    temps: usize,
+    /// Inside an operand that `RustOp::drops` leaves out, where nothing
+    /// is read.
+    dropped: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Binding {
+    Let(usize),
+    /// A temporary for a fallible op, and whether it is read.
+    Temp(usize, bool),
+}
+
+impl<'a, T, BOP, TOP> Emitter<'a, T, BOP, TOP>
+    where T: RustType + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T> + RustOp,
+          TOP: Copy + PartialEq + TernaryOp<T> + RustOp,
+{
+    fn expr(&mut self) -> Expr {
+        let node = self.program.nodes[self.position];
+        self.position += 1;
+        match node {
+            Node::Input(index) => {
+                self.inputs_read[index as usize] |= !self.dropped;
+                Expr::atom(format!("a{}", index))
+            }
+            Node::Local(index) => {
+                self.lets_read[index as usize] |= !self.dropped;
+                Expr::atom(format!("l{}", index))
+            }
+            Node::Constant(index) => self.program.constants()[index as usize].rust_literal(),
+            Node::Literal(index) => self.program.literals()[index as usize].rust_literal(),
+            Node::Lettuce => {
+                // the statement is emitted even where its body is dropped
+                let dropped = core::mem::replace(&mut self.dropped, false);
+                let value = self.expr();
+                self.dropped = dropped;
+                self.bindings.push((Binding::Let(self.lets), value.text));
+                self.lets += 1;
+                self.expr()
+            }
+            Node::BinaryOp(op) if op.is_fallible() => {
+                let dropped = core::mem::replace(&mut self.dropped, false);
+                let args = [self.expr(), self.expr()];
+                self.dropped = dropped;
+                let temp = self.temps;
+                self.temps += 1;
+                self.bindings.push((Binding::Temp(temp, !dropped), format!("{}?", op.rust(&args).text)));
+                Expr::atom(format!("t{}", temp))
+            }
+            Node::BinaryOp(op) => {
+                let args = [self.expr(), self.expr()];
+                op.rust(&args)
+            }
+            Node::TernaryOp(op) => {
+                let dropped = self.dropped;
+                let args: [Expr; 3] = core::array::from_fn(|index| {
+                    self.dropped = dropped || op.drops(index);
+                    self.expr()
+                });
+                self.dropped = dropped;
+                op.rust(&args)
+            }
+            Node::Call(..) => unreachable!("calls are inlined first"),
+        }
+    }
+}
+
+/// Renders `program` as `fn name(a0: T, ...) -> T`, or `-> (T, T, ...)`
+/// for multi-output programs, with any calls inlined. Fails if the
+/// program reads constants that were not set.
+pub fn emit_rust<T, BOP, TOP>(program: &Program<T, BOP, TOP>, name: &str) -> Result<String, ProgramError>
+    where T: RustType + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T> + RustOp,
+          TOP: Copy + PartialEq + TernaryOp<T> + RustOp,
+{
+    if !program.functions().is_empty() {
+        return emit_rust(&program.inline_calls()?, name);
+    }
+    let info = program.info();
+    if program.constants().len() < info.constant_count {
+        return Err(ProgramError::new(ErrorKind::TooFewConstants)
+            .counts(info.constant_count, program.constants().len()));
+    }
+    let fallible = info.binary_ops.iter().any(|(op, _)| op.is_fallible());
+
+    let mut emitter = Emitter {
+        program,
+        position: 0,
+        bindings: Vec::new(),
+        inputs_read: vec![false; info.input_count],
+        lets_read: vec![false; info.local_count],
+        lets: 0,
+        temps: 0,
+        dropped: false,
+    };
+    let results: Vec<String> = (0..program.output_count()).map(|_| emitter.expr().text).collect();
+    let (ty, result) = if results.len() == 1 {
+        (T::NAME.to_string(), results[0].clone())
+    } else {
+        (format!("({})", vec![T::NAME; results.len()].join(", ")), format!("({})", results.join(", ")))
+    };
+
+    let unused = |read: bool| if read { "" } else { "_" };
+    let params: Vec<String> = emitter.inputs_read.iter().enumerate()
+        .map(|(index, &read)| format!("{}a{}: {}", unused(read), index, T::NAME))
+        .collect();
+    let body: String = emitter.bindings.iter()
+        .map(|&(binding, ref value)| {
+            let (name, read) = match binding {
+                Binding::Let(slot) => (format!("l{}", slot), emitter.lets_read[slot]),
+                Binding::Temp(temp, read) => (format!("t{}", temp), read),
+            };
+            format!("    let {}{} = {};\n", unused(read), name, value)
+        })
+        .collect();
+    let (ret, tail) = if fallible {
+        (format!("Option<{}>", ty), format!("Some({})", result))
+    } else {
+        (ty, result)
+    };
+    Ok(format!("fn {}({}) -> {} {{\n{}    {}\n}}\n", name, params.join(", "), ret, body, tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{ProgramF64, ProgramI32, ProgramU32};
+    use crate::corpus;
+
+    const SIN_SOURCE: &str = "\
+fn k_sin(a0: f64) -> f64 {
+    let l0 = a0 * a0;
+    let l1 = l0 * l0;
+    let l2 = (l0 * l1).mul_add(l0.mul_add(1.58969099521155e-10_f64, -2.5050760253406863e-8_f64), l0.mul_add(l0.mul_add(2.7557313707070068e-6_f64, -0.0001984126982985795_f64), 0.00833333333332249_f64));
+    let l3 = l0 * a0;
+    l3.mul_add(l0.mul_add(l2, -0.16666666666666632_f64), a0)
+}
+";
+
+    // SIN_SOURCE, pasted
+    fn k_sin(a0: f64) -> f64 {
+        let l0 = a0 * a0;
+        let l1 = l0 * l0;
+        let l2 = (l0 * l1).mul_add(l0.mul_add(1.58969099521155e-10_f64, -2.5050760253406863e-8_f64), l0.mul_add(l0.mul_add(2.7557313707070068e-6_f64, -0.0001984126982985795_f64), 0.00833333333332249_f64));
+        let l3 = l0 * a0;
+        l3.mul_add(l0.mul_add(l2, -0.16666666666666632_f64), a0)
+    }
+
+    #[test]
+    fn sin_source() {
+        let mut sin = corpus::sin();
+        assert_eq!(emit_rust(&sin, "k_sin").unwrap(), SIN_SOURCE);
+        let mut x = -1.0;
+        while x < 1.0 {
+            assert_eq!(k_sin(x).to_bits(), sin.eval(&[x]).unwrap().to_bits());
+            x += 1.0 / 1024.0;
+        }
+
+        let unset = ProgramF64::new(sin.nodes.clone()).unwrap();
+        assert_eq!(emit_rust(&unset, "k_sin").err().map(|e| e.kind), Some(ErrorKind::TooFewConstants));
+    }
+
+    #[test]
+    fn precedence() {
+        use Node::*;
+        use BinaryOpF64::*;
+        let program = ProgramF64::with_literals(vec![
+            BinaryOp(Mul),
+            BinaryOp(Sub), Input(0), BinaryOp(Sub), Input(2), Literal(0),
+            TernaryOp(TernaryOpF64::IfLt), Input(0), Literal(1), BinaryOp(Pow), Literal(0), Input(0),
+        ], vec![-2.0, f64::INFINITY]).unwrap();
+        assert_eq!(emit_rust(&program, "f").unwrap(), "\
+fn f(a0: f64, _a1: f64, a2: f64) -> f64 {
+    (a0 - (a2 - -2.0_f64)) * (if a0 < 0.0 { f64::INFINITY } else { (-2.0_f64).powf(a0) })
+}
+");
+    }
+
+    #[test]
+    fn clamp() {
+        use Node::*;
+        let mut program = ProgramF64::new(vec![
+            TernaryOp(TernaryOpF64::Clamp), Input(0), Input(1), Input(2),
+        ]).unwrap();
+        assert_eq!(emit_rust(&program, "f").unwrap(), "\
+fn f(a0: f64, a1: f64, a2: f64) -> f64 {
+    { let (x, lo, hi) = (a0, a1, a2); let x = if x < lo { lo } else { x }; if x > hi { hi } else { x } }
+}
+");
+
+        fn f(a0: f64, a1: f64, a2: f64) -> f64 {
+            { let (x, lo, hi) = (a0, a1, a2); let x = if x < lo { lo } else { x }; if x > hi { hi } else { x } }
+        }
+        let values = [-1.0, 0.0, -0.0, 2.0, f64::NAN];
+        for &a in values.iter() {
+            for &lo in values.iter() {
+                for &hi in values.iter() {
+                    let expected = program.eval(&[a, lo, hi]).unwrap();
+                    assert_eq!(f(a, lo, hi).to_bits(), expected.to_bits(), "{} {} {}", a, lo, hi);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn multiple_outputs() {
+        use Node::*;
+        use BinaryOpF64::*;
+        let mut program = ProgramF64::multi(vec![
+            Lettuce, BinaryOp(Mul), Input(0), Input(0),
+            TernaryOp(TernaryOpF64::MulAdd), Local(0), Input(1), Literal(0),
+            BinaryOp(Sub), Local(0), Input(1),
+        ], vec![1.0]).unwrap();
+        assert_eq!(emit_rust(&program, "f").unwrap(), "\
+fn f(a0: f64, a1: f64) -> (f64, f64) {
+    let l0 = a0 * a0;
+    (l0.mul_add(a1, 1.0_f64), l0 - a1)
+}
+");
+
+        fn f(a0: f64, a1: f64) -> (f64, f64) {
+            let l0 = a0 * a0;
+            (l0.mul_add(a1, 1.0_f64), l0 - a1)
+        }
+        for &(a, b) in &[(0.5, 3.0), (-2.0, 0.1), (1e200, -1.0)] {
+            let (x, y) = f(a, b);
+            assert_eq!([x, y], program.eval_multi(&[a, b]).unwrap());
+        }
+
+        let fallible = ProgramI32::multi(vec![
+            BinaryOp(BinaryOpI32::CheckedAdd), Input(0), Input(1), Input(1),
+        ], Vec::new()).unwrap();
+        assert_eq!(emit_rust(&fallible, "g").unwrap(), "\
+fn g(a0: i32, a1: i32) -> Option<(i32, i32)> {
+    let t0 = a0.checked_add(a1)?;
+    Some((t0, a1))
+}
+");
+    }
+
+    const CHECKED_SOURCE: &str = "\
+fn f(a0: i32, a1: i32) -> Option<i32> {
+    let l0 = match a1 { 0 => 0, d => a0.wrapping_div(d) };
+    let t0 = ((a0 as u32).wrapping_shr(a1 as u32) as i32).checked_mul(l0)?;
+    Some(if (a0 < a1) as i32 != 0 { t0 } else { a0 ^ -7_i32 })
+}
+";
+
+    #[test]
+    fn integer_ops() {
+        use Node::*;
+        use BinaryOpI32::*;
+        let mut program = ProgramI32::with_literals(vec![
+            Lettuce, BinaryOp(Div), Input(0), Input(1),
+            TernaryOp(TernaryOpI32::Select),
+            BinaryOp(Lt), Input(0), Input(1),
+            BinaryOp(CheckedMul), BinaryOp(LogicalShr), Input(0), Input(1), Local(0),
+            BinaryOp(Xor), Input(0), Literal(0),
+        ], vec![-7]).unwrap();
+        assert_eq!(emit_rust(&program, "f").unwrap(), CHECKED_SOURCE);
+
+        fn f(a0: i32, a1: i32) -> Option<i32> {
+            let l0 = match a1 { 0 => 0, d => a0.wrapping_div(d) };
+            let t0 = ((a0 as u32).wrapping_shr(a1 as u32) as i32).checked_mul(l0)?;
+            Some(if (a0 < a1) as i32 != 0 { t0 } else { a0 ^ -7_i32 })
+        }
+        for &a in &[0, 1, -1, 5, -100, 1 << 20, i32::MIN, i32::MAX] {
+            for &b in &[0, 1, -1, 3, 31, 33, -100, i32::MIN, i32::MAX] {
+                assert_eq!(f(a, b), program.eval(&[a, b]).ok(), "{} {}", a, b);
+            }
+        }
+
+        let unsigned = ProgramU32::new(vec![
+            TernaryOp(TernaryOpU32::IfLt), BinaryOp(BinaryOpU32::Shr), Input(0), Input(1), Input(0), Input(1),
+        ]).unwrap();
+        assert_eq!(emit_rust(&unsigned, "g").unwrap(), "fn g(_a0: u32, a1: u32) -> u32 {\n    a1\n}\n");
+        let dropped_let = ProgramU32::new(vec![
+            Lettuce, Input(0), TernaryOp(TernaryOpU32::IfGe), Local(0), Input(1), Local(0),
+        ]).unwrap();
+        assert_eq!(emit_rust(&dropped_let, "k").unwrap(), "fn k(a0: u32, a1: u32) -> u32 {\n    let _l0 = a0;\n    a1\n}\n");
+
+        let mut checked = ProgramU32::new(vec![
+            Lettuce, Input(0),
+            TernaryOp(TernaryOpU32::IfGe), BinaryOp(BinaryOpU32::CheckedAdd), Local(0), Input(1), Input(1), Input(2),
+        ]).unwrap();
+        assert_eq!(emit_rust(&checked, "h").unwrap(), "\
+fn h(a0: u32, a1: u32, _a2: u32) -> Option<u32> {
+    let l0 = a0;
+    let _t0 = l0.checked_add(a1)?;
+    Some(a1)
+}
+");
+
+        fn h(a0: u32, a1: u32, _a2: u32) -> Option<u32> {
+            let l0 = a0;
+            let _t0 = l0.checked_add(a1)?;
+            Some(a1)
+        }
+        for &(a, b) in &[(0, 0), (1, 2), (u32::MAX, 1), (7, u32::MAX)] {
+            assert_eq!(h(a, b, 3), checked.eval(&[a, b, 3]).ok(), "{} {}", a, b);
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Symbolic regression by genetic programming over `f64` programs.
+//!
+//! Individuals are let-free trees over the configured ops, the dataset's
+//! inputs and random literals. Every generation keeps the best individual
+//! and fills the rest of the population from tournament winners, by
+//! subtree crossover or by mutation. Fitness is the mean squared error on
+//! the dataset, evaluated with `LaneProgram`; ties go to the smaller tree.
+//!
+//! With `GpConfig::adfs` set, every individual also evolves the bodies of
+//! its own functions (automatically defined functions, ADFs), which its
+//! main tree calls. Crossover and mutation work on one tree at a time, and
+//! crossover swaps subtrees between the same tree of both parents.
+
+use crate::binary_op::BinaryOpF64;
+use crate::ternary_op::TernaryOpF64;
+use crate::program::{Function, FunctionF64, MAX_CALL_DEPTH, Node, NodeF64, ProgramError, ProgramF64, subtree_end};
+use crate::rng::Rng;
+
+#[derive(Debug, Clone)]
+pub struct GpConfig {
+    pub population: usize,
+    pub tournament: usize,
+    /// Largest depth of the random trees of the first generation; the
+    /// subtrees grown by mutation are at most half as deep.
+    pub init_depth: usize,
+    /// Offspring larger than this, counting the nodes of every tree, are
+    /// replaced by their first parent.
+    pub max_nodes: usize,
+    /// Share of offspring made by crossover, the rest are mutants.
+    pub crossover_rate: f64,
+    pub binary_ops: Vec<BinaryOpF64>,
+    pub ternary_ops: Vec<TernaryOpF64>,
+    /// Chance that a new leaf is a literal rather than an input.
+    pub literal_rate: f64,
+    /// New literals are uniform in `[lo, hi)`.
+    pub literal_range: (f64, f64),
+    /// Arity of each ADF, all at least 1. The main tree may call every
+    /// ADF, ADF `i` only those before it.
+    pub adfs: Vec<usize>,
+    pub seed: u64,
+}
+
+impl Default for GpConfig {
+    fn default() -> Self {
+        use BinaryOpF64::*;
+        Self {
+            population: 500,
+            tournament: 5,
+            init_depth: 5,
+            max_nodes: 64,
+            crossover_rate: 0.7,
+            binary_ops: vec![Add, Sub, Mul, Div],
+            ternary_ops: Vec::new(),
+            literal_rate: 0.3,
+            literal_range: (-2.0, 2.0),
+            adfs: Vec::new(),
+            seed: 1,
+        }
+    }
+}
+
+/// Samples stored by column: `inputs[i][row]` is input `i` of a row and
+/// `targets[row]` the value the program should produce for it.
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    pub inputs: Vec<Vec<f64>>,
+    pub targets: Vec<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Individual {
+    pub nodes: Vec<NodeF64>,
+    pub literals: Vec<f64>,
+    /// The ADFs `adf0, adf1, ...`, empty without `GpConfig::adfs`.
+    pub functions: Vec<FunctionF64>,
+    /// Mean squared error, infinite when any sample is not finite.
+    pub error: f64,
+}
+
+impl Individual {
+    pub fn program(&self) -> ProgramF64 {
+        ProgramF64::with_functions(self.nodes.clone(), self.literals.clone(), self.functions.clone())
+            .expect("individuals are valid trees")
+    }
+
+    /// Nodes in the main tree and every ADF.
+    pub fn size(&self) -> usize {
+        self.nodes.len() + self.functions.iter().map(|function| function.nodes.len()).sum::<usize>()
+    }
+
+    /// The main tree for 0, ADF `tree - 1` otherwise.
+    fn tree(&self, tree: usize) -> &[NodeF64] {
+        match tree {
+            0 => &self.nodes,
+            _ => &self.functions[tree - 1].nodes,
+        }
+    }
+
+    fn tree_mut(&mut self, tree: usize) -> &mut Vec<NodeF64> {
+        match tree {
+            0 => &mut self.nodes,
+            _ => &mut self.functions[tree - 1].nodes,
+        }
+    }
+
+    fn better_than(&self, other: &Individual) -> bool {
+        (self.error, self.size()) < (other.error, other.size())
+    }
+}
+
+/// What a tree may read and call: inputs below `inputs` and the first
+/// `calls` ADFs.
+#[derive(Clone, Copy)]
+struct Scope {
+    inputs: usize,
+    calls: usize,
+}
+
+pub struct Gp {
+    config: GpConfig,
+    dataset: Dataset,
+    rng: Rng,
+    population: Vec<Individual>,
+    generation: usize,
+}
+
+impl Gp {
+    /// Panics if the config has no ops or an empty population, the dataset
+    /// no inputs, or an ADF takes no arguments or more than a call can pass.
+    pub fn new(config: GpConfig, dataset: Dataset) -> Self {
+        assert!(config.population > 0, "empty population");
+        assert!(!config.binary_ops.is_empty() || !config.ternary_ops.is_empty(), "no ops to build trees from");
+        assert!(!dataset.inputs.is_empty(), "dataset has no inputs");
+        assert!(config.adfs.iter().all(|&arity| (1..=u8::MAX as usize).contains(&arity)), "invalid ADF arity");
+        assert!(config.adfs.len() <= MAX_CALL_DEPTH, "too many ADFs");
+        let mut gp = Self {
+            rng: Rng::new(config.seed),
+            config,
+            dataset,
+            population: Vec::new(),
+            generation: 0,
+        };
+        // ramped half-and-half
+        for index in 0..gp.config.population {
+            let depth = 1 + index % gp.config.init_depth.max(1);
+            let (mut nodes, mut literals) = (Vec::new(), Vec::new());
+            gp.grow(gp.scope(0), depth, index % 2 == 0, &mut nodes, &mut literals);
+            let mut functions = Vec::new();
+            for adf in 0..gp.config.adfs.len() {
+                let mut body = Vec::new();
+                gp.grow(gp.scope(adf + 1), depth, index % 2 == 0, &mut body, &mut literals);
+                functions.push(Function { name: format!("adf{}", adf), arity: gp.config.adfs[adf], nodes: body });
+            }
+            let individual = gp.evaluate(Individual { nodes, literals, functions, error: 0.0 });
+            gp.population.push(individual);
+        }
+        gp
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    pub fn population(&self) -> &[Individual] {
+        &self.population
+    }
+
+    pub fn best(&self) -> &Individual {
+        self.population.iter()
+            .fold(&self.population[0], |best, individual| {
+                if individual.better_than(best) { individual } else { best }
+            })
+    }
+
+    pub fn step(&mut self) {
+        let mut next = vec![self.best().clone()];
+        while next.len() < self.config.population {
+            let child = if self.rng.next_f64() < self.config.crossover_rate {
+                let (a, b) = (self.tournament(), self.tournament());
+                self.crossover(a, b)
+            } else {
+                let parent = self.tournament();
+                self.mutate(parent)
+            };
+            next.push(child);
+        }
+        self.population = next;
+        self.generation += 1;
+    }
+
+    pub fn error(&self, nodes: &[NodeF64], literals: &[f64]) -> f64 {
+        self.program_error(ProgramF64::with_literals(nodes.to_vec(), literals.to_vec()))
+    }
+
+    /// Calls are inlined, so this is `error` of the inlined program.
+    fn program_error(&self, program: Result<ProgramF64, ProgramError>) -> f64 {
+        let columns: Vec<&[f64]> = self.dataset.inputs.iter().map(|column| &column[..]).collect();
+        let values = program
+            .and_then(|program| program.lanes().map_err(|(_, error)| error))
+            .and_then(|mut program| program.eval_columns(&columns));
+        let values = match values {
+            Ok(values) => values,
+            Err(_) => return f64::INFINITY,
+        };
+        let mut sum = 0.0;
+        for (value, target) in values.iter().zip(&self.dataset.targets) {
+            let diff = value - target;
+            sum += diff * diff;
+        }
+        let error = sum / self.dataset.targets.len().max(1) as f64;
+        if error.is_finite() { error } else { f64::INFINITY }
+    }
+
+    /// Compacts the literals of `individual` and sets its error.
+    fn evaluate(&self, mut individual: Individual) -> Individual {
+        compact_literals(&mut individual);
+        individual.error = if individual.functions.is_empty() {
+            self.error(&individual.nodes, &individual.literals)
+        } else {
+            self.program_error(ProgramF64::with_functions(
+                individual.nodes.clone(), individual.literals.clone(), individual.functions.clone()))
+        };
+        individual
+    }
+
+    /// The scope of the main tree for 0, of ADF `tree - 1` otherwise.
+    fn scope(&self, tree: usize) -> Scope {
+        match tree {
+            0 => Scope { inputs: self.dataset.inputs.len(), calls: self.config.adfs.len() },
+            _ => Scope { inputs: self.config.adfs[tree - 1], calls: tree - 1 },
+        }
+    }
+
+    /// A random tree of an individual: always the main tree without ADFs.
+    fn random_tree(&mut self) -> usize {
+        if self.config.adfs.is_empty() {
+            0
+        } else {
+            self.rng.below(1 + self.config.adfs.len())
+        }
+    }
+
+    fn tournament(&mut self) -> usize {
+        let mut winner = self.rng.below(self.population.len());
+        for _ in 1..self.config.tournament {
+            let challenger = self.rng.below(self.population.len());
+            if self.population[challenger].better_than(&self.population[winner]) {
+                winner = challenger;
+            }
+        }
+        winner
+    }
+
+    fn leaf(&mut self, scope: Scope, nodes: &mut Vec<NodeF64>, literals: &mut Vec<f64>) {
+        if self.rng.next_f64() < self.config.literal_rate {
+            let (lo, hi) = self.config.literal_range;
+            literals.push(lo + (hi - lo) * self.rng.next_f64());
+            nodes.push(Node::Literal((literals.len() - 1) as u16));
+        } else {
+            nodes.push(Node::Input(self.rng.below(scope.inputs) as u16));
+        }
+    }
+
+    /// An op or a call to one of the ADFs in `scope`.
+    fn random_op(&mut self, scope: Scope) -> NodeF64 {
+        let binary = self.config.binary_ops.len();
+        let ternary = self.config.ternary_ops.len();
+        let index = self.rng.below(binary + ternary + scope.calls);
+        if index < binary {
+            Node::BinaryOp(self.config.binary_ops[index])
+        } else if index < binary + ternary {
+            Node::TernaryOp(self.config.ternary_ops[index - binary])
+        } else {
+            let adf = index - binary - ternary;
+            Node::Call(adf as u16, self.config.adfs[adf] as u8)
+        }
+    }
+
+    /// Appends a random tree of at most `depth` levels of ops, exactly
+    /// `depth` on every path when `full`.
+    fn grow(&mut self, scope: Scope, depth: usize, full: bool, nodes: &mut Vec<NodeF64>, literals: &mut Vec<f64>) {
+        if depth == 0 || (!full && self.rng.next_f64() < 0.3) {
+            self.leaf(scope, nodes, literals);
+            return;
+        }
+        let op = self.random_op(scope);
+        nodes.push(op);
+        for _ in 0..op.arity() {
+            self.grow(scope, depth - 1, full, nodes, literals);
+        }
+    }
+
+    /// Replaces a random subtree of `a` with a random subtree of the same
+    /// tree of `b`.
+    fn crossover(&mut self, a: usize, b: usize) -> Individual {
+        let tree = self.random_tree();
+        let (a, b) = (&self.population[a], &self.population[b]);
+        let (tree_a, tree_b) = (a.tree(tree), b.tree(tree));
+        let start_a = self.rng.below(tree_a.len());
+        let end_a = subtree_end(tree_a, start_a);
+        let start_b = self.rng.below(tree_b.len());
+        let end_b = subtree_end(tree_b, start_b);
+        if a.size() - (end_a - start_a) + (end_b - start_b) > self.config.max_nodes {
+            return a.clone();
+        }
+
+        let mut child = a.clone();
+        let mut nodes = tree_a[..start_a].to_vec();
+        for &node in &tree_b[start_b..end_b] {
+            nodes.push(match node {
+                Node::Literal(index) => {
+                    child.literals.push(b.literals[index as usize]);
+                    Node::Literal((child.literals.len() - 1) as u16)
+                }
+                node => node,
+            });
+        }
+        nodes.extend_from_slice(&tree_a[end_a..]);
+        *child.tree_mut(tree) = nodes;
+        self.evaluate(child)
+    }
+
+    /// Either regrows a random subtree or changes a single node: a
+    /// literal is nudged, an op or input is swapped for another one.
+    fn mutate(&mut self, parent: usize) -> Individual {
+        let mut child = self.population[parent].clone();
+        let tree = self.random_tree();
+        let scope = self.scope(tree);
+        let position = self.rng.below(child.tree(tree).len());
+        if self.rng.next_f64() < 0.5 {
+            let end = subtree_end(child.tree(tree), position);
+            let mut subtree = Vec::new();
+            let depth = 1 + self.rng.below((self.config.init_depth / 2).max(1));
+            self.grow(scope, depth, false, &mut subtree, &mut child.literals);
+            if child.size() - (end - position) + subtree.len() <= self.config.max_nodes {
+                child.tree_mut(tree).splice(position..end, subtree);
+            }
+        } else {
+            match child.tree(tree)[position] {
+                Node::Literal(index) => {
+                    let value = &mut child.literals[index as usize];
+                    let scale = 0.1 * (value.abs() + 0.1);
+                    *value += scale * (2.0 * self.rng.next_f64() - 1.0);
+                }
+                Node::Input(_) => {
+                    child.tree_mut(tree)[position] = Node::Input(self.rng.below(scope.inputs) as u16);
+                }
+                node => {
+                    let op = self.random_op(scope);
+                    if op.arity() == node.arity() {
+                        child.tree_mut(tree)[position] = op;
+                    }
+                }
+            }
+        }
+        self.evaluate(child)
+    }
+}
+
+/// Drops literals no node of any tree refers to, keeping the order of the
+/// rest.
+fn compact_literals(individual: &mut Individual) {
+    let literals = &individual.literals;
+    let mut remap = vec![None; literals.len()];
+    let mut kept = Vec::new();
+    let trees = individual.functions.iter_mut().map(|function| &mut function.nodes);
+    for node in core::iter::once(&mut individual.nodes).chain(trees).flatten() {
+        if let Node::Literal(index) = node {
+            let slot = remap[*index as usize].get_or_insert_with(|| {
+                kept.push(literals[*index as usize]);
+                kept.len() - 1
+            });
+            *index = *slot as u16;
+        }
+    }
+    individual.literals = kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dataset() -> Dataset {
+        let xs: Vec<f64> = (0..40).map(|i| i as f64 / 20.0 - 1.0).collect();
+        let targets = xs.iter().map(|x| x * x * x + x).collect();
+        Dataset { inputs: vec![xs], targets }
+    }
+
+    #[test]
+    fn finds_polynomial() {
+        let config = GpConfig { population: 300, seed: 3, ..GpConfig::default() };
+        let mut gp = Gp::new(config, dataset());
+        let initial = gp.best().error;
+        for _ in 0..40 {
+            gp.step();
+            let best = gp.best();
+            assert!(best.nodes.len() <= 64);
+            assert_eq!(best.error, gp.error(&best.nodes, &best.literals));
+        }
+        assert_eq!(gp.generation(), 40);
+        assert!(gp.best().error < initial);
+        assert!(gp.best().error < 1e-6, "{:?} {}", gp.best().program(), gp.best().error);
+    }
+
+    #[test]
+    fn deterministic() {
+        let run = || {
+            let mut gp = Gp::new(GpConfig { population: 50, ..GpConfig::default() }, dataset());
+            for _ in 0..5 {
+                gp.step();
+            }
+            gp.population().iter().map(|individual| format!("{:?}", individual.program())).collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn evolves_adfs() {
+        let data = dataset();
+        let xs = data.inputs[0].clone();
+        let targets = xs.iter().map(|x| (x * x + 1.0) * (x * x + 1.0) + x * x).collect();
+        let config = GpConfig { population: 200, adfs: vec![1, 2], seed: 5, ..GpConfig::default() };
+        let mut gp = Gp::new(config, Dataset { inputs: vec![xs.clone()], targets });
+        let initial = gp.best().error;
+        for _ in 0..20 {
+            gp.step();
+        }
+        let best = gp.best();
+        assert!(best.error < initial);
+        assert!(best.size() <= 64);
+        assert_eq!(best.functions.iter().map(|function| function.arity).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(best.functions[1].name, "adf1");
+        let mut program = best.program();
+        let sum: f64 = xs.iter().zip(&gp.dataset.targets)
+            .map(|(&x, target)| (program.eval(&[x]).unwrap() - target).powi(2))
+            .sum();
+        assert_eq!(best.error, sum / xs.len() as f64);
+        assert!(gp.population().iter().any(|individual| individual.nodes.iter().any(|node| matches!(node, Node::Call(..)))));
+    }
+
+    #[test]
+    fn literals_stay_compact() {
+        use BinaryOpF64::*;
+        let mut individual = Individual {
+            nodes: vec![
+                Node::BinaryOp(Add), Node::Literal(2), Node::BinaryOp(Mul), Node::Literal(0), Node::Literal(2),
+            ],
+            literals: vec![1.0, 2.0, 3.0],
+            functions: Vec::new(),
+            error: 0.0,
+        };
+        compact_literals(&mut individual);
+        assert_eq!(individual.literals, vec![3.0, 1.0]);
+        assert_eq!(individual.nodes[1], Node::Literal(0));
+        assert_eq!(individual.nodes[3], Node::Literal(1));
+        assert_eq!(individual.nodes[4], Node::Literal(0));
+    }
+}
@@ -0,0 +1,491 @@
+//! Infix and LaTeX formatting of programs.
+//!
+//! Binary ops whose `repr()` is an operator (`+`, `<<`, `<=`, ...) are
+//! written infix with the usual precedence and only the parentheses the
+//! tree needs, everything else is a call such as `min(a, b)`. The plain
+//! infix form reads back with `parse::parse_infix` unless the program has
+//! functions: calls are written by name and the definitions are left out,
+//! and the parser knows neither. LaTeX additionally
+//! draws `/` as `\frac`, `pow` as a superscript, `mul_add` as
+//! `a \cdot b + c` and comparisons as Iverson brackets.
+//!
+//! Multi-output programs are written as a tuple `(x, y)`. Their locals are
+//! shared between outputs, so `LetStyle::Inline` reads a local outside the
+//! `let ... in` that binds it; `LetStyle::default_for` picks `Where` for
+//! them.
+
+use core::fmt;
+use crate::binary_op::BinaryOp;
+use crate::ternary_op::TernaryOp;
+use crate::program::{Node, Program};
+
+/// How `Lettuce` bindings are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LetStyle {
+    /// `let l0 = a0 * a0 in l0 + l0`
+    Inline,
+    /// The expression first, followed by the bindings in evaluation order.
+    Where,
+}
+
+impl LetStyle {
+    /// `Where` for multi-output programs, `Inline` otherwise.
+    pub fn default_for<T, BOP, TOP>(program: &Program<T, BOP, TOP>) -> Self
+        where T: Copy + fmt::Debug,
+              BOP: Copy + PartialEq + BinaryOp<T>,
+              TOP: Copy + PartialEq + TernaryOp<T>,
+    {
+        if program.output_count() > 1 { LetStyle::Where } else { LetStyle::Inline }
+    }
+}
+
+pub const PREC_LET: u8 = 0;
+pub const PREC_CMP: u8 = 1;
+pub const PREC_BIT_OR: u8 = 2;
+pub const PREC_BIT_XOR: u8 = 3;
+pub const PREC_BIT_AND: u8 = 4;
+pub const PREC_SHIFT: u8 = 5;
+pub const PREC_ADD: u8 = 6;
+pub const PREC_MUL: u8 = 7;
+pub const PREC_UNARY: u8 = 8;
+pub const PREC_POW: u8 = 9;
+pub const PREC_ATOM: u8 = 10;
+
+/// Precedence of a binary op written infix, keyed by its `repr()`, or
+/// `None` for ops written as calls. All infix ops are left associative,
+/// comparisons don't chain.
+pub fn infix_precedence(repr: &str) -> Option<u8> {
+    Some(match repr {
+        "==" | "!=" | "<" | "<=" | ">" | ">=" => PREC_CMP,
+        "|" => PREC_BIT_OR,
+        "^" => PREC_BIT_XOR,
+        "&" => PREC_BIT_AND,
+        "<<" | ">>" | ">>>" => PREC_SHIFT,
+        "+" | "-" => PREC_ADD,
+        "*" | "/" | "%" => PREC_MUL,
+        _ => return None,
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Formatted {
+    text: String,
+    prec: u8,
+    /// Starts with a minus sign, so it needs parentheses after an operator.
+    negative: bool,
+}
+
+impl Formatted {
+    fn new(text: String, prec: u8) -> Self {
+        Self { text, prec, negative: false }
+    }
+}
+
+trait Syntax {
+    fn name(&self, prefix: char, index: usize) -> String;
+    fn literal(&self, debug: String) -> Formatted;
+    fn group(&self, text: &str) -> String;
+    fn binary(&self, repr: &str, a: Formatted, b: Formatted) -> Formatted;
+    fn ternary(&self, repr: &str, a: Formatted, b: Formatted, c: Formatted) -> Formatted;
+    fn function(&self, name: &str, args: &[Formatted]) -> Formatted;
+    fn let_in(&self, name: String, value: Formatted, body: Formatted) -> Formatted;
+    fn where_clause(&self, body: String, bindings: &[(String, String)]) -> String;
+
+    fn operand(&self, operand: &Formatted, parens: bool) -> String {
+        if parens {
+            self.group(&operand.text)
+        } else {
+            operand.text.clone()
+        }
+    }
+
+    fn infix(&self, a: &Formatted, op: &str, b: &Formatted, prec: u8) -> Formatted {
+        let lhs = self.operand(a, a.prec < prec || (prec == PREC_CMP && a.prec == prec));
+        let rhs = self.operand(b, b.prec <= prec || b.negative);
+        Formatted::new(format!("{} {} {}", lhs, op, rhs), prec)
+    }
+
+    fn call(&self, name: &str, args: &[Formatted]) -> Formatted {
+        let args: Vec<&str> = args.iter().map(|arg| arg.text.as_str()).collect();
+        Formatted::new(format!("{}{}", name, self.group(&args.join(", "))), PREC_ATOM)
+    }
+}
+
+struct Plain;
+
+impl Syntax for Plain {
+    fn name(&self, prefix: char, index: usize) -> String {
+        format!("{}{}", prefix, index)
+    }
+
+    fn literal(&self, debug: String) -> Formatted {
+        let negative = debug.starts_with('-');
+        Formatted { text: debug, prec: if negative { PREC_UNARY } else { PREC_ATOM }, negative }
+    }
+
+    fn group(&self, text: &str) -> String {
+        format!("({})", text)
+    }
+
+    fn binary(&self, repr: &str, a: Formatted, b: Formatted) -> Formatted {
+        match infix_precedence(repr) {
+            Some(prec) => self.infix(&a, repr, &b, prec),
+            None => self.call(repr, &[a, b]),
+        }
+    }
+
+    fn ternary(&self, repr: &str, a: Formatted, b: Formatted, c: Formatted) -> Formatted {
+        self.call(repr, &[a, b, c])
+    }
+
+    fn function(&self, name: &str, args: &[Formatted]) -> Formatted {
+        self.call(name, args)
+    }
+
+    fn let_in(&self, name: String, value: Formatted, body: Formatted) -> Formatted {
+        let value = self.operand(&value, value.prec == PREC_LET);
+        Formatted::new(format!("let {} = {} in {}", name, value, body.text), PREC_LET)
+    }
+
+    fn where_clause(&self, body: String, bindings: &[(String, String)]) -> String {
+        let mut out = body;
+        if !bindings.is_empty() {
+            out.push_str("\nwhere");
+            for (name, value) in bindings {
+                out.push_str(&format!("\n    {} = {}", name, value));
+            }
+        }
+        out
+    }
+}
+
+struct Latex;
+
+impl Latex {
+    fn operator_name(repr: &str) -> String {
+        match repr {
+            "min" | "max" => format!("\\{}", repr),
+            _ => format!("\\operatorname{{{}}}", repr.replace('_', "\\_")),
+        }
+    }
+
+    fn cases(&self, cond: Formatted, b: Formatted, c: Formatted) -> Formatted {
+        Formatted::new(format!("\\begin{{cases}} {} & \\text{{if }} {} \\\\ {} & \\text{{otherwise}} \\end{{cases}}",
+            b.text, cond.text, c.text), PREC_ATOM)
+    }
+}
+
+impl Syntax for Latex {
+    fn name(&self, prefix: char, index: usize) -> String {
+        format!("{}_{{{}}}", prefix, index)
+    }
+
+    fn literal(&self, debug: String) -> Formatted {
+        let negative = debug.starts_with('-');
+        let (text, prec) = match debug.trim_start_matches('-') {
+            "inf" => ("\\infty".to_string(), PREC_ATOM),
+            "NaN" => ("\\mathrm{NaN}".to_string(), PREC_ATOM),
+            digits => match digits.split_once('e') {
+                Some((mantissa, exponent)) => {
+                    (format!("{} \\times 10^{{{}}}", mantissa, exponent), PREC_MUL)
+                }
+                None => (digits.to_string(), PREC_ATOM),
+            },
+        };
+        if negative {
+            Formatted { text: format!("-{}", text), prec: prec.min(PREC_UNARY), negative }
+        } else {
+            Formatted::new(text, prec)
+        }
+    }
+
+    fn group(&self, text: &str) -> String {
+        format!("\\left({}\\right)", text)
+    }
+
+    fn binary(&self, repr: &str, a: Formatted, b: Formatted) -> Formatted {
+        let op = match repr {
+            "/" => {
+                // a fraction reads as one unit, but not as a base
+                return Formatted::new(format!("\\frac{{{}}}{{{}}}", a.text, b.text), PREC_POW);
+            }
+            "pow" => {
+                let base = self.operand(&a, a.prec < PREC_ATOM);
+                return Formatted::new(format!("{}^{{{}}}", base, b.text), PREC_POW);
+            }
+            "==" | "!=" | "<" | "<=" | ">" | ">=" => {
+                let op = match repr {
+                    "==" => "=",
+                    "!=" => "\\neq",
+                    "<=" => "\\leq",
+                    ">=" => "\\geq",
+                    op => op,
+                };
+                let compared = self.infix(&a, op, &b, PREC_CMP);
+                return Formatted::new(format!("\\left[{}\\right]", compared.text), PREC_ATOM);
+            }
+            "*" => "\\cdot",
+            "%" => "\\bmod",
+            "^" => "\\oplus",
+            "&" => "\\mathbin{\\&}",
+            "|" => "\\mathbin{|}",
+            "<<" => "\\ll",
+            ">>" => "\\gg",
+            ">>>" => "\\ggg",
+            op => op,
+        };
+        match infix_precedence(repr) {
+            Some(prec) => self.infix(&a, op, &b, prec),
+            None => self.call(&Latex::operator_name(repr), &[a, b]),
+        }
+    }
+
+    fn ternary(&self, repr: &str, a: Formatted, b: Formatted, c: Formatted) -> Formatted {
+        let zero = Formatted::new("0".to_string(), PREC_ATOM);
+        match repr {
+            "mul_add" => {
+                let product = self.infix(&a, "\\cdot", &b, PREC_MUL);
+                self.infix(&product, "+", &c, PREC_ADD)
+            }
+            "select" => self.cases(self.infix(&a, "\\neq", &zero, PREC_CMP), b, c),
+            "if_lt" => self.cases(self.infix(&a, "<", &zero, PREC_CMP), b, c),
+            "if_ge" => self.cases(self.infix(&a, "\\geq", &zero, PREC_CMP), b, c),
+            _ => self.call(&Latex::operator_name(repr), &[a, b, c]),
+        }
+    }
+
+    fn function(&self, name: &str, args: &[Formatted]) -> Formatted {
+        self.call(&Latex::operator_name(name), args)
+    }
+
+    fn let_in(&self, name: String, value: Formatted, body: Formatted) -> Formatted {
+        let value = self.operand(&value, value.prec == PREC_LET);
+        Formatted::new(format!("\\mathbf{{let}}\\ {} = {}\\ \\mathbf{{in}}\\ {}", name, value, body.text),
+            PREC_LET)
+    }
+
+    fn where_clause(&self, body: String, bindings: &[(String, String)]) -> String {
+        if bindings.is_empty() {
+            return body;
+        }
+        let mut out = format!("\\begin{{aligned}}\n&{} \\\\\n\\text{{where}}\\quad ", body);
+        let lines: Vec<String> = bindings.iter()
+            .map(|(name, value)| format!("{} &= {}", name, value))
+            .collect();
+        out.push_str(&lines.join(" \\\\\n"));
+        out.push_str("\n\\end{aligned}");
+        out
+    }
+}
+
+struct Printer<'a, S, T, BOP, TOP>
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>,
+{
+    syntax: S,
+    program: &'a Program<T, BOP, TOP>,
+    style: LetStyle,
+    position: usize,
+    lets: usize,
+    bindings: Vec<(String, String)>,
+}
+
+impl<'a, S, T, BOP, TOP> Printer<'a, S, T, BOP, TOP>
+    where S: Syntax,
+          T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    fn expr(&mut self) -> Formatted {
+        let node = self.program.nodes[self.position];
+        self.position += 1;
+        match node {
+            Node::Input(index) => Formatted::new(self.syntax.name('a', index as usize), PREC_ATOM),
+            Node::Local(index) => Formatted::new(self.syntax.name('l', index as usize), PREC_ATOM),
+            Node::Constant(index) => Formatted::new(self.syntax.name('c', index as usize), PREC_ATOM),
+            Node::Literal(index) => {
+                self.syntax.literal(format!("{:?}", self.program.literals()[index as usize]))
+            }
+            Node::Lettuce => {
+                let value = self.expr();
+                let name = self.syntax.name('l', self.lets);
+                self.lets += 1;
+                match self.style {
+                    LetStyle::Inline => {
+                        let body = self.expr();
+                        self.syntax.let_in(name, value, body)
+                    }
+                    LetStyle::Where => {
+                        self.bindings.push((name, value.text));
+                        self.expr()
+                    }
+                }
+            }
+            Node::BinaryOp(op) => {
+                let a = self.expr();
+                let b = self.expr();
+                self.syntax.binary(op.repr(), a, b)
+            }
+            Node::TernaryOp(op) => {
+                let a = self.expr();
+                let b = self.expr();
+                let c = self.expr();
+                self.syntax.ternary(op.repr(), a, b, c)
+            }
+            Node::Call(index, arity) => {
+                let args: Vec<Formatted> = (0..arity).map(|_| self.expr()).collect();
+                self.syntax.function(&self.program.functions()[index as usize].name, &args)
+            }
+        }
+    }
+}
+
+fn print<S, T, BOP, TOP>(syntax: S, program: &Program<T, BOP, TOP>, style: LetStyle) -> String
+    where S: Syntax,
+          T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    let mut printer = Printer { syntax, program, style, position: 0, lets: 0, bindings: Vec::new() };
+    let outputs: Vec<String> = (0..program.output_count()).map(|_| printer.expr().text).collect();
+    let body = if outputs.len() == 1 {
+        outputs[0].clone()
+    } else {
+        printer.syntax.group(&outputs.join(", "))
+    };
+    printer.syntax.where_clause(body, &printer.bindings)
+}
+
+/// Inputs are `a0, a1, ...`, constants `c0, ...`, locals `l0, ...` and
+/// literals are written out. Calls are written by function name only, so
+/// the text of a program with functions does not parse back.
+pub fn to_infix<T, BOP, TOP>(program: &Program<T, BOP, TOP>, style: LetStyle) -> String
+    where T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    print(Plain, program, style)
+}
+
+/// Math-mode LaTeX, names as in `to_infix` with subscripts.
+pub fn to_latex<T, BOP, TOP>(program: &Program<T, BOP, TOP>, style: LetStyle) -> String
+    where T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>,
+{
+    print(Latex, program, style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_op::{BinaryOpF64, BinaryOpI32};
+    use crate::ternary_op::TernaryOpF64;
+    use crate::program::{Function, ProgramF64, ProgramI32};
+    use crate::corpus;
+
+    #[test]
+    fn sin_infix() {
+        let sin = corpus::sin();
+        assert_eq!(to_infix(&sin, LetStyle::Where), "\
+mul_add(l3, mul_add(l0, l2, c0), a0)
+where
+    l0 = a0 * a0
+    l1 = l0 * l0
+    l2 = mul_add(l0 * l1, mul_add(l0, c5, c4), mul_add(l0, mul_add(l0, c3, c2), c1))
+    l3 = l0 * a0");
+        assert_eq!(to_infix(&sin, LetStyle::Inline),
+            "let l0 = a0 * a0 in let l1 = l0 * l0 in \
+            let l2 = mul_add(l0 * l1, mul_add(l0, c5, c4), mul_add(l0, mul_add(l0, c3, c2), c1)) in \
+            let l3 = l0 * a0 in mul_add(l3, mul_add(l0, l2, c0), a0)");
+    }
+
+    #[test]
+    fn sin_latex() {
+        let sin = corpus::sin();
+        assert_eq!(to_latex(&sin, LetStyle::Where), "\
+\\begin{aligned}
+&l_{3} \\cdot \\left(l_{0} \\cdot l_{2} + c_{0}\\right) + a_{0} \\\\
+\\text{where}\\quad l_{0} &= a_{0} \\cdot a_{0} \\\\
+l_{1} &= l_{0} \\cdot l_{0} \\\\
+l_{2} &= l_{0} \\cdot l_{1} \\cdot \\left(l_{0} \\cdot c_{5} + c_{4}\\right) + \
+\\left(l_{0} \\cdot \\left(l_{0} \\cdot c_{3} + c_{2}\\right) + c_{1}\\right) \\\\
+l_{3} &= l_{0} \\cdot a_{0}
+\\end{aligned}");
+        assert!(to_latex(&sin, LetStyle::Inline).starts_with(
+            "\\mathbf{let}\\ l_{0} = a_{0} \\cdot a_{0}\\ \\mathbf{in}\\ \\mathbf{let}\\ l_{1} ="));
+    }
+
+    #[test]
+    fn multiple_outputs() {
+        use Node::*;
+        use BinaryOpF64::*;
+        let program = ProgramF64::multi(vec![
+            Lettuce, BinaryOp(Mul), Input(0), Input(0), BinaryOp(Add), Local(0), Input(1),
+            BinaryOp(Sub), Local(0), Input(1),
+        ], Vec::new()).unwrap();
+        assert_eq!(to_infix(&program, LetStyle::Where), "(l0 + a1, l0 - a1)\nwhere\n    l0 = a0 * a0");
+        assert_eq!(to_infix(&program, LetStyle::Inline), "(let l0 = a0 * a0 in l0 + a1, l0 - a1)");
+        assert_eq!(to_latex(&program, LetStyle::Inline),
+            "\\left(\\mathbf{let}\\ l_{0} = a_{0} \\cdot a_{0}\\ \\mathbf{in}\\ l_{0} + a_{1}, \
+            l_{0} - a_{1}\\right)");
+    }
+
+    #[test]
+    fn calls() {
+        use Node::*;
+        use BinaryOpF64::*;
+        let hypot = Function { name: "hypot_sq".to_string(), arity: 2, nodes: vec![
+            BinaryOp(Add), BinaryOp(Mul), Input(0), Input(0), BinaryOp(Mul), Input(1), Input(1),
+        ] };
+        let program = ProgramF64::with_functions(vec![
+            BinaryOp(Mul), Call(0, 2), Input(0), BinaryOp(Add), Input(1), Input(1), Input(0),
+        ], Vec::new(), vec![hypot]).unwrap();
+        assert_eq!(to_infix(&program, LetStyle::Inline), "hypot_sq(a0, a1 + a1) * a0");
+        assert_eq!(to_latex(&program, LetStyle::Inline),
+            "\\operatorname{hypot\\_sq}\\left(a_{0}, a_{1} + a_{1}\\right) \\cdot a_{0}");
+    }
+
+    #[test]
+    fn minimal_parens() {
+        use Node::*;
+        use BinaryOpF64::*;
+        let program = ProgramF64::with_literals(vec![
+            BinaryOp(Sub),
+            BinaryOp(Sub), Input(0), BinaryOp(Sub), Input(1), Input(2),
+            BinaryOp(Div),
+            BinaryOp(Pow), BinaryOp(Add), Input(0), Literal(0), Literal(1),
+            BinaryOp(Mul), Input(1), Literal(2),
+        ], vec![1.0, 2.0, -1e-20]).unwrap();
+        assert_eq!(to_infix(&program, LetStyle::Inline),
+            "a0 - (a1 - a2) - pow(a0 + 1.0, 2.0) / (a1 * (-1e-20))");
+        assert_eq!(to_latex(&program, LetStyle::Inline),
+            "a_{0} - \\left(a_{1} - a_{2}\\right) - \
+            \\frac{\\left(a_{0} + 1.0\\right)^{2.0}}{a_{1} \\cdot \\left(-1 \\times 10^{-20}\\right)}");
+
+        let nested = ProgramF64::new(vec![
+            BinaryOp(Mul), Lettuce, Input(0), Local(0),
+            TernaryOp(TernaryOpF64::IfLt), Input(0), Input(1), Input(2),
+        ]).unwrap();
+        assert_eq!(to_infix(&nested, LetStyle::Inline), "(let l0 = a0 in l0) * if_lt(a0, a1, a2)");
+        assert_eq!(to_latex(&nested, LetStyle::Where),
+            "\\begin{aligned}\n&l_{0} \\cdot \\begin{cases} a_{1} & \\text{if } a_{0} < 0 \\\\ \
+            a_{2} & \\text{otherwise} \\end{cases} \\\\\n\\text{where}\\quad l_{0} &= a_{0}\n\\end{aligned}");
+    }
+
+    #[test]
+    fn integer_precedence() {
+        use Node::*;
+        use BinaryOpI32::*;
+        let program = ProgramI32::with_literals(vec![
+            BinaryOp(Lt),
+            BinaryOp(Shl), BinaryOp(Add), Input(0), Input(1), Literal(0),
+            BinaryOp(Xor), BinaryOp(And), Input(0), Input(1), BinaryOp(CheckedAdd), Input(1), Literal(1),
+        ], vec![3, -4]).unwrap();
+        assert_eq!(to_infix(&program, LetStyle::Inline), "a0 + a1 << 3 < a0 & a1 ^ checked_add(a1, -4)");
+        assert_eq!(to_latex(&program, LetStyle::Inline),
+            "\\left[a_{0} + a_{1} \\ll 3 < a_{0} \\mathbin{\\&} a_{1} \\oplus \
+            \\operatorname{checked\\_add}\\left(a_{1}, -4\\right)\\right]");
+    }
+}
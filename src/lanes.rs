@@ -0,0 +1,354 @@
+//! Evaluation of `LANES` inputs at once. With the `simd` feature a lane
+//! vector is a `std::simd::Simd` (nightly only) and the float ops map to
+//! vector instructions; without it a lane vector is a plain array and
+//! every op runs the scalar `run` lane by lane. Both give the same bits
+//! as `Program::eval`.
+
+use core::fmt;
+
+use crate::binary_op::*;
+use crate::ternary_op::*;
+use crate::program::{ErrorKind, Node, Program, ProgramError};
+
+pub const LANES: usize = 8;
+
+#[cfg(feature = "simd")]
+pub type Lanes<T> = std::simd::Simd<T, LANES>;
+
+#[cfg(not(feature = "simd"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Lanes<T>([T; LANES]);
+
+#[cfg(not(feature = "simd"))]
+impl<T: Copy> Lanes<T> {
+    pub fn splat(value: T) -> Self {
+        Self([value; LANES])
+    }
+
+    pub fn from_array(array: [T; LANES]) -> Self {
+        Self(array)
+    }
+
+    pub fn to_array(self) -> [T; LANES] {
+        self.0
+    }
+}
+
+/// Scalar types that can fill a lane vector.
+#[cfg(feature = "simd")]
+pub trait Element: Copy + std::simd::SimdElement {}
+#[cfg(not(feature = "simd"))]
+pub trait Element: Copy {}
+
+impl Element for f32 {}
+impl Element for f64 {}
+impl Element for i32 {}
+impl Element for u32 {}
+impl Element for i64 {}
+impl Element for u64 {}
+
+fn map_binary<T: Element, BOP: BinaryOp<T> + ?Sized>(op: &BOP, lhs: Lanes<T>, rhs: Lanes<T>) -> Lanes<T> {
+    let (lhs, rhs) = (lhs.to_array(), rhs.to_array());
+    Lanes::from_array(core::array::from_fn(|lane| op.run(lhs[lane], rhs[lane])))
+}
+
+fn map_ternary<T: Element, TOP: TernaryOp<T> + ?Sized>(op: &TOP, a: Lanes<T>, b: Lanes<T>, c: Lanes<T>) -> Lanes<T> {
+    let (a, b, c) = (a.to_array(), b.to_array(), c.to_array());
+    Lanes::from_array(core::array::from_fn(|lane| op.run(a[lane], b[lane], c[lane])))
+}
+
+pub trait BinaryLaneOp<T: Element>: BinaryOp<T> {
+    fn run_lanes(&self, lhs: Lanes<T>, rhs: Lanes<T>) -> Lanes<T> {
+        map_binary(self, lhs, rhs)
+    }
+}
+
+pub trait TernaryLaneOp<T: Element>: TernaryOp<T> {
+    fn run_lanes(&self, a: Lanes<T>, b: Lanes<T>, c: Lanes<T>) -> Lanes<T> {
+        map_ternary(self, a, b, c)
+    }
+}
+
+impl BinaryLaneOp<i32> for BinaryOpI32 {}
+impl BinaryLaneOp<u32> for BinaryOpU32 {}
+impl BinaryLaneOp<i64> for BinaryOpI64 {}
+impl BinaryLaneOp<u64> for BinaryOpU64 {}
+impl TernaryLaneOp<i32> for TernaryOpI32 {}
+impl TernaryLaneOp<u32> for TernaryOpU32 {}
+impl TernaryLaneOp<i64> for TernaryOpI64 {}
+impl TernaryLaneOp<u64> for TernaryOpU64 {}
+
+#[cfg(not(feature = "simd"))]
+mod float_ops {
+    use super::*;
+
+    impl BinaryLaneOp<f32> for BinaryOpF32 {}
+    impl BinaryLaneOp<f64> for BinaryOpF64 {}
+    impl TernaryLaneOp<f32> for TernaryOpF32 {}
+    impl TernaryLaneOp<f64> for TernaryOpF64 {}
+}
+
+#[cfg(feature = "simd")]
+mod float_ops {
+    use super::*;
+    use std::simd::{Select, StdFloat};
+    use std::simd::cmp::{SimdPartialEq, SimdPartialOrd};
+    use std::simd::num::SimdFloat;
+
+    macro_rules! float_lane_ops {
+        ($t:ty, $bop:ident, $top:ident) => {
+            impl BinaryLaneOp<$t> for $bop {
+                fn run_lanes(&self, lhs: Lanes<$t>, rhs: Lanes<$t>) -> Lanes<$t> {
+                    match self {
+                        $bop::Add => lhs + rhs,
+                        $bop::Sub => lhs - rhs,
+                        $bop::Mul => lhs * rhs,
+                        $bop::Div => lhs / rhs,
+                        $bop::Min => lhs.simd_min(rhs),
+                        $bop::Max => lhs.simd_max(rhs),
+                        $bop::Pow | $bop::Hypot => map_binary(self, lhs, rhs),
+                    }
+                }
+            }
+
+            impl TernaryLaneOp<$t> for $top {
+                fn run_lanes(&self, a: Lanes<$t>, b: Lanes<$t>, c: Lanes<$t>) -> Lanes<$t> {
+                    let zero = Lanes::splat(0.0);
+                    match self {
+                        $top::MulAdd => a.mul_add(b, c),
+                        $top::Clamp => {
+                            let a = a.simd_lt(b).select(b, a);
+                            a.simd_gt(c).select(c, a)
+                        }
+                        $top::Select => a.simd_ne(zero).select(b, c),
+                        $top::IfLt => a.simd_lt(zero).select(b, c),
+                        $top::IfGe => a.simd_ge(zero).select(b, c),
+                    }
+                }
+            }
+        };
+    }
+
+    float_lane_ops!(f32, BinaryOpF32, TernaryOpF32);
+    float_lane_ops!(f64, BinaryOpF64, TernaryOpF64);
+}
+
+/// A program prepared for lane evaluation: constants and literals are
+/// splatted once, and fallible ops are rejected up front because one
+/// failing lane has no way to report itself. Constants may still be unset,
+/// evaluation fails with `TooFewConstants` until `set_constants`.
+pub struct LaneProgram<T, BOP, TOP>
+    where T: Element,
+          BOP: BinaryLaneOp<T> + Copy,
+          TOP: TernaryLaneOp<T> + Copy
+{
+    program: Program<T, BOP, TOP>,
+    constants: Vec<Lanes<T>>,
+    literals: Vec<Lanes<T>>,
+    locals: Vec<Lanes<T>>,
+    position: usize,
+}
+
+impl<T, BOP, TOP> Program<T, BOP, TOP>
+    where T: Element + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryLaneOp<T>,
+          TOP: Copy + PartialEq + TernaryLaneOp<T>
+{
+    /// Calls are inlined first, see `inline_calls`. A program that fails
+    /// is handed back with the error.
+    // both variants hold the program, boxing the error would not shrink
+    // the result
+    #[allow(clippy::result_large_err)]
+    pub fn lanes(self) -> Result<LaneProgram<T, BOP, TOP>, (Self, ProgramError)> {
+        if !self.functions.is_empty() {
+            return match self.inline_calls() {
+                Ok(inlined) => inlined.lanes().map_err(|(_, error)| (self, error)),
+                Err(error) => Err((self, error)),
+            };
+        }
+        for (position, node) in self.nodes.iter().enumerate() {
+            if let Node::BinaryOp(op) = node {
+                if op.is_fallible() {
+                    return Err((self, ProgramError::new(ErrorKind::FallibleOp).at(position)));
+                }
+            }
+        }
+        let literals = self.literals.iter().map(|&literal| Lanes::splat(literal)).collect();
+        let constants = self.constants.iter().map(|&constant| Lanes::splat(constant)).collect();
+        Ok(LaneProgram { program: self, constants, literals, locals: Vec::new(), position: 0 })
+    }
+}
+
+impl<T, BOP, TOP> LaneProgram<T, BOP, TOP>
+    where T: Element,
+          BOP: Copy + BinaryLaneOp<T>,
+          TOP: Copy + TernaryLaneOp<T>
+{
+    pub fn program(&self) -> &Program<T, BOP, TOP> {
+        &self.program
+    }
+
+    pub fn into_inner(self) -> Program<T, BOP, TOP> {
+        self.program
+    }
+
+    pub fn set_constants(&mut self, constants: &[T]) -> Result<(), ProgramError> {
+        if constants.len() < self.program.info.constant_count {
+            return Err(ProgramError::new(ErrorKind::TooFewConstants)
+                .counts(self.program.info.constant_count, constants.len()));
+        }
+        self.program.constants.clear();
+        self.program.constants.extend(constants.iter().copied());
+        self.program.info.unset_constants.clear();
+        self.constants.clear();
+        self.constants.extend(constants.iter().map(|&constant| Lanes::splat(constant)));
+        Ok(())
+    }
+
+    fn start(&self, input_count: usize) -> Result<(), ProgramError> {
+        if input_count < self.program.info.input_count {
+            return Err(ProgramError::new(ErrorKind::TooFewInputs)
+                .counts(self.program.info.input_count, input_count));
+        }
+        if !self.program.info.unset_constants.is_empty() {
+            return Err(ProgramError::new(ErrorKind::TooFewConstants)
+                .counts(self.program.info.constant_count, self.program.constants.len()));
+        }
+        Ok(())
+    }
+
+    /// `inputs[i]` holds input `i` of every lane. Evaluates the first
+    /// output, like `Program::eval`.
+    pub fn eval(&mut self, inputs: &[Lanes<T>]) -> Result<Lanes<T>, ProgramError> {
+        self.start(inputs.len())?;
+        self.position = 0;
+        self.locals.clear();
+        self.locals.reserve(self.program.info.local_count);
+        Ok(self.eval_inner(inputs))
+    }
+
+    /// Evaluates every row of a table stored by column: `columns[i][row]`
+    /// is input `i` of that row. All columns must have the same length;
+    /// the first one that differs gives `ColumnLength`.
+    pub fn eval_columns(&mut self, columns: &[&[T]]) -> Result<Vec<T>, ProgramError> {
+        self.start(columns.len())?;
+        let rows = columns.first().map_or(0, |column| column.len());
+        if let Some(index) = columns.iter().position(|column| column.len() != rows) {
+            return Err(ProgramError::new(ErrorKind::ColumnLength)
+                .index(index)
+                .counts(rows, columns[index].len()));
+        }
+        let mut out = Vec::with_capacity(rows);
+        let mut inputs = Vec::with_capacity(columns.len());
+        for start in (0..rows).step_by(LANES) {
+            let end = rows.min(start + LANES);
+            inputs.clear();
+            // the last chunk repeats its first row in the unused lanes
+            inputs.extend(columns.iter().map(|column| {
+                Lanes::from_array(core::array::from_fn(|lane| {
+                    column[if start + lane < end { start + lane } else { start }]
+                }))
+            }));
+            let values = self.eval(&inputs)?.to_array();
+            out.extend_from_slice(&values[..end - start]);
+        }
+        Ok(out)
+    }
+
+    fn eval_inner(&mut self, inputs: &[Lanes<T>]) -> Lanes<T> {
+        let node = self.program.nodes[self.position];
+        self.position += 1;
+
+        match node {
+            Node::Input(index) => inputs[index as usize],
+            Node::Local(index) => self.locals[index as usize],
+            Node::Constant(index) => self.constants[index as usize],
+            Node::Literal(index) => self.literals[index as usize],
+            Node::Lettuce => {
+                let value = self.eval_inner(inputs);
+                self.locals.push(value);
+                self.eval_inner(inputs)
+            }
+            Node::BinaryOp(op) => {
+                let lhs = self.eval_inner(inputs);
+                let rhs = self.eval_inner(inputs);
+                op.run_lanes(lhs, rhs)
+            }
+            Node::TernaryOp(op) => {
+                let a = self.eval_inner(inputs);
+                let b = self.eval_inner(inputs);
+                let c = self.eval_inner(inputs);
+                op.run_lanes(a, b, c)
+            }
+            Node::Call(..) => unreachable!("`lanes` inlines calls"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::{Node::*, ProgramF32, ProgramF64, ProgramI32};
+    use crate::rng::Rng;
+
+    #[test]
+    fn sin_matches_eval() {
+        let mut rng = Rng::new(7);
+        let xs: Vec<f64> = (0..1000).map(|_| rng.next_f64() * 2.0 - 1.0).collect();
+        let mut lanes = crate::corpus::sin().lanes().unwrap();
+        let values = lanes.eval_columns(&[&xs]).unwrap();
+        let mut sin = lanes.into_inner();
+        for (x, value) in xs.iter().zip(values) {
+            assert_eq!(value.to_bits(), sin.eval(&[*x]).unwrap().to_bits(), "x = {}", x);
+        }
+    }
+
+    #[test]
+    fn conditionals() {
+        use BinaryOpF32::*;
+        let inputs = [0.0, -0.0, 1.0, -1.0, f32::NAN, f32::INFINITY, 0.5, -2.5, 3.0];
+        for op in TernaryOpF32::ALL.iter() {
+            let mut lanes = ProgramF32::with_literals(vec![
+                TernaryOp(*op), Input(0),
+                BinaryOp(Min), Input(1), Input(0),
+                BinaryOp(Pow), Literal(0), Input(1),
+            ], vec![2.0]).unwrap().lanes().unwrap();
+            let ys: Vec<f32> = inputs.iter().rev().copied().collect();
+            let values = lanes.eval_columns(&[&inputs, &ys]).unwrap();
+            let mut program = lanes.into_inner();
+            for ((x, y), value) in inputs.iter().zip(&ys).zip(values) {
+                assert_eq!(value.to_bits(), program.eval(&[*x, *y]).unwrap().to_bits(), "{:?} {:?} {:?}", op, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn integers_and_errors() {
+        use BinaryOpI32::*;
+        let program = ProgramI32::new(vec![
+            TernaryOp(TernaryOpI32::IfLt), Input(0), BinaryOp(Shl), Input(1), Input(0), Constant(0),
+        ]).unwrap();
+        let xs = [3, -1, 0, i32::MIN, 40];
+        let ys = [1, 2, -3, 4, 5];
+        // constants may be set after the program is prepared
+        let mut lanes = program.lanes().unwrap();
+        assert_eq!(lanes.eval_columns(&[&xs, &ys]).unwrap_err().kind, ErrorKind::TooFewConstants);
+        lanes.set_constants(&[-7]).unwrap();
+        let values = lanes.eval_columns(&[&xs, &ys]).unwrap();
+        let mut program = lanes.into_inner();
+        for ((x, y), value) in xs.iter().zip(&ys).zip(values) {
+            assert_eq!(value, program.eval(&[*x, *y]).unwrap());
+        }
+
+        let checked = ProgramI32::new(vec![BinaryOp(CheckedAdd), Input(0), Input(1)]).unwrap();
+        let (mut checked, error) = checked.lanes().err().unwrap();
+        assert_eq!(error.kind, ErrorKind::FallibleOp);
+        assert_eq!(checked.eval(&[i32::MAX, 1]).unwrap_err().kind, ErrorKind::ArithmeticError);
+
+        let mut lanes = ProgramF64::new(vec![BinaryOp(BinaryOpF64::Add), Input(0), Input(1)]).unwrap()
+            .lanes().unwrap();
+        let err = lanes.eval_columns(&[&[1.0, 2.0], &[1.0]]).unwrap_err();
+        assert_eq!((err.kind, err.index, err.expected, err.actual), (ErrorKind::ColumnLength, Some(1), Some(2), Some(1)));
+        assert_eq!(lanes.eval_columns(&[&[], &[]]).unwrap(), Vec::<f64>::new());
+    }
+}
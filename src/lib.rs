@@ -0,0 +1,40 @@
+//! Expression trees stored as prefix node vectors, with a validating
+//! evaluator, exporters to Rust, C, Graphviz and infix notation, and a
+//! parser for the infix form.
+
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+
+pub mod binary_op;
+pub mod ternary_op;
+pub mod program;
+pub mod verified;
+pub mod lanes;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod rng;
+pub mod accuracy;
+pub mod double_double;
+pub mod codec;
+pub mod emit_rust;
+pub mod emit_c;
+pub mod dot;
+pub mod infix;
+pub mod parse;
+pub mod gp;
+pub mod corpus;
+pub mod symbolic;
+pub mod repl;
+#[cfg(feature = "jit")]
+pub mod compile;
+#[cfg(test)]
+mod differential;
+
+pub use binary_op::{BinaryOp, OpList, BinaryOpF32, BinaryOpF64, BinaryOpI32, BinaryOpU32, BinaryOpI64, BinaryOpU64};
+pub use ternary_op::{TernaryOp, TernaryOpF32, TernaryOpF64, TernaryOpI32, TernaryOpU32, TernaryOpI64, TernaryOpU64};
+pub use program::{
+    Bits, ErrorKind, Function, Index, Node, Program, ProgramError, ProgramInfo,
+    NodeF32, NodeF64, NodeI32, NodeU32, NodeI64, NodeU64,
+    ProgramF32, ProgramF64, ProgramI32, ProgramU32, ProgramI64, ProgramU64,
+};
+pub use verified::VerifiedProgram;
+pub use lanes::{LaneProgram, Lanes, LANES};
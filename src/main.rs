@@ -1,347 +1,426 @@
-mod binary_op;
-mod ternary_op;
-mod program;
-// mod compile;
-
-use binary_op::{BinaryOpF64};
-use ternary_op::{TernaryOpF64};
-use program::{Node, ProgramF64};
-// use compile::{compile, to_fn};
-use core_simd::{SimdF64, LanesAtMost32};
-
-fn k_sin(x: f64) -> f64 {
-    const S0: f64 = -1.66666666666666324348e-01; /* 0xBFC55555, 0x55555549 */
-    const S1: f64 =  8.33333333332248946124e-03; /* 0x3F811111, 0x1110F8A6 */
-    const S2: f64 = -1.98412698298579493134e-04; /* 0xBF2A01A0, 0x19C161D5 */
-    const S3: f64 =  2.75573137070700676789e-06; /* 0x3EC71DE3, 0x57B1FE7D */
-    const S4: f64 = -2.50507602534068634195e-08; /* 0xBE5AE5E6, 0x8A2B9CEB */
-    const S5: f64 =  1.58969099521155010221e-10; /* 0x3DE5D93A, 0x5ACFD57C */
-    let z = x*x;
-    let w = z*z;
-    let r = S1 + z*(S2 + z*S3 + z*w*(S4 + z*S5));
-    let v = z*x;
-    x + v*(S0 + z*r)
+//! `beaver-solve`: evaluate, convert, benchmark and fit `f64` programs,
+//! or work with them interactively.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{BufRead, Read, Write};
+use std::time::Instant;
+
+use beaver_solver::ProgramF64;
+use beaver_solver::codec;
+use beaver_solver::dot::{to_dot, DotOptions};
+use beaver_solver::emit_c::emit_c;
+use beaver_solver::emit_rust::emit_rust;
+use beaver_solver::gp::{Dataset, Gp, GpConfig};
+use beaver_solver::infix::{to_infix, to_latex, LetStyle};
+use beaver_solver::parse::{parse_infix, parse_sexpr, Symbols};
+use beaver_solver::program::MAX_CALL_DEPTH;
+use beaver_solver::repl::Session;
+use beaver_solver::{BinaryOpF64, OpList, TernaryOp, BinaryOp, TernaryOpF64};
+
+const USAGE: &str = "\
+usage:
+    beaver-solve eval PROGRAM [--csv FILE | INPUT...]
+    beaver-solve convert PROGRAM --to FORMAT [-o FILE] [--name NAME] [--where]
+    beaver-solve bench PROGRAM [--count N]
+    beaver-solve fit FILE [--generations N] [--population N] [--seed N] [--ops OPS]
+                          [--adfs N,N,..]
+    beaver-solve repl
+
+PROGRAM is a file, `-` for stdin, or `-e TEXT`. Its format is taken from
+`--from sexpr|infix|json|binary`, or guessed: binary files start with the
+`BVRP` magic, JSON with `{`, and text that is not an s-expression is read
+as infix.
+
+    --constants, -c V,V,..  constant values, replacing any stored ones
+    --inputs NAME,NAME,..   input names for infix text (defaults to a CSV
+                            header when there is one)
+    --to FORMAT             sexpr, infix, latex, json, binary, rust, c, dot
+    --where                 write lets as `where` bindings in infix and
+                            LaTeX, the default for multi-output programs
+
+A CSV file has one sample per line and may start with a header. For `fit`
+the last column is the target and the others are inputs; `--ops` lists
+the op names to build programs from, e.g. `+,-,*,/,mul_add`, and `--adfs`
+the arities, 1 to 255, of up to 32 functions evolved alongside the program.
+
+`repl` reads commands from stdin; `help` lists them.";
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Options that take no value.
+const SWITCHES: &[&str] = &["--where"];
+
+struct Args {
+    positional: Vec<String>,
+    options: HashMap<String, String>,
 }
 
-fn k_sin_simd<const LANES: usize>(x: SimdF64<LANES>) -> SimdF64<LANES>
-    where SimdF64<LANES>: LanesAtMost32
-{
-    const S0: f64 = -1.66666666666666324348e-01; /* 0xBFC55555, 0x55555549 */
-    const S1: f64 =  8.33333333332248946124e-03; /* 0x3F811111, 0x1110F8A6 */
-    const S2: f64 = -1.98412698298579493134e-04; /* 0xBF2A01A0, 0x19C161D5 */
-    const S3: f64 =  2.75573137070700676789e-06; /* 0x3EC71DE3, 0x57B1FE7D */
-    const S4: f64 = -2.50507602534068634195e-08; /* 0xBE5AE5E6, 0x8A2B9CEB */
-    const S5: f64 =  1.58969099521155010221e-10; /* 0x3DE5D93A, 0x5ACFD57C */
-    let z = x*x;
-    let w = z*z;
-    let r = SimdF64::splat(S1) + z*(SimdF64::splat(S2) + z*SimdF64::splat(S3) + z*w*(SimdF64::splat(S4) + z*SimdF64::splat(S5)));
-    let v = z*x;
-    x + v*(SimdF64::splat(S0) + z*r)
+impl Args {
+    fn parse(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            // negative inputs, `-inf` and `-NaN` included, are not options
+            if arg == "-" || arg.parse::<f64>().is_ok() || !arg.starts_with('-') {
+                positional.push(arg);
+            } else if SWITCHES.contains(&arg.as_str()) {
+                options.insert(arg, String::new());
+            } else {
+                let value = args.next().ok_or_else(|| format!("{} needs a value", arg))?;
+                options.insert(arg, value);
+            }
+        }
+        Ok(Self { positional, options })
+    }
+
+    fn get(&self, names: &[&str]) -> Option<&str> {
+        names.iter().find_map(|name| self.options.get(*name)).map(String::as_str)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    fn number<N: std::str::FromStr>(&self, name: &str, default: N) -> Result<N> {
+        match self.get(&[name]) {
+            Some(text) => text.parse().map_err(|_| format!("{} expects a number, got `{}`", name, text).into()),
+            None => Ok(default),
+        }
+    }
 }
-/*
-#[derive(Clone, Copy)]
-enum Instr {
-    Set(u8, u8),
-    Add(u8, u8, u8),
-    Mul(u8, u8, u8),
+
+fn read_source(path: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if path == "-" {
+        std::io::stdin().read_to_end(&mut bytes)?;
+    } else {
+        bytes = std::fs::read(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+    }
+    Ok(bytes)
 }
 
-struct CProgram {
-    instrs: Vec<Instr>,
-    memory: Vec<f64>,
+fn parse_list(text: &str) -> Result<Vec<f64>> {
+    text.split(',')
+        .map(|value| value.trim().parse::<f64>().map_err(|_| format!("invalid number `{}`", value.trim()).into()))
+        .collect()
 }
 
-impl CProgram {
-    fn run(&mut self) {
-        for instr in self.instrs.iter().copied() {
-            match instr {
-                Instr::Set(dst, src) => {
-                    self.memory[dst as usize] = self.memory[src as usize];
-                }
-                Instr::Add(dst, lhs, rhs) => {
-                    self.memory[dst as usize] = self.memory[lhs as usize] + self.memory[rhs as usize];
-                }
-                Instr::Mul(dst, lhs, rhs) => {
-                    self.memory[dst as usize] = self.memory[lhs as usize] * self.memory[rhs as usize];
+/// A CSV table: optional header and rows of numbers.
+struct Csv {
+    header: Option<Vec<String>>,
+    rows: Vec<Vec<f64>>,
+}
+
+fn read_csv(path: &str) -> Result<Csv> {
+    let text = String::from_utf8(read_source(path)?)?;
+    let mut header = None;
+    let mut rows = Vec::new();
+    for (line_number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_list(line) {
+            Ok(row) => {
+                if let Some(first) = rows.first().map(Vec::len) {
+                    if row.len() != first {
+                        return Err(format!("{}:{}: expected {} columns, got {}", path, line_number + 1, first, row.len()).into());
+                    }
                 }
+                rows.push(row);
+            }
+            Err(_) if rows.is_empty() && header.is_none() => {
+                header = Some(line.split(',').map(|name| name.trim().to_string()).collect());
             }
+            Err(err) => return Err(format!("{}:{}: {}", path, line_number + 1, err).into()),
         }
     }
+    Ok(Csv { header, rows })
 }
 
-#[derive(Clone, Copy)]
-struct TInstr {
-    op_byte: u8,
-    a: u8,
-    b: u8,
-    c: u8,
-}
-impl TInstr {
-    fn new(op_byte: u8, a: u8, b: u8, c: u8) -> Self {
-        Self { op_byte, a, b, c }
+fn load_program(args: &Args, input_names: Option<&[String]>) -> Result<ProgramF64> {
+    let bytes = match (args.get(&["-e"]), args.positional.first()) {
+        (Some(text), _) => text.as_bytes().to_vec(),
+        (None, Some(path)) => read_source(path)?,
+        (None, None) => return Err("no program given".into()),
+    };
+    let mut symbols = Symbols::default();
+    if let Some(names) = args.get(&["--inputs"]) {
+        symbols.inputs = names.split(',').map(|name| name.trim().to_string()).collect();
+    } else if let Some(names) = input_names {
+        symbols.inputs = names.to_vec();
     }
+
+    let format = match args.get(&["--from"]) {
+        Some(format) => format,
+        None if bytes.starts_with(b"BVRP") => "binary",
+        None if bytes.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'{') => "json",
+        None => "text",
+    };
+    let mut program = match format {
+        "binary" => codec::decode(&bytes)?,
+        "json" => from_json(&bytes)?,
+        "sexpr" => parse_sexpr(std::str::from_utf8(&bytes)?, &symbols)?,
+        "infix" => parse_infix(std::str::from_utf8(&bytes)?, &symbols)?,
+        "text" => {
+            let text = std::str::from_utf8(&bytes)?;
+            match parse_sexpr(text, &symbols) {
+                Ok(program) => program,
+                Err(_) => parse_infix(text, &symbols)?,
+            }
+        }
+        other => return Err(format!("unknown input format `{}`", other).into()),
+    };
+    if let Some(constants) = args.get(&["--constants", "-c"]) {
+        program.set_constants(&parse_list(constants)?)?;
+    }
+    Ok(program)
 }
 
-struct TProgram {
-    instrs: Vec<TInstr>,
-    memory: Vec<f64>,
+#[cfg(feature = "json")]
+fn from_json(bytes: &[u8]) -> Result<ProgramF64> {
+    Ok(serde_json::from_slice(bytes)?)
 }
 
-fn op_add(vm_state: &mut TProgram, instr: TInstr, pc: usize) {
-    let TInstr { op_byte: _, a, b, c } = instr;
-    vm_state.memory[a as usize] = vm_state.memory[b as usize] + vm_state.memory[c as usize];
-    let instr = vm_state.instrs[pc];
-    OP_TABLE[instr.op_byte as usize](vm_state, instr, pc + 1)
+#[cfg(not(feature = "json"))]
+fn from_json(_bytes: &[u8]) -> Result<ProgramF64> {
+    Err("JSON needs the `json` feature".into())
 }
-fn op_mul(vm_state: &mut TProgram, instr: TInstr, pc: usize) {
-    let TInstr { op_byte: _, a, b, c } = instr;
-    vm_state.memory[a as usize] = vm_state.memory[b as usize] * vm_state.memory[c as usize];
-    let instr = vm_state.instrs[pc];
-    OP_TABLE[instr.op_byte as usize](vm_state, instr, pc + 1)
+
+#[cfg(feature = "json")]
+fn to_json(program: &ProgramF64) -> Result<Vec<u8>> {
+    let mut json = serde_json::to_vec_pretty(program)?;
+    json.push(b'\n');
+    Ok(json)
 }
-fn op_set(vm_state: &mut TProgram, instr: TInstr, pc: usize) {
-    let TInstr { op_byte: _, a, b, c: _ } = instr;
-    vm_state.memory[a as usize] = vm_state.memory[b as usize];
-    let instr = vm_state.instrs[pc];
-    OP_TABLE[instr.op_byte as usize](vm_state, instr, pc + 1)
+
+#[cfg(not(feature = "json"))]
+fn to_json(_program: &ProgramF64) -> Result<Vec<u8>> {
+    Err("JSON needs the `json` feature".into())
 }
-fn op_return(_vm_state: &mut TProgram, _instr: TInstr, _pc: usize) {
+
+fn eval(args: &Args) -> Result<()> {
+    if let Some(path) = args.get(&["--csv"]) {
+        let csv = read_csv(path)?;
+        let mut program = load_program(args, csv.header.as_deref())?;
+        for row in &csv.rows {
+            match program.eval_multi(row) {
+                Ok(values) => println!("{}", outputs(values)),
+                Err(error) => return Err(program.explain(error).into()),
+            }
+        }
+        return Ok(());
+    }
+    let mut program = load_program(args, None)?;
+    let skip = if args.has("-e") { 0 } else { 1 };
+    let inputs = args.positional.iter().skip(skip)
+        .map(|value| value.parse::<f64>().map_err(|_| format!("invalid input `{}`", value)))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    match program.eval_multi(&inputs) {
+        Ok(values) => println!("{}", outputs(values)),
+        Err(error) => return Err(program.explain(error).into()),
+    }
+    Ok(())
 }
-const OP_TABLE: [fn(&mut TProgram, TInstr, usize); 4] = [op_set, op_add, op_mul, op_return];
 
-impl TProgram {
-    fn run(&mut self) {
-        let instr = self.instrs[0];
-        let op_byte = instr.op_byte;
-        OP_TABLE[op_byte as usize](self, instr, 1);
+/// Comma separated, so multi-output programs print one line per row.
+fn outputs(values: &[f64]) -> String {
+    values.iter().map(f64::to_string).collect::<Vec<_>>().join(", ")
+}
+
+fn convert(args: &Args) -> Result<()> {
+    let program = load_program(args, None)?;
+    let name = args.get(&["--name"]).unwrap_or("program");
+    let style = if args.has("--where") { LetStyle::Where } else { LetStyle::default_for(&program) };
+    let output = match args.get(&["--to"]).ok_or("missing --to")? {
+        "sexpr" => format!("{:?}\n", program).into_bytes(),
+        "infix" => format!("{}\n", to_infix(&program, style)).into_bytes(),
+        "latex" => format!("{}\n", to_latex(&program, style)).into_bytes(),
+        "json" => to_json(&program)?,
+        "binary" => codec::encode(&program),
+        "rust" => emit_rust(&program, name)?.into_bytes(),
+        "c" => emit_c(&program, name)?.into_bytes(),
+        "dot" => to_dot(&program, &DotOptions::default()).into_bytes(),
+        other => return Err(format!("unknown output format `{}`", other).into()),
+    };
+    match args.get(&["-o"]) {
+        Some(path) => std::fs::write(path, output).map_err(|err| format!("cannot write {}: {}", path, err))?,
+        None => std::io::stdout().write_all(&output)?,
     }
+    Ok(())
 }
-*/
 
-fn main() {
-    use BinaryOpF64::*;
-    use TernaryOpF64::*;
-    use Node::*;
-    let mut sin = ProgramF64::new(vec![
-        // let z = x*x
-        Lettuce,
-        BinaryOp(Mul), Input(0), Input(0),
-
-        Lettuce, // let w = z*z
-        BinaryOp(Mul), Local(0), Local(0),
-
-        Lettuce, // let r = S1 + z*(S2 + z*S3) + z*w*(S4 + z*S5)
-        TernaryOp(MulAdd),
-        BinaryOp(Mul),
-        Local(0),
-        Local(1),
-        TernaryOp(MulAdd),
-        Local(0),
-        Constant(5),
-        Constant(4),
-        TernaryOp(MulAdd),
-        Local(0),
-        TernaryOp(MulAdd),
-        Local(0),
-        Constant(3),
-        Constant(2),
-        Constant(1),
-
-        Lettuce, // let v = z*x
-        BinaryOp(Mul),
-        Local(0),
-        Input(0),
-
-        // x + v*(S0 + z*r)
-        TernaryOp(MulAdd),
-        Local(3),
-        TernaryOp(MulAdd),
-        Local(0),
-        Local(2),
-        Constant(0),
-        Input(0),
-    ]).expect("failed to validate program");
-
-    let count: usize = 1_000_000;
+fn bench(args: &Args) -> Result<()> {
+    let mut program = load_program(args, None)?;
+    let count: usize = args.number("--count", 1_000_000)?;
     let step: f64 = 1.0 / (count as f64);
+    let width = program.info().input_count;
+    println!("program: {:?}", program);
 
-    println!("sin: {:?}", sin);
-    let constants = &[
-        -1.66666666666666324348e-01, /* 0xBFC55555, 0x55555549 */
-        8.33333333332248946124e-03, /* 0x3F811111, 0x1110F8A6 */
-        -1.98412698298579493134e-04, /* 0xBF2A01A0, 0x19C161D5 */
-        2.75573137070700676789e-06, /* 0x3EC71DE3, 0x57B1FE7D */
-        -2.50507602534068634195e-08, /* 0xBE5AE5E6, 0x8A2B9CEB */
-        1.58969099521155010221e-10, /* 0x3DE5D93A, 0x5ACFD57C */
-    ];
-    sin.set_constants(constants).expect("cannot set constants");
-    use std::f64::consts::PI;
-
-    let start = std::time::Instant::now();
+    let start = Instant::now();
     let mut sum = 0.0;
+    let mut inputs = vec![0.0; width];
     let mut x = 0.0;
     for _ in 0..count {
-        let inputs = &[x];
-        sum += sin.eval(inputs).unwrap();
+        inputs.iter_mut().for_each(|input| *input = x);
+        sum += program.eval(&inputs).map_err(|error| program.explain(error))?;
         x += step;
     }
     let elapsed = start.elapsed();
     println!("vm_1 time:   {:?} v={}", elapsed, sum);
-    // println!("compiled:\n{}", compile(&sin.nodes).expect("cant compile"));
-
-    /*
-    // input=0, consts=1..6, locals=7..11
-    let instrs = vec![
-        Instr::Mul(7, 0, 0), // let z = x*x
-        Instr::Mul(8, 7, 7), // let w = z*z
-        // let r = S1 + z*(S2 + z*S3) + z*w*(S4 + z*S5)
-        Instr::Mul(9, 7, 6),
-        Instr::Add(9, 9, 5),
-        Instr::Mul(9, 9, 8),
-        Instr::Mul(9, 9, 7),
-        Instr::Mul(10, 7, 4),
-        Instr::Add(10, 10, 3),
-        Instr::Mul(10, 10, 7),
-        Instr::Add(10, 10, 2),
-        Instr::Add(9, 9, 10),
-        // let v = z*x;
-        Instr::Mul(10, 7, 0),
-        // x + v*(S0 + z*r)
-        Instr::Mul(11, 7, 9),
-        Instr::Add(11, 11, 1),
-        Instr::Mul(11, 11, 10),
-        Instr::Add(0, 0, 11),
-    ];
-    let memory = vec![
-        0.0,
-        -1.66666666666666324348e-01, /* 0xBFC55555, 0x55555549 */
-        8.33333333332248946124e-03, /* 0x3F811111, 0x1110F8A6 */
-        -1.98412698298579493134e-04, /* 0xBF2A01A0, 0x19C161D5 */
-        2.75573137070700676789e-06, /* 0x3EC71DE3, 0x57B1FE7D */
-        -2.50507602534068634195e-08, /* 0xBE5AE5E6, 0x8A2B9CEB */
-        1.58969099521155010221e-10, /* 0x3DE5D93A, 0x5ACFD57C */
-        0.0,
-        0.0,
-        0.0,
-        0.0,
-        0.0,
-    ];
-    let mut csin = CProgram { instrs, memory };
-    let start = std::time::Instant::now();
-    let mut sum = 0.0;
-    let mut x = 0.0;
-    for _ in 0..count {
-        csin.memory[0] = x;
-        csin.run();
-        sum += csin.memory[0];
-        x += step;
+
+    match program.verify() {
+        Ok(mut verified) => {
+            let start = Instant::now();
+            let mut sum = 0.0;
+            let mut x = 0.0;
+            for _ in 0..count {
+                inputs.iter_mut().for_each(|input| *input = x);
+                sum += verified.eval(&inputs);
+                x += step;
+            }
+            let elapsed = start.elapsed();
+            println!("vm_v time:   {:?} v={}", elapsed, sum);
+            program = verified.into_inner();
+        }
+        Err((rejected, err)) => {
+            println!("vm_v skipped: {}", err);
+            program = rejected;
+        }
     }
-    let elapsed = start.elapsed();
-    println!("vm_2 time:   {:?} v={}", elapsed, sum);
-
-    // input=0, consts=1..6, locals=7..11
-    const ADD: u8 = 1;
-    const MUL: u8 = 2;
-    const RETURN: u8 = 3;
-    let instrs = vec![
-        TInstr::new(MUL, 7, 0, 0), // let z = x*x
-        TInstr::new(MUL, 8, 7, 7), // let w = z*z
-        // let r = S1 + z*(S2 + z*S3) + z*w*(S4 + z*S5)
-        TInstr::new(MUL, 9, 7, 6),
-        TInstr::new(ADD, 9, 9, 5),
-        TInstr::new(MUL, 9, 9, 8),
-        TInstr::new(MUL, 9, 9, 7),
-        TInstr::new(MUL, 10, 7, 4),
-        TInstr::new(ADD, 10, 10, 3),
-        TInstr::new(MUL, 10, 10, 7),
-        TInstr::new(ADD, 10, 10, 2),
-        TInstr::new(ADD, 9, 9, 10),
-        // let v = z*x;
-        TInstr::new(MUL, 10, 7, 0),
-        // x + v*(S0 + z*r)
-        TInstr::new(MUL, 11, 7, 9),
-        TInstr::new(ADD, 11, 11, 1),
-        TInstr::new(MUL, 11, 11, 10),
-        TInstr::new(ADD, 0, 0, 11),
-        TInstr::new(RETURN, 0, 0, 0),
-    ];
-    let memory = vec![
-        0.0,
-        -1.66666666666666324348e-01, /* 0xBFC55555, 0x55555549 */
-        8.33333333332248946124e-03, /* 0x3F811111, 0x1110F8A6 */
-        -1.98412698298579493134e-04, /* 0xBF2A01A0, 0x19C161D5 */
-        2.75573137070700676789e-06, /* 0x3EC71DE3, 0x57B1FE7D */
-        -2.50507602534068634195e-08, /* 0xBE5AE5E6, 0x8A2B9CEB */
-        1.58969099521155010221e-10, /* 0x3DE5D93A, 0x5ACFD57C */
-        0.0,
-        0.0,
-        0.0,
-        0.0,
-        0.0,
-    ];
-    let mut tsin = TProgram { instrs, memory };
-    let start = std::time::Instant::now();
-    let mut sum = 0.0;
-    let mut x = 0.0;
-    for _ in 0..count {
-        tsin.memory[0] = x;
-        tsin.run();
-        sum += tsin.memory[0];
-        x += step;
+
+    let label = if cfg!(feature = "simd") { "simd" } else { "lanes" };
+    match program.lanes() {
+        Ok(mut lanes) => {
+            let xs: Vec<f64> = (0..count).map(|ii| (ii as f64) * step).collect();
+            let columns = vec![&xs[..]; width];
+            let start = Instant::now();
+            let sum: f64 = lanes.eval_columns(&columns)?.iter().sum();
+            let elapsed = start.elapsed();
+            println!("{:<5} time:  {:?} v={}", label, elapsed, sum);
+        }
+        Err((_, err)) => println!("{} skipped: {}", label, err),
     }
-    let elapsed = start.elapsed();
-    println!("vm_tco time: {:?} v={}", elapsed, sum);
-    */
+    Ok(())
+}
 
-    /*
-    let sin_jit = to_fn();
-    println!("const_ptr = {:x?}", constants.as_ptr());
-    let start = std::time::Instant::now();
-    let mut sum = 0.0;
-    let mut x = 0.0;
-    for _ in 0..count {
-        sum += sin_jit(x, constants.as_ptr());
-        x += step;
+fn fit(args: &Args) -> Result<()> {
+    let path = args.positional.first().ok_or("no dataset given")?;
+    let csv = read_csv(path)?;
+    let columns = csv.rows.first().map_or(0, Vec::len);
+    if columns < 2 {
+        return Err("the dataset needs at least one input and a target column".into());
     }
-    let elapsed = start.elapsed();
-    println!("jit time:    {:?} v={}", elapsed, sum);
-    */
+    let dataset = Dataset {
+        inputs: (0..columns - 1).map(|column| csv.rows.iter().map(|row| row[column]).collect()).collect(),
+        targets: csv.rows.iter().map(|row| row[columns - 1]).collect(),
+    };
 
-    let start = std::time::Instant::now();
-    let mut sum = 0.0;
-    let mut x = 0.0;
-    for _ in 0..count {
-        sum += k_sin(x);
-        x += step;
+    let mut config = GpConfig {
+        population: args.number("--population", GpConfig::default().population)?,
+        seed: args.number("--seed", GpConfig::default().seed)?,
+        ..GpConfig::default()
+    };
+    if config.population == 0 {
+        return Err("--population must be at least 1".into());
+    }
+    if let Some(ops) = args.get(&["--ops"]) {
+        config.binary_ops.clear();
+        for name in ops.split(',').map(str::trim) {
+            if let Some(&op) = BinaryOpF64::ALL.iter().find(|op| BinaryOp::<f64>::repr(*op) == name) {
+                config.binary_ops.push(op);
+            } else if let Some(&op) = TernaryOpF64::ALL.iter().find(|op| TernaryOp::<f64>::repr(*op) == name) {
+                config.ternary_ops.push(op);
+            } else {
+                return Err(format!("unknown op `{}`", name).into());
+            }
+        }
+    }
+    if let Some(adfs) = args.get(&["--adfs"]) {
+        for arity in adfs.split(',').map(str::trim) {
+            match arity.parse() {
+                Ok(arity) if arity > 0 && arity <= u8::MAX as usize => config.adfs.push(arity),
+                _ => return Err(format!("--adfs expects arities from 1 to 255, got `{}`", arity).into()),
+            }
+        }
+        if config.adfs.len() > MAX_CALL_DEPTH {
+            return Err(format!("--adfs takes at most {} functions", MAX_CALL_DEPTH).into());
+        }
+    }
+    let generations: usize = args.number("--generations", 50)?;
+    let names = csv.header.map(|mut header| {
+        header.truncate(columns - 1);
+        header
+    });
+
+    let start = Instant::now();
+    let mut gp = Gp::new(config, dataset);
+    for _ in 0..generations {
+        gp.step();
+        let best = gp.best();
+        println!("gen {:>4}: error={:e} nodes={}", gp.generation(), best.error, best.size());
     }
     let elapsed = start.elapsed();
-    println!("native time: {:?} v={}", elapsed, sum);
+    println!("fit time:    {:?}", elapsed);
 
-    const LANES: usize = 32;
-    let mut init = [0.0_f64; LANES];
-    for ii in 0..LANES {
-        init[ii] = (ii as f64) * step;
+    let best = gp.best().program();
+    if let Some(names) = names {
+        let legend: Vec<String> = names.iter().enumerate()
+            .map(|(index, name)| format!("a{} = {}", index, name))
+            .collect();
+        println!("inputs: {}", legend.join(", "));
     }
+    println!("{:?}", best);
+    println!("{}", to_infix(&best, LetStyle::Inline));
+    Ok(())
+}
 
-    let mut x = SimdF64::from_array(init);
-    let step = SimdF64::splat(step * (LANES as f64));
-    let mut sum = 0.0;
-    let start = std::time::Instant::now();
-    for _ in (0..count).step_by(LANES) {
-        sum += k_sin_simd(x).horizontal_sum();
-        x += step;
+fn repl() -> Result<()> {
+    let mut session = Session::new();
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        stdout.flush()?;
+        line.clear();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        match line.trim() {
+            "quit" | "exit" => return Ok(()),
+            line => match session.run_line(line) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => println!("{}", output),
+                Err(err) => println!("error: {}", err),
+            },
+        }
     }
-    let elapsed = start.elapsed();
-    println!("simd time:   {:?} v={}", elapsed, sum);
+}
 
-    //println!("eval({:?}, c={:?}) = {:?}", inputs, constants, );
-    //println!("sin(0.3)={}", (PI/3.0).sin());
+fn run() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let command = args.next().ok_or(USAGE)?;
+    let args = Args::parse(args)?;
+    match command.as_str() {
+        "eval" => eval(&args),
+        "convert" => convert(&args),
+        "bench" => bench(&args),
+        "fit" => fit(&args),
+        "repl" => repl(),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(format!("unknown command `{}`\n{}", other, USAGE).into()),
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
-/*
-(let l0 (* i0 i0)
-(let l1 (* l0 l0)
-(let l2 (+ c2 (* l0 c3))
-(let l3 (* l0 i0)
-    (+ (+ i0 (* l3 (+ c0 (* l0 c1))))
-       (* l3 (* l1 l2)))))))
-*/
@@ -0,0 +1,730 @@
+//! Parsing programs from text.
+//!
+//! The infix syntax is the one `infix::to_infix` prints: operators by
+//! `repr()` with the precedence of `infix::infix_precedence`, every other
+//! op as a call (`min(a, b)`, `mul_add(a, b, c)`), `let name = value in
+//! body`, and unary minus, which is folded into literals or lowered to a
+//! multiplication by `-1`. Names that are not symbols but parse as a
+//! literal, such as `NaN` and `inf`, are literals. The whole expression may
+//! be followed by `where name = value ...`, bindings in evaluation order
+//! that become lets around it.
+//!
+//! The s-expression syntax is the one `Program`'s `Debug` prints:
+//! `(op args...)` and `(let name value body)`, with the same names for
+//! inputs, constants and locals as the infix syntax.
+//!
+//! Multi-output programs are a tuple at the top, `(x, y)` in infix and
+//! `(tuple x y)` as an s-expression. As in `Program::multi`, an output may
+//! read the lets of the outputs before it.
+//!
+//! Neither syntax has functions: the `(defn name (a0 ...) body)` forms
+//! that `Debug` prints before the tree, and calls by function name, are
+//! rejected as unknown functions. Programs with functions round-trip
+//! through `codec` or serde instead.
+
+use core::fmt;
+use core::str::FromStr;
+use crate::binary_op::{BinaryOp, OpList};
+use crate::ternary_op::TernaryOp;
+use crate::infix::{infix_precedence, PREC_CMP, PREC_LET};
+use crate::program::{intern, Bits, Index, Node, Program, ProgramError};
+
+/// Names for inputs and constants. `a0, a1, ...` and `c0, c1, ...` are
+/// understood as well unless the table defines them.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    /// `inputs[i]` is read as `Input(i)`.
+    pub inputs: Vec<String>,
+    /// `constants[j]` is read as `Constant(j)`.
+    pub constants: Vec<String>,
+}
+
+impl Symbols {
+    pub fn new(inputs: &[&str], constants: &[&str]) -> Self {
+        Self {
+            inputs: inputs.iter().map(|name| name.to_string()).collect(),
+            constants: constants.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnexpectedChar(char),
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    UnknownName(String),
+    UnknownFunction(String),
+    UnknownOperator(String),
+    WrongArgumentCount { name: String, expected: usize, actual: usize },
+    InvalidLiteral(String),
+    ChainedComparison,
+    IndexTooLarge,
+    Program(ProgramError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset into the source.
+    pub offset: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c)?,
+            ParseErrorKind::UnexpectedToken(token) => write!(f, "unexpected `{}`", token)?,
+            ParseErrorKind::UnexpectedEnd => f.write_str("unexpected end of input")?,
+            ParseErrorKind::UnknownName(name) => write!(f, "unknown name `{}`", name)?,
+            ParseErrorKind::UnknownFunction(name) => write!(f, "unknown function `{}`", name)?,
+            ParseErrorKind::UnknownOperator(op) => {
+                write!(f, "operator `{}` is not supported for this type", op)?
+            }
+            ParseErrorKind::WrongArgumentCount { name, expected, actual } => {
+                write!(f, "`{}` takes {} arguments, got {}", name, expected, actual)?
+            }
+            ParseErrorKind::InvalidLiteral(literal) => write!(f, "invalid literal `{}`", literal)?,
+            ParseErrorKind::ChainedComparison => f.write_str("comparisons cannot be chained")?,
+            ParseErrorKind::IndexTooLarge => f.write_str("index does not fit a node")?,
+            ParseErrorKind::Program(error) => return write!(f, "invalid program: {}", error),
+        }
+        write!(f, " at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    Number(&'a str),
+    Name(&'a str),
+    Op(&'a str),
+}
+
+impl<'a> Token<'a> {
+    fn text(&self) -> &'a str {
+        match *self {
+            Token::Number(text) | Token::Name(text) | Token::Op(text) => text,
+        }
+    }
+}
+
+const OPERATORS: &[&str] = &[
+    ">>>", "<<", ">>", "<=", ">=", "==", "!=",
+    "+", "-", "*", "/", "%", "^", "&", "|", "<", ">", "(", ")", ",", "=",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token<'_>, usize)>, ParseError> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let c = bytes[offset];
+        let start = offset;
+        if c.is_ascii_whitespace() {
+            offset += 1;
+            continue;
+        }
+        let token = if c.is_ascii_digit() || (c == b'.' && bytes.get(offset + 1).is_some_and(u8::is_ascii_digit)) {
+            while offset < bytes.len() {
+                let c = bytes[offset];
+                let exponent_sign = (c == b'+' || c == b'-')
+                    && matches!(bytes[offset - 1], b'e' | b'E');
+                if c.is_ascii_alphanumeric() || c == b'.' || c == b'_' || exponent_sign {
+                    offset += 1;
+                } else {
+                    break;
+                }
+            }
+            Token::Number(&source[start..offset])
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while offset < bytes.len() && (bytes[offset].is_ascii_alphanumeric() || bytes[offset] == b'_') {
+                offset += 1;
+            }
+            Token::Name(&source[start..offset])
+        } else {
+            let op = OPERATORS.iter().find(|op| source[offset..].starts_with(*op))
+                .ok_or_else(|| ParseError {
+                    kind: ParseErrorKind::UnexpectedChar(source[offset..].chars().next().unwrap()),
+                    offset,
+                })?;
+            offset += op.len();
+            Token::Op(op)
+        };
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+type Nodes<BOP, TOP> = Vec<Node<BOP, TOP>>;
+
+struct Parser<'a, T, BOP, TOP> {
+    tokens: Vec<(Token<'a>, usize)>,
+    next: usize,
+    end: usize,
+    symbols: &'a Symbols,
+    /// Let names in scope with their local slot, innermost last.
+    scope: Vec<(&'a str, usize)>,
+    /// Every let name bound so far, which later outputs may read.
+    bound: Vec<(&'a str, usize)>,
+    lets: usize,
+    literals: Vec<T>,
+    ops: core::marker::PhantomData<(BOP, TOP)>,
+}
+
+impl<'a, T, BOP, TOP> Parser<'a, T, BOP, TOP>
+    where T: Bits + fmt::Debug + PartialEq + FromStr,
+          BOP: OpList + BinaryOp<T>,
+          TOP: OpList + TernaryOp<T>,
+{
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next).map(|(token, _)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |&(_, offset)| offset)
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError { kind, offset: self.offset() }
+    }
+
+    fn bump(&mut self) -> Result<Token<'a>, ParseError> {
+        let token = self.peek().cloned().ok_or_else(|| self.error(ParseErrorKind::UnexpectedEnd))?;
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(Token::Op(found)) if *found == op => {
+                self.next += 1;
+                Ok(())
+            }
+            Some(token) => Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string()))),
+            None => Err(self.error(ParseErrorKind::UnexpectedEnd)),
+        }
+    }
+
+    fn literal(&mut self, text: &str, offset: usize) -> Result<Node<BOP, TOP>, ParseError> {
+        let value = text.replace('_', "").parse::<T>().map_err(|_| ParseError {
+            kind: ParseErrorKind::InvalidLiteral(text.to_string()),
+            offset,
+        })?;
+        let index = intern(&mut self.literals, value);
+        Ok(Node::Literal(to_index(index, offset)?))
+    }
+
+    fn name(&self, name: &str, offset: usize) -> Result<Node<BOP, TOP>, ParseError> {
+        if let Some(&(_, slot)) = self.scope.iter().rev().find(|(known, _)| *known == name) {
+            return Ok(Node::Local(to_index(slot, offset)?));
+        }
+        if let Some(index) = self.symbols.inputs.iter().position(|known| known == name) {
+            return Ok(Node::Input(to_index(index, offset)?));
+        }
+        if let Some(index) = self.symbols.constants.iter().position(|known| known == name) {
+            return Ok(Node::Constant(to_index(index, offset)?));
+        }
+        let numbered = |prefix: char| {
+            name.strip_prefix(prefix)
+                .filter(|digits| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|digits| digits.parse::<usize>().ok())
+        };
+        if let Some(index) = numbered('a') {
+            return Ok(Node::Input(to_index(index, offset)?));
+        }
+        if let Some(index) = numbered('c') {
+            return Ok(Node::Constant(to_index(index, offset)?));
+        }
+        Err(ParseError { kind: ParseErrorKind::UnknownName(name.to_string()), offset })
+    }
+
+    fn call(&mut self, name: &str, offset: usize) -> Result<Nodes<BOP, TOP>, ParseError> {
+        self.expect("(")?;
+        let mut args = vec![];
+        if self.peek() != Some(&Token::Op(")")) {
+            loop {
+                args.push(self.expr(PREC_LET)?);
+                if self.peek() == Some(&Token::Op(",")) {
+                    self.next += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(")")?;
+
+        let (node, arity) = if let Some(&op) = BOP::ALL.iter().find(|op| op.repr() == name) {
+            (Node::BinaryOp(op), 2)
+        } else if let Some(&op) = TOP::ALL.iter().find(|op| op.repr() == name) {
+            (Node::TernaryOp(op), 3)
+        } else {
+            return Err(ParseError { kind: ParseErrorKind::UnknownFunction(name.to_string()), offset });
+        };
+        if args.len() != arity {
+            let kind = ParseErrorKind::WrongArgumentCount {
+                name: name.to_string(), expected: arity, actual: args.len(),
+            };
+            return Err(ParseError { kind, offset });
+        }
+        let mut nodes = vec![node];
+        for arg in args {
+            nodes.extend(arg);
+        }
+        Ok(nodes)
+    }
+
+    fn primary(&mut self) -> Result<Nodes<BOP, TOP>, ParseError> {
+        let offset = self.offset();
+        match self.bump()? {
+            Token::Number(text) => Ok(vec![self.literal(text, offset)?]),
+            Token::Name("let") => {
+                let name = match self.bump()? {
+                    Token::Name(name) if name != "let" && name != "in" => name,
+                    token => {
+                        self.next -= 1;
+                        return Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string())));
+                    }
+                };
+                self.expect("=")?;
+                let mut nodes = vec![Node::Lettuce];
+                nodes.extend(self.expr(PREC_LET)?);
+                match self.bump()? {
+                    Token::Name("in") => {}
+                    token => {
+                        self.next -= 1;
+                        return Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string())));
+                    }
+                }
+                nodes.extend(self.let_body(name, |parser| parser.expr(PREC_LET))?);
+                Ok(nodes)
+            }
+            Token::Name(name) if self.peek() == Some(&Token::Op("(")) => self.call(name, offset),
+            Token::Name(name) => Ok(vec![self.name_or_literal(name, offset)?]),
+            Token::Op("(") => {
+                let nodes = self.expr(PREC_LET)?;
+                self.expect(")")?;
+                Ok(nodes)
+            }
+            Token::Op("-") => {
+                match self.tokens.get(self.next) {
+                    Some(&(Token::Number(text), _)) => {
+                        self.next += 1;
+                        return Ok(vec![self.literal(&format!("-{}", text), offset)?]);
+                    }
+                    Some(&(Token::Name(text), _)) if self.name(text, offset).is_err() && text.parse::<T>().is_ok() => {
+                        self.next += 1;
+                        return Ok(vec![self.literal(&format!("-{}", text), offset)?]);
+                    }
+                    _ => {}
+                }
+                let operand = self.primary()?;
+                let minus_one = self.literal("-1", offset)?;
+                let mul = BOP::ALL.iter().find(|op| op.repr() == "*")
+                    .ok_or_else(|| ParseError { kind: ParseErrorKind::UnknownOperator("-".to_string()), offset })?;
+                let mut nodes = vec![Node::BinaryOp(*mul), minus_one];
+                nodes.extend(operand);
+                Ok(nodes)
+            }
+            token => {
+                self.next -= 1;
+                Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string())))
+            }
+        }
+    }
+
+    /// A name, or a literal spelled as one (`NaN`, `inf`) if no symbol has
+    /// that name.
+    fn name_or_literal(&mut self, name: &str, offset: usize) -> Result<Node<BOP, TOP>, ParseError> {
+        match self.name(name, offset) {
+            Ok(node) => Ok(node),
+            Err(error) => match name.parse::<T>() {
+                Ok(_) => self.literal(name, offset),
+                Err(_) => Err(error),
+            },
+        }
+    }
+
+    fn let_body<F>(&mut self, name: &'a str, body: F) -> Result<Nodes<BOP, TOP>, ParseError>
+        where F: FnOnce(&mut Self) -> Result<Nodes<BOP, TOP>, ParseError>
+    {
+        // the slot is taken once the value has been evaluated
+        self.scope.push((name, self.lets));
+        self.bound.push((name, self.lets));
+        self.lets += 1;
+        let nodes = body(self);
+        self.scope.pop();
+        nodes
+    }
+
+    fn sexpr(&mut self) -> Result<Nodes<BOP, TOP>, ParseError> {
+        let offset = self.offset();
+        match self.bump()? {
+            Token::Number(text) => Ok(vec![self.literal(text, offset)?]),
+            Token::Name(name) => Ok(vec![self.name_or_literal(name, offset)?]),
+            Token::Op("-") => match self.tokens.get(self.next) {
+                Some(&(Token::Number(text), next)) | Some(&(Token::Name(text), next)) if next == offset + 1 => {
+                    self.next += 1;
+                    Ok(vec![self.literal(&format!("-{}", text), offset)?])
+                }
+                _ => Err(ParseError { kind: ParseErrorKind::UnexpectedToken("-".to_string()), offset }),
+            },
+            Token::Op("(") => {
+                let head_offset = self.offset();
+                let nodes = match self.bump()? {
+                    Token::Name("let") => {
+                        let name = match self.bump()? {
+                            Token::Name(name) => name,
+                            token => {
+                                self.next -= 1;
+                                return Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string())));
+                            }
+                        };
+                        let mut nodes = vec![Node::Lettuce];
+                        nodes.extend(self.sexpr()?);
+                        nodes.extend(self.let_body(name, Self::sexpr)?);
+                        nodes
+                    }
+                    Token::Name(head) | Token::Op(head) if !matches!(head, "(" | ")") => {
+                        let (node, arity) = if let Some(&op) = BOP::ALL.iter().find(|op| op.repr() == head) {
+                            (Node::BinaryOp(op), 2)
+                        } else if let Some(&op) = TOP::ALL.iter().find(|op| op.repr() == head) {
+                            (Node::TernaryOp(op), 3)
+                        } else {
+                            let kind = ParseErrorKind::UnknownFunction(head.to_string());
+                            return Err(ParseError { kind, offset: head_offset });
+                        };
+                        let mut nodes = vec![node];
+                        let mut args = 0;
+                        while self.peek().is_some() && self.peek() != Some(&Token::Op(")")) {
+                            nodes.extend(self.sexpr()?);
+                            args += 1;
+                        }
+                        if args != arity {
+                            let kind = ParseErrorKind::WrongArgumentCount {
+                                name: head.to_string(), expected: arity, actual: args,
+                            };
+                            return Err(ParseError { kind, offset: head_offset });
+                        }
+                        nodes
+                    }
+                    token => {
+                        self.next -= 1;
+                        return Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string())));
+                    }
+                };
+                self.expect(")")?;
+                Ok(nodes)
+            }
+            token => {
+                self.next -= 1;
+                Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string())))
+            }
+        }
+    }
+
+    /// The outputs of `(tuple x y ...)`, or the one tree of any other
+    /// s-expression, with their count.
+    fn sexpr_outputs(&mut self) -> Result<(Nodes<BOP, TOP>, usize), ParseError> {
+        let tuple = self.tokens.get(self.next + 1).map(|(token, _)| token) == Some(&Token::Name("tuple"));
+        if self.peek() != Some(&Token::Op("(")) || !tuple {
+            return Ok((self.sexpr()?, 1));
+        }
+        self.next += 2;
+        let mut nodes = Vec::new();
+        let mut outputs = 0;
+        while self.peek().is_some() && self.peek() != Some(&Token::Op(")")) {
+            self.scope = self.bound.clone();
+            nodes.extend(self.sexpr()?);
+            outputs += 1;
+        }
+        self.expect(")")?;
+        Ok((nodes, outputs))
+    }
+
+    /// An expression or a tuple `(x, y, ...)` of them, optionally followed
+    /// by `where` bindings, with the number of outputs. The bindings are
+    /// read first and become lets around the first output.
+    fn infix_outputs(&mut self) -> Result<(Nodes<BOP, TOP>, usize), ParseError> {
+        let mut nodes = Vec::new();
+        if let Some(at) = self.tokens.iter().position(|(token, _)| *token == Token::Name("where")) {
+            self.next = at + 1;
+            while self.peek().is_some() {
+                let name = match self.bump()? {
+                    Token::Name(name) if !matches!(name, "let" | "in" | "where") => name,
+                    token => {
+                        self.next -= 1;
+                        return Err(self.error(ParseErrorKind::UnexpectedToken(token.text().to_string())));
+                    }
+                };
+                self.expect("=")?;
+                nodes.push(Node::Lettuce);
+                nodes.extend(self.expr(PREC_LET)?);
+                self.scope.push((name, self.lets));
+                self.bound.push((name, self.lets));
+                self.lets += 1;
+            }
+            self.end = self.tokens[at].1;
+            self.tokens.truncate(at);
+            self.next = 0;
+        }
+
+        let (next, lets, bound) = (self.next, self.lets, self.bound.len());
+        if self.peek() == Some(&Token::Op("(")) {
+            self.next += 1;
+            let first = self.expr(PREC_LET)?;
+            if self.peek() == Some(&Token::Op(",")) {
+                nodes.extend(first);
+                let mut outputs = 1;
+                while self.peek() == Some(&Token::Op(",")) {
+                    self.next += 1;
+                    self.scope = self.bound.clone();
+                    nodes.extend(self.expr(PREC_LET)?);
+                    outputs += 1;
+                }
+                self.expect(")")?;
+                return Ok((nodes, outputs));
+            }
+            // only a parenthesized expression, read it again as a whole
+            self.next = next;
+            self.lets = lets;
+            self.bound.truncate(bound);
+        }
+        nodes.extend(self.expr(PREC_LET)?);
+        Ok((nodes, 1))
+    }
+
+    /// Precedence climbing over the binary operators binding tighter
+    /// than `min_prec`.
+    fn expr(&mut self, min_prec: u8) -> Result<Nodes<BOP, TOP>, ParseError> {
+        let mut lhs = self.primary()?;
+        let mut compared = false;
+        while let Some(&(Token::Op(op), offset)) = self.tokens.get(self.next) {
+            let prec = match infix_precedence(op) {
+                Some(prec) if prec > min_prec => prec,
+                _ => break,
+            };
+            if prec == PREC_CMP {
+                if compared {
+                    return Err(ParseError { kind: ParseErrorKind::ChainedComparison, offset });
+                }
+                compared = true;
+            }
+            self.next += 1;
+            let bop = *BOP::ALL.iter().find(|bop| bop.repr() == op)
+                .ok_or_else(|| ParseError { kind: ParseErrorKind::UnknownOperator(op.to_string()), offset })?;
+            let rhs = self.expr(prec)?;
+            let mut nodes = Vec::with_capacity(1 + lhs.len() + rhs.len());
+            nodes.push(Node::BinaryOp(bop));
+            nodes.extend(lhs);
+            nodes.extend(rhs);
+            lhs = nodes;
+        }
+        Ok(lhs)
+    }
+}
+
+fn to_index(index: usize, offset: usize) -> Result<Index, ParseError> {
+    if index > Index::MAX as usize {
+        return Err(ParseError { kind: ParseErrorKind::IndexTooLarge, offset });
+    }
+    Ok(index as Index)
+}
+
+fn parse<'a, T, BOP, TOP, F>(source: &'a str, symbols: &'a Symbols, root: F) -> Result<Program<T, BOP, TOP>, ParseError>
+    where T: Bits + fmt::Debug + PartialEq + FromStr,
+          BOP: OpList + BinaryOp<T>,
+          TOP: OpList + TernaryOp<T>,
+          F: FnOnce(&mut Parser<'a, T, BOP, TOP>) -> Result<(Nodes<BOP, TOP>, usize), ParseError>,
+{
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        next: 0,
+        end: source.len(),
+        symbols,
+        scope: Vec::new(),
+        bound: Vec::new(),
+        lets: 0,
+        literals: Vec::new(),
+        ops: core::marker::PhantomData,
+    };
+    let (nodes, outputs) = root(&mut parser)?;
+    if let Some(token) = parser.peek() {
+        return Err(parser.error(ParseErrorKind::UnexpectedToken(token.text().to_string())));
+    }
+    let program = match outputs {
+        1 => Program::with_literals(nodes, parser.literals),
+        _ => Program::multi(nodes, parser.literals),
+    };
+    program.map_err(|error| ParseError { kind: ParseErrorKind::Program(error), offset: 0 })
+}
+
+/// Parses an infix expression. Constants are left unset.
+pub fn parse_infix<T, BOP, TOP>(source: &str, symbols: &Symbols) -> Result<Program<T, BOP, TOP>, ParseError>
+    where T: Bits + fmt::Debug + PartialEq + FromStr,
+          BOP: OpList + BinaryOp<T>,
+          TOP: OpList + TernaryOp<T>,
+{
+    parse(source, symbols, Parser::infix_outputs)
+}
+
+/// Parses an s-expression. Constants are left unset.
+pub fn parse_sexpr<T, BOP, TOP>(source: &str, symbols: &Symbols) -> Result<Program<T, BOP, TOP>, ParseError>
+    where T: Bits + fmt::Debug + PartialEq + FromStr,
+          BOP: OpList + BinaryOp<T>,
+          TOP: OpList + TernaryOp<T>,
+{
+    parse(source, symbols, Parser::sexpr_outputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::infix::{to_infix, LetStyle};
+    use crate::binary_op::BinaryOpF64;
+    use crate::ternary_op::TernaryOpF64;
+    use crate::program::{ProgramF32, ProgramF64, ProgramI32};
+    use crate::corpus;
+
+    fn parse_f64(source: &str, symbols: &Symbols) -> Result<ProgramF64, ParseError> {
+        parse_infix(source, symbols)
+    }
+
+    #[test]
+    fn precedence() {
+        let symbols = Symbols::new(&["x", "y"], &["k"]);
+        let program = parse_f64("x*x + pow(y, 2) / k - -1.5", &symbols).unwrap();
+        assert_eq!(format!("{:?}", program), "(- (+ (* a0 a0) (/ (pow a1 2.0) c0)) -1.5)");
+
+        let program = parse_f64("x - (y - k) - -x * 2", &symbols).unwrap();
+        assert_eq!(format!("{:?}", program), "(- (- a0 (- a1 c0)) (* (* -1.0 a0) 2.0))");
+
+        let program: ProgramI32 = parse_infix("a0 + a1 << 3 < a0 & a1 ^ checked_add(a1, -4)", &Symbols::default()).unwrap();
+        assert_eq!(format!("{:?}", program), "(< (<< (+ a0 a1) 3) (^ (& a0 a1) (checked_add a1 -4)))");
+    }
+
+    #[test]
+    fn calls_and_lets() {
+        let symbols = Symbols::new(&["x", "lo", "hi"], &[]);
+        let mut program = parse_f64(
+            "let t = clamp(x, lo, hi) in let u = mul_add(t, t, 1) in hypot(min(t, u), max(t, 0.5))",
+            &symbols).unwrap();
+        assert_eq!(format!("{:?}", program),
+            "(let l0 (clamp a0 a1 a2) (let l1 (mul_add l0 l0 1.0) (hypot (min l0 l1) (max l0 0.5))))");
+        let t: f64 = 0.75;
+        let u = t.mul_add(t, 1.0);
+        assert_eq!(program.eval(&[0.75, 0.0, 1.0]).unwrap(), t.min(u).hypot(t.max(0.5)));
+
+        // slots follow evaluation order, names follow scope
+        let program = parse_f64("let x = (let y = a0 in y * y) in x + (let y = x in y)", &Symbols::default()).unwrap();
+        assert_eq!(format!("{:?}", program), "(let l1 (let l0 a0 (* l0 l0)) (+ l1 (let l2 l1 l2)))");
+    }
+
+    #[test]
+    fn round_trip() {
+        let sin = corpus::sin();
+        let parsed = parse_f64(&to_infix(&sin, LetStyle::Inline), &Symbols::default()).unwrap();
+        assert_eq!(parsed.nodes, sin.nodes);
+
+        let program: ProgramF32 = parse_infix("if_lt(a0 - 1e-3, a1 / 3, a2)", &Symbols::default()).unwrap();
+        assert_eq!(program.literals(), &[1e-3, 3.0]);
+        assert_eq!(to_infix(&program, LetStyle::Inline), "if_lt(a0 - 0.001, a1 / 3.0, a2)");
+
+        let program = parse_f64("min(NaN, -inf) * -a0", &Symbols::default()).unwrap();
+        assert_eq!(format!("{:?}", program), "(* (min NaN -inf) (* -1.0 a0))");
+        let program = parse_f64(&to_infix(&program, LetStyle::Inline), &Symbols::default()).unwrap();
+        assert_eq!(format!("{:?}", program), "(* (min NaN -inf) (* -1.0 a0))");
+    }
+
+    #[test]
+    fn sexpr() {
+        let sin = corpus::sin();
+        let parsed: ProgramF64 = parse_sexpr(&format!("{:?}", sin), &Symbols::default()).unwrap();
+        assert_eq!(parsed.nodes, sin.nodes);
+
+        let source = "(let l0 (<< a0 -3) (>>> (checked_add l0 a1) (- l0 -4)))";
+        let program: ProgramI32 = parse_sexpr(source, &Symbols::default()).unwrap();
+        assert_eq!(format!("{:?}", program), source);
+
+        let symbols = Symbols::new(&["x"], &["k"]);
+        let program: ProgramF64 = parse_sexpr("(select (- x NaN) -inf (* k -0.5))", &symbols).unwrap();
+        assert_eq!(format!("{:?}", program), "(select (- a0 NaN) -inf (* c0 -0.5))");
+        let program: ProgramF64 = parse_sexpr("(+ (- x 0) -0.0)", &symbols).unwrap();
+        assert_eq!(format!("{:?}", program), "(+ (- a0 0.0) -0.0)");
+
+        let kind = |source: &str| parse_sexpr::<f64, BinaryOpF64, TernaryOpF64>(source, &symbols)
+            .map(|program| program.nodes).err().map(|e| (e.kind, e.offset));
+        assert_eq!(kind("(sin x)"), Some((ParseErrorKind::UnknownFunction("sin".to_string()), 1)));
+        assert_eq!(kind("(+ x)"), Some((ParseErrorKind::WrongArgumentCount {
+            name: "+".to_string(), expected: 2, actual: 1,
+        }, 1)));
+        assert_eq!(kind("(+ x x"), Some((ParseErrorKind::UnexpectedEnd, 6)));
+        assert_eq!(kind("(- x - 1)"), Some((ParseErrorKind::UnexpectedToken("-".to_string()), 5)));
+        assert_eq!(kind("(let l0 x l1)"), Some((ParseErrorKind::UnknownName("l1".to_string()), 10)));
+        assert_eq!(kind("x x"), Some((ParseErrorKind::UnexpectedToken("x".to_string()), 2)));
+    }
+
+    #[test]
+    fn errors() {
+        let symbols = Symbols::new(&["x", "y"], &[]);
+        let kind = |source: &str| parse_f64(source, &symbols).err().map(|e| (e.kind, e.offset));
+
+        assert_eq!(kind("x*x + sin(y) / 2"),
+            Some((ParseErrorKind::UnknownFunction("sin".to_string()), 6)));
+        assert_eq!(kind("x + z"), Some((ParseErrorKind::UnknownName("z".to_string()), 4)));
+        assert_eq!(kind("x % y"), Some((ParseErrorKind::UnknownOperator("%".to_string()), 2)));
+        assert_eq!(kind("min(x)"), Some((ParseErrorKind::WrongArgumentCount {
+            name: "min".to_string(), expected: 2, actual: 1,
+        }, 0)));
+        assert_eq!(kind("x < y"), Some((ParseErrorKind::UnknownOperator("<".to_string()), 2)));
+        assert_eq!(kind("(x + y"), Some((ParseErrorKind::UnexpectedEnd, 6)));
+        assert_eq!(kind("x y"), Some((ParseErrorKind::UnexpectedToken("y".to_string()), 2)));
+        assert_eq!(kind("x $ y"), Some((ParseErrorKind::UnexpectedChar('$'), 2)));
+        assert_eq!(kind("1.2.3"), Some((ParseErrorKind::InvalidLiteral("1.2.3".to_string()), 0)));
+        assert_eq!(kind("let in = x in in"), Some((ParseErrorKind::UnexpectedToken("in".to_string()), 4)));
+
+        let integer = |source: &str| parse_infix::<i32, _, _>(source, &Symbols::default())
+            .map(|program: ProgramI32| program.nodes).err().map(|e| (e.kind, e.offset));
+        assert_eq!(integer("a0 + 0.5"), Some((ParseErrorKind::InvalidLiteral("0.5".to_string()), 5)));
+        assert_eq!(integer("a0 < a1 < 1"), Some((ParseErrorKind::ChainedComparison, 8)));
+        let error = parse_f64("x +", &symbols).unwrap_err();
+        assert_eq!(error.to_string(), "unexpected end of input at offset 3");
+    }
+
+    #[test]
+    fn multiple_outputs() {
+        use crate::binary_op::BinaryOpF64::*;
+        let mut program = ProgramF64::multi(vec![
+            Node::Lettuce, Node::BinaryOp(Mul), Node::Input(0), Node::Input(0),
+            Node::BinaryOp(Add), Node::Local(0), Node::Input(1),
+            Node::BinaryOp(Sub), Node::Local(0), Node::Input(1),
+        ], Vec::new()).unwrap();
+        let expected = program.eval_multi(&[3.0, 1.0]).unwrap().to_vec();
+        let symbols = Symbols::default();
+
+        let sexpr = format!("{:?}", program);
+        assert_eq!(sexpr, "(tuple (let l0 (* a0 a0) (+ l0 a1)) (- l0 a1))");
+        let parsed: ProgramF64 = parse_sexpr(&sexpr, &symbols).unwrap();
+        assert_eq!(parsed.nodes, program.nodes);
+
+        let infix = to_infix(&program, LetStyle::default_for(&program));
+        assert_eq!(infix, "(l0 + a1, l0 - a1)\nwhere\n    l0 = a0 * a0");
+        let mut parsed = parse_f64(&infix, &symbols).unwrap();
+        assert_eq!(parsed.eval_multi(&[3.0, 1.0]).unwrap(), &expected[..]);
+        assert_eq!(to_infix(&parsed, LetStyle::Where), infix);
+        let parsed = parse_f64(&to_infix(&program, LetStyle::Inline), &symbols).unwrap();
+        assert_eq!(parsed.nodes, program.nodes);
+
+        // `where` works for one output too, and a parenthesized expression
+        // is not a tuple
+        let mut sin = corpus::sin();
+        sin.set_constants(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        let mut parsed = parse_f64(&to_infix(&sin, LetStyle::Where), &symbols).unwrap();
+        parsed.set_constants(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert_eq!(parsed.eval(&[0.5]).unwrap(), sin.eval(&[0.5]).unwrap());
+        assert_eq!(parse_f64("(a0 + a1) * 2", &symbols).unwrap().output_count(), 1);
+        assert_eq!(parse_f64("((a0 + a1) * 2, a0)", &symbols).unwrap().output_count(), 2);
+        assert!(parse_f64("l0 where l0 = a0 l1 = l2", &symbols).is_err());
+    }
+}
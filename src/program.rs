@@ -1,25 +1,125 @@
 use core::fmt;
-use crate::binary_op::{BinaryOp, BinaryOpF32, BinaryOpF64, BinaryOpI32};
-use crate::ternary_op::{TernaryOp, TernaryOpF32, TernaryOpF64, TernaryOpI32};
+use crate::binary_op::{
+    BinaryOp, BinaryOpF32, BinaryOpF64, BinaryOpI32, BinaryOpU32, BinaryOpI64, BinaryOpU64, OpList,
+};
+use crate::ternary_op::{
+    TernaryOp, TernaryOpF32, TernaryOpF64, TernaryOpI32, TernaryOpU32, TernaryOpI64, TernaryOpU64,
+};
 
-#[derive(Debug, Clone, Copy)]
+/// Width of input, local, constant and literal indices. With one-byte op
+/// enums a node still fits in four bytes.
+pub type Index = u16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Node<BOP, TOP> {
-    Input(u8),
-    Local(u8),
-    Constant(u8),
+    Input(Index),
+    Local(Index),
+    Constant(Index),
+    /// Fixed value from the program's literal table. Unlike constants,
+    /// literals are part of the program text and are not meant to be tuned.
+    Literal(Index),
     Lettuce,
     BinaryOp(BOP),
     TernaryOp(TOP),
+    /// Calls the program's function with the given index on the argument
+    /// count that follows; the count must match the function's arity.
+    Call(Index, u8),
 }
 
+impl<BOP, TOP> Node<BOP, TOP> {
+    /// Number of operands that follow the node.
+    pub fn arity(&self) -> usize {
+        match self {
+            Node::Lettuce | Node::BinaryOp(_) => 2,
+            Node::TernaryOp(_) => 3,
+            &Node::Call(_, arity) => arity as usize,
+            Node::Input(_) | Node::Local(_) | Node::Constant(_) | Node::Literal(_) => 0,
+        }
+    }
+}
+
+/// A named sub-program, called with `Node::Call`. In its nodes `Input(i)`
+/// is argument `i`, locals belong to the call, and constants and literals
+/// are the calling program's. The arity is at most `u8::MAX`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Function<BOP, TOP> {
+    pub name: String,
+    pub arity: usize,
+    pub nodes: Vec<Node<BOP, TOP>>,
+}
+
+/// Longest chain of nested calls a program may make.
+pub const MAX_CALL_DEPTH: usize = 32;
+
+/// Most nodes `Program::inline_calls` may produce. Every call copies its
+/// body, so nested calls grow the program exponentially.
+pub const MAX_INLINED_NODES: usize = 1 << 20;
+
 pub type NodeF32 = Node<BinaryOpF32, TernaryOpF32>;
 pub type NodeF64 = Node<BinaryOpF64, TernaryOpF64>;
 pub type NodeI32 = Node<BinaryOpI32, TernaryOpI32>;
+pub type NodeU32 = Node<BinaryOpU32, TernaryOpU32>;
+pub type NodeI64 = Node<BinaryOpI64, TernaryOpI64>;
+pub type NodeU64 = Node<BinaryOpU64, TernaryOpU64>;
+
+pub type FunctionF32 = Function<BinaryOpF32, TernaryOpF32>;
+pub type FunctionF64 = Function<BinaryOpF64, TernaryOpF64>;
+pub type FunctionI32 = Function<BinaryOpI32, TernaryOpI32>;
+pub type FunctionU32 = Function<BinaryOpU32, TernaryOpU32>;
+pub type FunctionI64 = Function<BinaryOpI64, TernaryOpI64>;
+pub type FunctionU64 = Function<BinaryOpU64, TernaryOpU64>;
+
+/// Value types whose literals are told apart by their bits, so `0.0` and
+/// `-0.0` or NaNs with different payloads are separate literals.
+pub trait Bits: Copy {
+    fn bits(self) -> u128;
+}
+
+macro_rules! bits {
+    ($($t:ty => $u:ty),*) => {
+        $(
+            impl Bits for $t {
+                fn bits(self) -> u128 {
+                    self as $u as u128
+                }
+            }
+        )*
+    };
+}
+
+bits!(i32 => u32, u32 => u32, i64 => u64, u64 => u64);
+
+impl Bits for f32 {
+    fn bits(self) -> u128 {
+        self.to_bits() as u128
+    }
+}
+
+impl Bits for f64 {
+    fn bits(self) -> u128 {
+        self.to_bits() as u128
+    }
+}
+
+/// Position of the literal with the bits of `value`, appended if there is
+/// none yet.
+pub(crate) fn intern<T: Bits>(literals: &mut Vec<T>, value: T) -> usize {
+    match literals.iter().position(|known| known.bits() == value.bits()) {
+        Some(index) => index,
+        None => {
+            literals.push(value);
+            literals.len() - 1
+        }
+    }
+}
 
-#[derive(Debug, PartialEq, Eq)]
-pub enum ProgramError {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
     InvalidTree,
     NonExistentConstant,
+    NonExistentLiteral,
     NonExistentInput,
     NonExistentLocal,
     TooFewInputs,
@@ -29,47 +129,396 @@ pub enum ProgramError {
     RegisterDoubleFree,
     TooManyNodes,
     InvalidLocal,
+    ArithmeticError,
+    FallibleOp,
+    UnsupportedOp,
+    MultipleOutputs,
+    NonExistentFunction,
+    WrongArity,
+    RecursionLimit,
+    TooManyLocals,
+    InvalidName,
+    InlineLimit,
+    ColumnLength,
+}
+
+impl ErrorKind {
+    fn message(self) -> &'static str {
+        match self {
+            ErrorKind::InvalidTree => "invalid tree",
+            ErrorKind::NonExistentConstant => "constant does not exist",
+            ErrorKind::NonExistentLiteral => "literal does not exist",
+            ErrorKind::NonExistentInput => "input does not exist",
+            ErrorKind::NonExistentLocal => "local does not exist",
+            ErrorKind::TooFewInputs => "too few inputs",
+            ErrorKind::TooFewConstants => "too few constants",
+            ErrorKind::TooFewNodes => "tree is incomplete",
+            ErrorKind::TooFewRegisters => "out of registers",
+            ErrorKind::RegisterDoubleFree => "register freed twice",
+            ErrorKind::TooManyNodes => "nodes left over after the tree is complete",
+            ErrorKind::InvalidLocal => "local used before it is bound",
+            ErrorKind::ArithmeticError => "arithmetic overflow or division by zero",
+            ErrorKind::FallibleOp => "op may fail at runtime",
+            ErrorKind::UnsupportedOp => "op is not supported by this backend",
+            ErrorKind::MultipleOutputs => "program has more than one output",
+            ErrorKind::NonExistentFunction => "function does not exist",
+            ErrorKind::WrongArity => "call has the wrong number of arguments",
+            ErrorKind::RecursionLimit => "calls are recursive or nested too deeply",
+            ErrorKind::TooManyLocals => "more locals than an index can address",
+            ErrorKind::InvalidName => "function name is taken, is not an identifier or reads as an op, a slot or a keyword",
+            ErrorKind::InlineLimit => "inlined program is too large",
+            ErrorKind::ColumnLength => "columns have different lengths",
+        }
+    }
+}
+
+/// What went wrong and, where it applies, which node, which slot index
+/// and which counts were involved. Errors raised while building a
+/// `Program`, and evaluation errors passed through `Program::explain`,
+/// also carry the s-expression form of the program so `Display` can point
+/// at the offending node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorDetails {
+    pub kind: ErrorKind,
+    pub position: Option<usize>,
+    pub index: Option<usize>,
+    pub expected: Option<usize>,
+    pub actual: Option<usize>,
+    source: Option<(String, usize)>,
+}
+
+/// Boxed so that `Result<T, ProgramError>` stays small in the recursive
+/// evaluator; the details are reachable through `Deref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramError(Box<ErrorDetails>);
+
+impl core::ops::Deref for ProgramError {
+    type Target = ErrorDetails;
+    fn deref(&self) -> &ErrorDetails {
+        &self.0
+    }
+}
+
+impl ProgramError {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self(Box::new(ErrorDetails {
+            kind,
+            position: None,
+            index: None,
+            expected: None,
+            actual: None,
+            source: None,
+        }))
+    }
+
+    pub fn at(mut self, position: usize) -> Self {
+        self.0.position = Some(position);
+        self
+    }
+
+    pub fn index(mut self, index: usize) -> Self {
+        self.0.index = Some(index);
+        self
+    }
+
+    pub fn counts(mut self, expected: usize, actual: usize) -> Self {
+        self.0.expected = Some(expected);
+        self.0.actual = Some(actual);
+        self
+    }
+
+    /// Attaches the rendered program so the error can point into it. Calls
+    /// are shown as `f0, f1, ...`.
+    pub fn with_source<T, BOP, TOP>(self, nodes: &[Node<BOP, TOP>], literals: &[T]) -> Self
+        where T: fmt::Debug,
+              BOP: Copy + BinaryOp<T>,
+              TOP: Copy + TernaryOp<T>,
+    {
+        self.source(nodes, literals, &[])
+    }
+
+    fn source<T, BOP, TOP>(mut self, nodes: &[Node<BOP, TOP>], literals: &[T], functions: &[Function<BOP, TOP>]) -> Self
+        where T: fmt::Debug,
+              BOP: Copy + BinaryOp<T>,
+              TOP: Copy + TernaryOp<T>,
+    {
+        let mut text = String::new();
+        let mut offsets = Vec::with_capacity(nodes.len());
+        if write_sexpr(&mut text, nodes, literals, functions, &mut offsets).is_ok() {
+            let column = self.position
+                .map(|position| offsets.get(position).copied().unwrap_or(text.len()));
+            if let Some(column) = column {
+                self.0.source = Some((text, column));
+            }
+        }
+        self
+    }
+}
+
+impl From<ErrorKind> for ProgramError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.kind.message())?;
+        if let Some(position) = self.position {
+            write!(f, " at node {}", position)?;
+        }
+        if let Some(index) = self.index {
+            write!(f, ", index {}", index)?;
+        }
+        if let (Some(expected), Some(actual)) = (self.expected, self.actual) {
+            write!(f, " (expected {}, got {})", expected, actual)?;
+        }
+        if let Some((text, column)) = &self.source {
+            write!(f, "\n  {}\n  {:>width$}", text, "^", width = column + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+/// Static facts about a valid program, produced by `validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramInfo<BOP, TOP> {
+    /// Inputs, constants and literals are counted as one past the highest
+    /// index read, i.e. the length the corresponding slice must have.
+    pub input_count: usize,
+    pub constant_count: usize,
+    pub literal_count: usize,
+    /// Number of `Lettuce` bindings.
+    pub local_count: usize,
+    /// Nodes on the longest path from a root to a leaf.
+    pub max_depth: usize,
+    /// First node of each output tree, `[0]` for single-output programs.
+    pub roots: Vec<usize>,
+    /// Longest chain of nested calls, 0 for programs without calls.
+    pub call_depth: usize,
+    pub binary_ops: Vec<(BOP, usize)>,
+    pub ternary_ops: Vec<(TOP, usize)>,
+    /// Slots below the respective count that no node reads.
+    pub unused_inputs: Vec<usize>,
+    pub unused_constants: Vec<usize>,
+    /// Constant slots that some node reads but that hold no value yet.
+    /// `validate` lists every read slot; `Program::set_constants` empties
+    /// it, and `eval` refuses to run until it is empty.
+    pub unset_constants: Vec<usize>,
+    /// Local slots that are bound but never read.
+    pub unused_lets: Vec<usize>,
+    /// Local slot bound by each `Lettuce`, in node order. A let gets its
+    /// slot once its value is evaluated, so lets nested inside the value
+    /// come first.
+    pub let_slots: Vec<usize>,
+}
+
+fn bump<OP: PartialEq>(counts: &mut Vec<(OP, usize)>, op: OP) {
+    match counts.iter_mut().find(|(known, _)| *known == op) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((op, 1)),
+    }
+}
+
+fn unread(reads: &[bool]) -> Vec<usize> {
+    reads.iter().enumerate()
+        .filter(|(_, &read)| !read)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn read(reads: &[bool]) -> Vec<usize> {
+    reads.iter().enumerate()
+        .filter(|(_, &read)| read)
+        .map(|(index, _)| index)
+        .collect()
+}
+
+fn mark(reads: &mut Vec<bool>, index: Index) {
+    let index = index as usize;
+    if reads.len() <= index {
+        reads.resize(index + 1, false);
+    }
+    reads[index] = true;
 }
 
-pub fn validate<BOP: Copy, TOP: Copy>(nodes: &[Node<BOP, TOP>]) -> Result<(), ProgramError> {
-    let mut depth = 1;
-    let mut lets = 0;
+/// Checks that the nodes form exactly one tree and that every local is
+/// bound before it is read, following the order `Program::eval` runs in.
+/// A program that passes never hits a missing local or node at runtime.
+pub fn validate<BOP, TOP>(nodes: &[Node<BOP, TOP>]) -> Result<ProgramInfo<BOP, TOP>, ProgramError>
+    where BOP: Copy + PartialEq,
+          TOP: Copy + PartialEq,
+{
+    validate_trees(nodes, false)
+}
 
+/// Like `validate`, but the nodes may hold several trees back to back,
+/// one per output. Locals bound by a tree stay readable in the trees after
+/// it, which is how outputs share work.
+pub fn validate_multi<BOP, TOP>(nodes: &[Node<BOP, TOP>]) -> Result<ProgramInfo<BOP, TOP>, ProgramError>
+    where BOP: Copy + PartialEq,
+          TOP: Copy + PartialEq,
+{
+    validate_trees(nodes, true)
+}
+
+pub(crate) fn validate_trees<BOP, TOP>(nodes: &[Node<BOP, TOP>], multi: bool) -> Result<ProgramInfo<BOP, TOP>, ProgramError>
+    where BOP: Copy + PartialEq,
+          TOP: Copy + PartialEq,
+{
+    let walk = walk(nodes, multi);
+    if let Some(error) = walk.error {
+        return Err(error);
+    }
+    let mut info = walk.info;
     for node in nodes {
-        match node {
-            Node::Constant(_) | Node::Input(_) => {
-                depth -= 1;
+        match *node {
+            Node::BinaryOp(op) => bump(&mut info.binary_ops, op),
+            Node::TernaryOp(op) => bump(&mut info.ternary_ops, op),
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+/// What one pass over the nodes found. The walk keeps going past the first
+/// error so `let_slots` is filled for invalid programs too, which the error
+/// source rendering needs.
+struct Walk<BOP, TOP> {
+    info: ProgramInfo<BOP, TOP>,
+    /// Like `ProgramInfo::let_slots`, `None` for lets whose value never
+    /// completes.
+    let_slots: Vec<Option<usize>>,
+    error: Option<ProgramError>,
+}
+
+fn walk<BOP, TOP>(nodes: &[Node<BOP, TOP>], multi: bool) -> Walk<BOP, TOP> {
+    // (operands still missing, index into let_slots for lets)
+    let mut stack: Vec<(usize, Option<usize>)> = Vec::new();
+    let mut bound = 0;
+    let mut input_reads = Vec::new();
+    let mut constant_reads = Vec::new();
+    let mut local_reads = Vec::new();
+    let mut let_slots = Vec::new();
+    let mut error = None;
+    let mut info = ProgramInfo {
+        input_count: 0,
+        constant_count: 0,
+        literal_count: 0,
+        local_count: 0,
+        max_depth: 0,
+        roots: vec![0],
+        call_depth: 0,
+        binary_ops: Vec::new(),
+        ternary_ops: Vec::new(),
+        unused_inputs: Vec::new(),
+        unused_constants: Vec::new(),
+        unset_constants: Vec::new(),
+        unused_lets: Vec::new(),
+        let_slots: Vec::new(),
+    };
+
+    for (position, node) in nodes.iter().enumerate() {
+        if position != 0 && stack.is_empty() {
+            if !multi && error.is_none() {
+                error = Some(ProgramError::new(ErrorKind::TooManyNodes)
+                    .at(position)
+                    .counts(position, nodes.len()));
             }
-            &Node::Local(index) => {
-                if (index as usize) >= lets {
-                    return Err(ProgramError::InvalidLocal);
+            info.roots.push(position);
+        }
+        info.max_depth = info.max_depth.max(stack.len() + 1);
+
+        match *node {
+            Node::Input(index) => {
+                mark(&mut input_reads, index);
+            }
+            Node::Constant(index) => {
+                mark(&mut constant_reads, index);
+            }
+            Node::Literal(index) => {
+                info.literal_count = info.literal_count.max(index as usize + 1);
+            }
+            Node::Local(index) => {
+                if (index as usize) < bound {
+                    local_reads[index as usize] = true;
+                } else if error.is_none() {
+                    error = Some(ProgramError::new(ErrorKind::InvalidLocal)
+                        .at(position)
+                        .index(index as usize)
+                        .counts(index as usize + 1, bound));
                 }
-                depth -= 1;
             }
             Node::Lettuce => {
-                lets += 1;
-                depth += 1;
+                stack.push((2, Some(let_slots.len())));
+                let_slots.push(None);
+                continue;
             }
             Node::BinaryOp(_) => {
-                depth += 1;
+                stack.push((2, None));
+                continue;
             }
             Node::TernaryOp(_) => {
-                depth += 2;
+                stack.push((3, None));
+                continue;
+            }
+            Node::Call(_, 0) => {}
+            Node::Call(_, arity) => {
+                stack.push((arity as usize, None));
+                continue;
             }
         }
 
-        if depth < 0 {
-            return Err(ProgramError::TooManyNodes);
+        while let Some((left, lettuce)) = stack.last_mut() {
+            *left -= 1;
+            if *left == 1 {
+                if let Some(lettuce) = *lettuce {
+                    let_slots[lettuce] = Some(bound);
+                    local_reads.push(false);
+                    bound += 1;
+                }
+            }
+            if *left > 0 {
+                break;
+            }
+            stack.pop();
         }
     }
 
-    if depth == 0 {
-        Ok(())
-    } else {
-        Err(ProgramError::TooFewNodes)
+    if (nodes.is_empty() || !stack.is_empty()) && error.is_none() {
+        let missing: usize = stack.iter().map(|(left, _)| left).sum();
+        error = Some(ProgramError::new(ErrorKind::TooFewNodes)
+            .at(nodes.len())
+            .counts(nodes.len() + missing.max(1), nodes.len()));
     }
+
+    info.input_count = input_reads.len();
+    info.constant_count = constant_reads.len();
+    info.local_count = bound;
+    info.unused_inputs = unread(&input_reads);
+    info.unused_constants = unread(&constant_reads);
+    info.unset_constants = read(&constant_reads);
+    info.unused_lets = unread(&local_reads);
+    info.let_slots = let_slots.iter().map(|slot| slot.unwrap_or(0)).collect();
+    Walk { info, let_slots, error }
 }
 
+/// One past the last node of the subtree rooted at `position`, which
+/// must lie in a valid program.
+pub fn subtree_end<BOP, TOP>(nodes: &[Node<BOP, TOP>], position: usize) -> usize {
+    let mut end = position;
+    let mut open = 1;
+    while open > 0 {
+        open = open - 1 + nodes[end].arity();
+        end += 1;
+    }
+    end
+}
+
+/// Returns `(inputs, locals, constants)`: the number of input and constant
+/// slots the program reads from, and the number of let bindings.
 pub fn count_vars<BOP: Copy, TOP: Copy>(nodes: &[Node<BOP, TOP>]) -> (usize, usize, usize) {
     let mut input_count = 0;
     let mut local_count = 0;
@@ -77,18 +526,325 @@ pub fn count_vars<BOP: Copy, TOP: Copy>(nodes: &[Node<BOP, TOP>]) -> (usize, usi
     for node in nodes {
         match node {
             &Node::Input(index) => {
-                input_count = input_count.max(index as usize);
-            }
-            &Node::Local(index) => {
-                local_count = local_count.max(index as usize);
+                input_count = input_count.max(index as usize + 1);
             }
             &Node::Constant(index) => {
-                const_count = local_count.max(index as usize);
+                const_count = const_count.max(index as usize + 1);
+            }
+            Node::Lettuce => {
+                local_count += 1;
+            }
+            Node::Local(_) | Node::Literal(_) | Node::BinaryOp(_) | Node::TernaryOp(_) | Node::Call(..) => {}
+        }
+    }
+    (input_count, local_count, const_count)
+}
+
+/// Writes nodes in s-expression form, recording the offset at which each
+/// node starts. Incomplete or overlong node lists are rendered as far as
+/// they go, so this also serves error messages. Calls are named after
+/// `functions`, or `f0, f1, ...` past its end.
+fn write_sexpr<T, BOP, TOP>(out: &mut String, nodes: &[Node<BOP, TOP>], literals: &[T],
+    functions: &[Function<BOP, TOP>], offsets: &mut Vec<usize>) -> fmt::Result
+    where T: fmt::Debug,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>,
+{
+    use fmt::Write;
+
+    // operands still missing from each open list
+    let mut stack = vec![];
+    let slots = walk(nodes, true).let_slots;
+    let mut lettuce = 0;
+
+    for (pos, node) in nodes.iter().enumerate() {
+        if pos != 0 {
+            out.push(' ');
+        }
+        offsets.push(out.len());
+
+        match node {
+            Node::TernaryOp(op) => {
+                write!(out, "({}", op.repr())?;
+                stack.push(3);
+                continue;
+            }
+            Node::BinaryOp(op) => {
+                write!(out, "({}", op.repr())?;
+                stack.push(2);
+                continue;
+            }
+            Node::Lettuce => {
+                match slots[lettuce] {
+                    Some(slot) => write!(out, "(let l{}", slot)?,
+                    None => out.push_str("(let l?"),
+                }
+                stack.push(2);
+                lettuce += 1;
+                continue;
+            }
+            &Node::Call(function, arity) => {
+                match functions.get(function as usize) {
+                    Some(function) => write!(out, "({}", function.name)?,
+                    None => write!(out, "(f{}", function)?,
+                }
+                if arity > 0 {
+                    stack.push(arity as usize);
+                    continue;
+                }
+                out.push(')');
+            }
+            Node::Local(index) => {
+                write!(out, "l{}", index)?;
+            }
+            Node::Constant(index) => {
+                write!(out, "c{}", index)?;
+            }
+            &Node::Literal(index) => {
+                match literals.get(index as usize) {
+                    Some(value) => write!(out, "{:?}", value)?,
+                    None => write!(out, "#{}", index)?,
+                }
+            }
+            Node::Input(index) => {
+                write!(out, "a{}", index)?;
+            }
+        }
+
+        while let Some(left) = stack.last_mut() {
+            *left -= 1;
+            if *left > 0 {
+                break;
             }
-            Node::Lettuce | Node::BinaryOp(_) | Node::TernaryOp(_) => {}
+            stack.pop();
+            out.push(')');
         }
     }
-    return (input_count, local_count, const_count)
+    Ok(())
+}
+
+#[cold]
+#[inline(never)]
+fn missing(kind: ErrorKind, position: usize, index: Index, len: usize) -> ProgramError {
+    ProgramError::new(kind)
+        .at(position)
+        .index(index as usize)
+        .counts(index as usize + 1, len)
+}
+
+/// Checks that every function name is an identifier the printers can
+/// write and a reader can tell apart from an op, an `aN`, `cN` or `lN`
+/// slot, a keyword or another function. The error's index is the
+/// function's.
+pub fn check_names<T, BOP, TOP>(functions: &[Function<BOP, TOP>]) -> Result<(), ProgramError>
+    where BOP: OpList + BinaryOp<T>,
+          TOP: OpList + TernaryOp<T>,
+{
+    for (index, function) in functions.iter().enumerate() {
+        let name = function.name.as_str();
+        let mut chars = name.chars();
+        let identifier = matches!(chars.next(), Some(first) if first.is_ascii_alphabetic() || first == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        let slot = name.len() > 1 && matches!(name.as_bytes()[0], b'a' | b'c' | b'l')
+            && name[1..].bytes().all(|b| b.is_ascii_digit());
+        let reserved = ["let", "in", "where", "tuple", "defn"].contains(&name)
+            || BOP::ALL.iter().any(|op| op.repr() == name)
+            || TOP::ALL.iter().any(|op| op.repr() == name);
+        let duplicate = functions[..index].iter().any(|earlier| earlier.name == name);
+        if !identifier || slot || reserved || duplicate {
+            return Err(ProgramError::new(ErrorKind::InvalidName).index(index));
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every literal a tree reads exists and that every call
+/// names a function and passes it as many arguments as it takes.
+pub(crate) fn check_reads<BOP, TOP>(nodes: &[Node<BOP, TOP>], literal_count: usize, functions: &[Function<BOP, TOP>])
+    -> Result<(), ProgramError>
+{
+    for (position, node) in nodes.iter().enumerate() {
+        match *node {
+            Node::Literal(index) if index as usize >= literal_count => {
+                return Err(missing(ErrorKind::NonExistentLiteral, position, index, literal_count));
+            }
+            Node::Call(index, arity) => match functions.get(index as usize) {
+                None => return Err(missing(ErrorKind::NonExistentFunction, position, index, functions.len())),
+                Some(function) if function.arity != arity as usize => {
+                    return Err(ProgramError::new(ErrorKind::WrongArity)
+                        .at(position)
+                        .index(index as usize)
+                        .counts(function.arity, arity as usize));
+                }
+                Some(_) => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Longest chain of nested calls starting in `nodes`, with the depth of
+/// every function visited so far in `depths` and the functions whose
+/// bodies are being walked in `active`. Calls must already be checked.
+fn call_depth<BOP, TOP>(nodes: &[Node<BOP, TOP>], functions: &[Function<BOP, TOP>],
+    depths: &mut [Option<usize>], active: &mut Vec<usize>) -> Result<usize, ProgramError>
+{
+    let mut depth = 0;
+    for (position, node) in nodes.iter().enumerate() {
+        if let &Node::Call(index, _) = node {
+            let index = index as usize;
+            let callee = match depths[index] {
+                Some(callee) => callee,
+                None => {
+                    if active.contains(&index) || active.len() >= MAX_CALL_DEPTH {
+                        return Err(ProgramError::new(ErrorKind::RecursionLimit)
+                            .at(position)
+                            .index(index)
+                            .counts(MAX_CALL_DEPTH, active.len() + 1));
+                    }
+                    active.push(index);
+                    let callee = call_depth(&functions[index].nodes, functions, depths, active)?;
+                    active.pop();
+                    depths[index] = Some(callee);
+                    callee
+                }
+            };
+            depth = depth.max(callee + 1);
+        }
+    }
+    if depth > MAX_CALL_DEPTH {
+        return Err(ProgramError::new(ErrorKind::RecursionLimit).counts(MAX_CALL_DEPTH, depth));
+    }
+    Ok(depth)
+}
+
+/// Runs function bodies for `Program::eval`. The arguments and locals of
+/// the innermost call are at the end of the two stacks.
+struct Calls<'a, T, BOP, TOP> {
+    functions: &'a [Function<BOP, TOP>],
+    constants: &'a [T],
+    literals: &'a [T],
+    args: &'a mut Vec<T>,
+    locals: &'a mut Vec<T>,
+}
+
+impl<'a, T, BOP, TOP> Calls<'a, T, BOP, TOP>
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>,
+{
+    /// Calls `function` with the arguments from `args` to the top of the
+    /// stack. The program was checked on construction, so only ops fail.
+    fn call(&mut self, function: usize, args: usize) -> Result<T, ProgramError> {
+        let functions = self.functions;
+        let locals = self.locals.len();
+        let value = self.eval(&functions[function].nodes, &mut 0, args, locals);
+        self.locals.truncate(locals);
+        value
+    }
+
+    fn eval(&mut self, nodes: &[Node<BOP, TOP>], position: &mut usize, args: usize, locals: usize)
+        -> Result<T, ProgramError>
+    {
+        let node = nodes[*position];
+        *position += 1;
+        Ok(match node {
+            Node::Input(index) => self.args[args + index as usize],
+            Node::Local(index) => self.locals[locals + index as usize],
+            Node::Constant(index) => self.constants[index as usize],
+            Node::Literal(index) => self.literals[index as usize],
+            Node::Lettuce => {
+                let value = self.eval(nodes, position, args, locals)?;
+                self.locals.push(value);
+                self.eval(nodes, position, args, locals)?
+            }
+            Node::BinaryOp(op) => {
+                let lhs = self.eval(nodes, position, args, locals)?;
+                let rhs = self.eval(nodes, position, args, locals)?;
+                op.try_run(lhs, rhs).ok_or_else(|| ProgramError::new(ErrorKind::ArithmeticError))?
+            }
+            Node::TernaryOp(op) => {
+                let a = self.eval(nodes, position, args, locals)?;
+                let b = self.eval(nodes, position, args, locals)?;
+                let c = self.eval(nodes, position, args, locals)?;
+                op.run(a, b, c)
+            }
+            Node::Call(function, arity) => {
+                let base = self.args.len();
+                for _ in 0..arity {
+                    let value = self.eval(nodes, position, args, locals)?;
+                    self.args.push(value);
+                }
+                let value = self.call(function as usize, base);
+                self.args.truncate(base);
+                value?
+            }
+        })
+    }
+}
+
+/// Copies trees with every call replaced by its body, see
+/// `Program::inline_calls`.
+struct Inliner<'a, BOP, TOP> {
+    functions: &'a [Function<BOP, TOP>],
+    nodes: Vec<Node<BOP, TOP>>,
+    /// Locals bound so far in evaluation order, which is the next slot.
+    bound: usize,
+}
+
+impl<'a, BOP: Copy, TOP: Copy> Inliner<'a, BOP, TOP> {
+    fn bind(&mut self) -> Result<Index, ProgramError> {
+        if self.bound > Index::MAX as usize {
+            return Err(ProgramError::new(ErrorKind::TooManyLocals).counts(Index::MAX as usize + 1, self.bound + 1));
+        }
+        self.bound += 1;
+        Ok((self.bound - 1) as Index)
+    }
+
+    /// Copies the tree at `position`. Inside a function body `args` holds
+    /// the slots its arguments were bound to; `locals` maps the slots of
+    /// the tree's own lets to their new slots.
+    fn tree(&mut self, nodes: &[Node<BOP, TOP>], position: &mut usize, args: Option<&[Index]>,
+        locals: &mut Vec<Index>) -> Result<(), ProgramError>
+    {
+        if self.nodes.len() >= MAX_INLINED_NODES {
+            return Err(ProgramError::new(ErrorKind::InlineLimit).counts(MAX_INLINED_NODES, self.nodes.len() + 1));
+        }
+        let node = nodes[*position];
+        *position += 1;
+        match node {
+            Node::Input(index) => self.nodes.push(match args {
+                Some(args) => Node::Local(args[index as usize]),
+                None => node,
+            }),
+            Node::Local(index) => self.nodes.push(Node::Local(locals[index as usize])),
+            Node::Lettuce => {
+                self.nodes.push(node);
+                self.tree(nodes, position, args, locals)?;
+                let slot = self.bind()?;
+                locals.push(slot);
+                self.tree(nodes, position, args, locals)?;
+            }
+            Node::Call(function, arity) => {
+                let mut slots = Vec::with_capacity(arity as usize);
+                for _ in 0..arity {
+                    self.nodes.push(Node::Lettuce);
+                    self.tree(nodes, position, args, locals)?;
+                    slots.push(self.bind()?);
+                }
+                let functions = self.functions;
+                self.tree(&functions[function as usize].nodes, &mut 0, Some(&slots), &mut Vec::new())?;
+            }
+            _ => {
+                self.nodes.push(node);
+                for _ in 0..node.arity() {
+                    self.tree(nodes, position, args, locals)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct Program<T, BOP, TOP>
@@ -96,78 +852,237 @@ pub struct Program<T, BOP, TOP>
           TOP: TernaryOp<T> + Copy
 {
     pub nodes: Vec<Node<BOP, TOP>>,
-    locals: Vec<T>,
-    constants: Vec<T>,
-    position: usize,
-    const_count: usize,
-    local_count: usize,
-    input_count: usize,
+    pub(crate) locals: Vec<T>,
+    pub(crate) constants: Vec<T>,
+    pub(crate) literals: Vec<T>,
+    pub(crate) outputs: Vec<T>,
+    pub(crate) functions: Vec<Function<BOP, TOP>>,
+    /// Stacks of the calls in progress, see `Calls`.
+    pub(crate) args: Vec<T>,
+    pub(crate) call_locals: Vec<T>,
+    pub(crate) position: usize,
+    pub(crate) info: ProgramInfo<BOP, TOP>,
 }
 
 pub type ProgramF32 = Program<f32, BinaryOpF32, TernaryOpF32>;
 pub type ProgramF64 = Program<f64, BinaryOpF64, TernaryOpF64>;
 pub type ProgramI32 = Program<i32, BinaryOpI32, TernaryOpI32>;
+pub type ProgramU32 = Program<u32, BinaryOpU32, TernaryOpU32>;
+pub type ProgramI64 = Program<i64, BinaryOpI64, TernaryOpI64>;
+pub type ProgramU64 = Program<u64, BinaryOpU64, TernaryOpU64>;
 
 impl<T, BOP, TOP> Program<T, BOP, TOP>
-    where T: Copy,
-          BOP: Copy + BinaryOp<T>,
-          TOP: Copy + TernaryOp<T>
+    where T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>
 {
     pub fn new(nodes: Vec<Node<BOP, TOP>>) -> Result<Self, ProgramError> {
-        validate(&nodes)?;
-        let (input_count, local_count, const_count) = count_vars(&nodes);
+        Self::with_literals(nodes, Vec::new())
+    }
+
+    pub fn with_literals(nodes: Vec<Node<BOP, TOP>>, literals: Vec<T>) -> Result<Self, ProgramError> {
+        Self::build(nodes, literals, false, Vec::new())
+    }
+
+    /// A program with one output per tree in `nodes`, see `validate_multi`.
+    pub fn multi(nodes: Vec<Node<BOP, TOP>>, literals: Vec<T>) -> Result<Self, ProgramError> {
+        Self::build(nodes, literals, true, Vec::new())
+    }
+
+    pub(crate) fn build(nodes: Vec<Node<BOP, TOP>>, literals: Vec<T>, multi: bool,
+        functions: Vec<Function<BOP, TOP>>) -> Result<Self, ProgramError>
+    {
+        let mut info = validate_trees(&nodes, multi)
+            .and_then(|info| check_reads(&nodes, literals.len(), &functions).map(|_| info))
+            .map_err(|error| error.source(&nodes, &literals, &functions))?;
+
+        let mut constant_reads = vec![false; info.constant_count];
+        for (index, function) in functions.iter().enumerate() {
+            // a call node carries its argument count in a byte
+            if function.arity > u8::MAX as usize {
+                return Err(ProgramError::new(ErrorKind::WrongArity)
+                    .index(index)
+                    .counts(u8::MAX as usize, function.arity));
+            }
+            let body = validate(&function.nodes)
+                .and_then(|body| check_reads(&function.nodes, literals.len(), &functions).map(|_| body))
+                .map_err(|error| error.source(&function.nodes, &literals, &functions))?;
+            if body.input_count > function.arity {
+                let position = function.nodes.iter()
+                    .position(|node| matches!(node, &Node::Input(index) if index as usize >= function.arity))
+                    .expect("an input past the arity");
+                return Err(ProgramError::new(ErrorKind::NonExistentInput)
+                    .at(position)
+                    .index(body.input_count - 1)
+                    .counts(body.input_count, function.arity)
+                    .source(&function.nodes, &literals, &functions));
+            }
+            info.constant_count = info.constant_count.max(body.constant_count);
+        }
+        if !functions.is_empty() {
+            constant_reads.resize(info.constant_count, false);
+            let trees = core::iter::once(&nodes).chain(functions.iter().map(|function| &function.nodes));
+            for node in trees.flatten() {
+                if let &Node::Constant(index) = node {
+                    constant_reads[index as usize] = true;
+                }
+            }
+            info.unused_constants = unread(&constant_reads);
+            info.unset_constants = read(&constant_reads);
+            let mut depths = vec![None; functions.len()];
+            info.call_depth = call_depth(&nodes, &functions, &mut depths, &mut Vec::new())
+                .map_err(|error| error.source(&nodes, &literals, &functions))?;
+            // functions the trees never call must not recurse either
+            for (index, function) in functions.iter().enumerate() {
+                if depths[index].is_none() {
+                    let depth = call_depth(&function.nodes, &functions, &mut depths, &mut vec![index])
+                        .map_err(|error| error.source(&function.nodes, &literals, &functions))?;
+                    depths[index] = Some(depth);
+                }
+            }
+        }
 
         Ok(Self {
             nodes,
             locals: Vec::new(),
             constants: Vec::new(),
+            literals,
+            outputs: Vec::new(),
+            functions,
+            args: Vec::new(),
+            call_locals: Vec::new(),
             position: 0,
-            const_count,
-            local_count,
-            input_count,
+            info,
         })
     }
 
+    pub fn info(&self) -> &ProgramInfo<BOP, TOP> {
+        &self.info
+    }
+
+    pub fn literals(&self) -> &[T] {
+        &self.literals
+    }
+
+    pub fn constants(&self) -> &[T] {
+        &self.constants
+    }
+
+    pub fn functions(&self) -> &[Function<BOP, TOP>] {
+        &self.functions
+    }
+
     pub fn set_constants(&mut self, constants: &[T]) -> Result<(), ProgramError> {
-        if constants.len() < self.const_count {
-            return Err(ProgramError::TooFewConstants);
+        if constants.len() < self.info.constant_count {
+            return Err(ProgramError::new(ErrorKind::TooFewConstants)
+                .counts(self.info.constant_count, constants.len()));
         }
         self.constants.clear();
         self.constants.extend(constants.iter().copied());
+        self.info.unset_constants.clear();
         Ok(())
     }
 
+    pub fn output_count(&self) -> usize {
+        self.info.roots.len()
+    }
+
+    /// Evaluates the first output only. Errors carry the position of the
+    /// failing node but not the program text, see `explain`.
     pub fn eval(&mut self, inputs: &[T]) -> Result<T, ProgramError> {
-        if inputs.len() < self.input_count {
-            return Err(ProgramError::TooFewInputs);
+        self.start(inputs)?;
+        self.eval_inner(inputs)
+    }
+
+    /// Evaluates every output in order, sharing the locals between them.
+    pub fn eval_multi(&mut self, inputs: &[T]) -> Result<&[T], ProgramError> {
+        self.start(inputs)?;
+        self.outputs.clear();
+        for _ in 0..self.info.roots.len() {
+            let value = self.eval_inner(inputs)?;
+            self.outputs.push(value);
+        }
+        Ok(&self.outputs)
+    }
+
+    /// Attaches this program's text to an error from `eval`, so that
+    /// `Display` points at the failing node. Rendering is left to callers
+    /// that show the error, which keeps evaluation errors cheap.
+    pub fn explain(&self, error: ProgramError) -> ProgramError {
+        error.source(&self.nodes, &self.literals, &self.functions)
+    }
+
+    /// Fails unless the program has a single output.
+    pub fn single_output(&self) -> Result<(), ProgramError> {
+        if self.info.roots.len() > 1 {
+            return Err(ProgramError::new(ErrorKind::MultipleOutputs)
+                .counts(1, self.info.roots.len()));
+        }
+        Ok(())
+    }
+
+    /// An equivalent program without functions: every call becomes lets
+    /// binding its arguments in order around a copy of the body, so the
+    /// values are bit for bit the same. Constants are kept. Fails with
+    /// `InlineLimit` past `MAX_INLINED_NODES`.
+    pub fn inline_calls(&self) -> Result<Self, ProgramError> {
+        // `nodes` is public and may have changed since the program was built
+        let info = validate_trees(&self.nodes, true)
+            .and_then(|info| check_reads(&self.nodes, self.literals.len(), &self.functions).map(|_| info))
+            .map_err(|error| error.source(&self.nodes, &self.literals, &self.functions))?;
+        let outputs = info.roots.len();
+        let mut inliner = Inliner { functions: &self.functions, nodes: Vec::with_capacity(self.nodes.len()), bound: 0 };
+        let (mut position, mut locals) = (0, Vec::new());
+        for _ in 0..outputs {
+            inliner.tree(&self.nodes, &mut position, None, &mut locals)?;
+        }
+        let mut program = Self::build(inliner.nodes, self.literals.clone(), outputs > 1, Vec::new())?;
+        if !self.constants.is_empty() {
+            program.set_constants(&self.constants)?;
         }
-        if self.constants.len() < self.const_count {
-            return Err(ProgramError::TooFewConstants);
+        Ok(program)
+    }
+
+    fn start(&mut self, inputs: &[T]) -> Result<(), ProgramError> {
+        if inputs.len() < self.info.input_count {
+            return Err(ProgramError::new(ErrorKind::TooFewInputs)
+                .counts(self.info.input_count, inputs.len()));
+        }
+        if !self.info.unset_constants.is_empty() {
+            return Err(ProgramError::new(ErrorKind::TooFewConstants)
+                .counts(self.info.constant_count, self.constants.len()));
         }
 
         self.position = 0;
         self.locals.clear();
-        self.locals.reserve(self.local_count);
-        self.eval_inner(inputs)
+        self.locals.reserve(self.info.local_count);
+        self.args.clear();
+        self.call_locals.clear();
+        Ok(())
     }
 
     fn eval_inner(&mut self, inputs: &[T]) -> Result<T, ProgramError> {
-        let node = *self.nodes.get(self.position)
-            .ok_or(ProgramError::InvalidTree)?;
+        let position = self.position;
+        let node = *self.nodes.get(position)
+            .ok_or_else(|| ProgramError::new(ErrorKind::InvalidTree).at(position))?;
         self.position += 1;
 
         match node {
             Node::Input(index) => {
                 inputs.get(index as usize).copied()
-                    .ok_or(ProgramError::NonExistentInput)
+                    .ok_or_else(|| missing(ErrorKind::NonExistentInput, position, index, inputs.len()))
             }
             Node::Local(index) => {
                 self.locals.get(index as usize).copied()
-                    .ok_or(ProgramError::NonExistentLocal)
+                    .ok_or_else(|| missing(ErrorKind::NonExistentLocal, position, index, self.locals.len()))
             }
             Node::Constant(index) => {
                 self.constants.get(index as usize).copied()
-                    .ok_or(ProgramError::NonExistentConstant)
+                    .ok_or_else(|| missing(ErrorKind::NonExistentConstant, position, index, self.constants.len()))
+            }
+            Node::Literal(index) => {
+                self.literals.get(index as usize).copied()
+                    .ok_or_else(|| missing(ErrorKind::NonExistentLiteral, position, index, self.literals.len()))
             }
             Node::Lettuce => {
                 let value = self.eval_inner(inputs)?;
@@ -177,7 +1092,8 @@ impl<T, BOP, TOP> Program<T, BOP, TOP>
             Node::BinaryOp(op) => {
                 let lhs = self.eval_inner(inputs)?;
                 let rhs = self.eval_inner(inputs)?;
-                Ok(op.run(lhs, rhs))
+                op.try_run(lhs, rhs)
+                    .ok_or_else(|| ProgramError::new(ErrorKind::ArithmeticError).at(position))
             }
             Node::TernaryOp(op) => {
                 let a = self.eval_inner(inputs)?;
@@ -185,82 +1101,102 @@ impl<T, BOP, TOP> Program<T, BOP, TOP>
                 let c = self.eval_inner(inputs)?;
                 Ok(op.run(a, b, c))
             }
+            Node::Call(index, arity) => {
+                let function = self.functions.get(index as usize)
+                    .ok_or_else(|| missing(ErrorKind::NonExistentFunction, position, index, self.functions.len()))?;
+                if function.arity != arity as usize {
+                    return Err(ProgramError::new(ErrorKind::WrongArity)
+                        .at(position)
+                        .index(index as usize)
+                        .counts(function.arity, arity as usize));
+                }
+                let args = self.args.len();
+                for _ in 0..arity {
+                    let value = self.eval_inner(inputs)?;
+                    self.args.push(value);
+                }
+                let mut calls = Calls {
+                    functions: &self.functions,
+                    constants: &self.constants,
+                    literals: &self.literals,
+                    args: &mut self.args,
+                    locals: &mut self.call_locals,
+                };
+                let value = calls.call(index as usize, args);
+                self.args.truncate(args);
+                value.map_err(|error| error.at(position))
+            }
         }
     }
 }
 
-impl<T, BOP, TOP> fmt::Debug for Program<T, BOP, TOP>
+impl<T, BOP, TOP> Program<T, BOP, TOP>
     where T: Copy,
-          BOP: Copy + fmt::Debug + BinaryOp<T>,
-          TOP: Copy + fmt::Debug + TernaryOp<T>,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>
 {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut stack = vec![];
-        let mut left = 1;
-        let mut lettuce = 0;
-
-        for (pos, node) in self.nodes.iter().enumerate() {
-            let mut do_reduce = false;
-            //println!("node {:?}, stack {:?}, left {}", node, stack, left);
-            match node {
-                Node::TernaryOp(op) => {
-                    write!(f, "({}", op.repr())?;
-                    if pos != 0 {
-                        stack.push(left);
-                    }
-                    left = 3;
-                }
-                Node::BinaryOp(op) => {
-                    write!(f, "({}", op.repr())?;
-                    if pos != 0 {
-                        stack.push(left);
-                    }
-                    left = 2;
-                }
-                Node::Lettuce => {
-                    write!(f, "(let l{}", lettuce)?;
-                    if pos != 0 {
-                        stack.push(left);
-                    }
-                    left = 2;
-                    lettuce += 1;
-                }
-                Node::Local(index) => {
-                    write!(f, "l{}", index)?;
-                    do_reduce = true;
-                }
-                Node::Constant(index) => {
-                    write!(f, "c{}", index)?;
-                    do_reduce = true;
-                }
-                Node::Input(index) => {
-                    write!(f, "a{}", index)?;
-                    do_reduce = true;
-                }
-            }
+    /// Runs `function` on `args`, which must be as many as it takes.
+    pub(crate) fn call_function(&self, function: usize, args: &[T]) -> Result<T, ProgramError> {
+        Calls {
+            functions: &self.functions,
+            constants: &self.constants,
+            literals: &self.literals,
+            args: &mut args.to_vec(),
+            locals: &mut Vec::new(),
+        }.call(function, 0)
+    }
+}
 
-            if do_reduce {
-                loop {
-                    left -= 1;
+impl<T, BOP, TOP> Program<T, BOP, TOP>
+    where T: Copy + fmt::Debug,
+          BOP: Copy + OpList + BinaryOp<T>,
+          TOP: Copy + OpList + TernaryOp<T>
+{
+    /// A program that may call `functions`. Every body must be a valid
+    /// tree that reads no input past its arity, no function may reach
+    /// itself through calls or nest more than `MAX_CALL_DEPTH` calls deep,
+    /// and names must pass `check_names`.
+    pub fn with_functions(nodes: Vec<Node<BOP, TOP>>, literals: Vec<T>, functions: Vec<Function<BOP, TOP>>)
+        -> Result<Self, ProgramError>
+    {
+        check_names(&functions)?;
+        Self::build(nodes, literals, false, functions)
+    }
+}
 
-                    if left == 0 {
-                        f.write_str(")")?;
-                        if let Some(last) = stack.pop() {
-                            left = last
-                        } else {
-                            break;
-                        }
-                    } else {
-                        break;
-                    }
-                }
-            }
+impl<T, BOP, TOP> Program<T, BOP, TOP>
+    where T: Bits,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>
+{
+    /// Index of the literal with the bits of `value`, added to the table if
+    /// there is none yet. Panics if the table is full.
+    pub fn intern_literal(&mut self, value: T) -> Index {
+        let index = intern(&mut self.literals, value);
+        assert!(index <= Index::MAX as usize, "literal table is full");
+        index as Index
+    }
+}
 
-            if left > 0 {
-                f.write_str(" ")?;
-            }
+impl<T, BOP, TOP> fmt::Debug for Program<T, BOP, TOP>
+    where T: Copy + fmt::Debug,
+          BOP: Copy + fmt::Debug + BinaryOp<T>,
+          TOP: Copy + fmt::Debug + TernaryOp<T>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for function in &self.functions {
+            let params: Vec<String> = (0..function.arity).map(|index| format!("a{}", index)).collect();
+            let mut body = String::new();
+            write_sexpr(&mut body, &function.nodes, &self.literals, &self.functions, &mut Vec::new())?;
+            write!(f, "(defn {} ({}) {}) ", function.name, params.join(" "), body)?;
+        }
+        let mut out = String::new();
+        write_sexpr(&mut out, &self.nodes, &self.literals, &self.functions, &mut Vec::new())?;
+        if self.info.roots.len() > 1 {
+            write!(f, "(tuple {})", out)
+        } else {
+            f.write_str(&out)
         }
-        Ok(())
     }
 }
 
@@ -274,16 +1210,16 @@ mod tests {
             .unwrap();
 
         assert_eq!(
-            ProgramF32::new(vec![ Node::BinaryOp(BinaryOpF32::Add) ]).err(),
-            Some(ProgramError::TooFewNodes)
+            ProgramF32::new(vec![ Node::BinaryOp(BinaryOpF32::Add) ]).err().map(|e| e.kind),
+            Some(ErrorKind::TooFewNodes)
         );
 
         assert_eq!(
             ProgramF32::new(vec![
                 Node::BinaryOp(BinaryOpF32::Add),
                 Node::Input(0),
-            ]).err(),
-            Some(ProgramError::TooFewNodes)
+            ]).err().map(|e| e.kind),
+            Some(ErrorKind::TooFewNodes)
         );
 
         ProgramF32::new(vec![
@@ -301,6 +1237,261 @@ mod tests {
         ]).unwrap();
     }
 
+    #[test]
+    fn error_display() {
+        let error = ProgramF32::new(vec![
+            Node::BinaryOp(BinaryOpF32::Add),
+            Node::Input(0),
+            Node::Input(1),
+            Node::Input(2),
+        ]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TooManyNodes);
+        assert_eq!(error.position, Some(3));
+        assert_eq!(
+            error.to_string(),
+            "nodes left over after the tree is complete at node 3 (expected 3, got 4)\n\
+             \x20 (+ a0 a1) a2\n\
+             \x20           ^"
+        );
+
+        let error = ProgramF32::new(vec![
+            Node::Lettuce,
+            Node::Input(0),
+            Node::BinaryOp(BinaryOpF32::Mul),
+            Node::Local(0),
+            Node::Local(1),
+        ]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "local used before it is bound at node 4, index 1 (expected 2, got 1)\n\
+             \x20 (let l0 a0 (* l0 l1))\n\
+             \x20                  ^"
+        );
+
+        let mut program = ProgramF32::new(vec![ Node::Input(1) ]).unwrap();
+        let error = program.eval(&[1.0]).unwrap_err();
+        assert_eq!(error.to_string(), "too few inputs (expected 2, got 1)");
+
+        let mut program = ProgramI32::new(vec![
+            Node::BinaryOp(BinaryOpI32::Add), Node::Input(0), Node::BinaryOp(BinaryOpI32::CheckedDiv),
+            Node::Input(0), Node::Input(1),
+        ]).unwrap();
+        let error = program.eval(&[1, 0]).unwrap_err();
+        assert_eq!(error.to_string(), "arithmetic overflow or division by zero at node 2");
+        assert_eq!(
+            program.explain(error).to_string(),
+            "arithmetic overflow or division by zero at node 2\n\
+             \x20 (+ a0 (checked_div a0 a1))\n\
+             \x20       ^"
+        );
+    }
+
+    #[test]
+    fn program_info() {
+        let program = ProgramF32::new(vec![
+            Node::Lettuce,
+            Node::Lettuce,
+            Node::Input(0),
+            Node::BinaryOp(BinaryOpF32::Add),
+            Node::Local(0),
+            Node::Constant(2),
+            Node::BinaryOp(BinaryOpF32::Mul),
+            Node::Local(1),
+            Node::Input(3),
+        ]).unwrap();
+        assert_eq!(
+            format!("{:?}", program),
+            "(let l1 (let l0 a0 (+ l0 c2)) (* l1 a3))"
+        );
+
+        let info = program.info();
+        assert_eq!(info.input_count, 4);
+        assert_eq!(info.constant_count, 3);
+        assert_eq!(info.local_count, 2);
+        assert_eq!(info.max_depth, 4);
+        assert_eq!(info.binary_ops, vec![(BinaryOpF32::Add, 1), (BinaryOpF32::Mul, 1)]);
+        assert_eq!(info.unused_inputs, vec![1, 2]);
+        assert_eq!(info.unused_constants, vec![0, 1]);
+        assert_eq!(info.unset_constants, vec![2]);
+        assert_eq!(info.unused_lets, Vec::<usize>::new());
+        assert_eq!(info.let_slots, vec![1, 0]);
+        assert_eq!(count_vars(&program.nodes), (4, 2, 3));
+
+        let mut program = program;
+        let inputs = [1.0, 0.0, 0.0, 2.0];
+        assert_eq!(program.eval(&inputs).unwrap_err().kind, ErrorKind::TooFewConstants);
+        program.set_constants(&[0.0, 0.0, 3.0]).unwrap();
+        assert!(program.info().unset_constants.is_empty());
+        assert_eq!(program.eval(&inputs).unwrap(), 8.0);
+
+        // the outer let is not bound while its own value is evaluated
+        let error = ProgramF32::new(vec![
+            Node::Lettuce,
+            Node::Lettuce,
+            Node::Input(0),
+            Node::Local(1),
+            Node::Local(0),
+        ]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidLocal);
+        assert_eq!(error.position, Some(3));
+
+        let mut program = ProgramF32::new(vec![ Node::Input(0) ]).unwrap();
+        assert_eq!(program.eval(&[]).unwrap_err().kind, ErrorKind::TooFewInputs);
+        assert_eq!(
+            ProgramF32::new(vec![]).unwrap_err().kind,
+            ErrorKind::TooFewNodes
+        );
+    }
+
+    #[test]
+    fn multiple_outputs() {
+        let nodes = vec![
+            Node::Lettuce,
+            Node::BinaryOp(BinaryOpF32::Mul), Node::Input(0), Node::Input(0),
+            Node::BinaryOp(BinaryOpF32::Add), Node::Local(0), Node::Input(1),
+            Node::BinaryOp(BinaryOpF32::Sub), Node::Local(0), Node::Input(1),
+            Node::Local(0),
+        ];
+        let error = ProgramF32::new(nodes.clone()).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TooManyNodes);
+        assert_eq!(error.position, Some(7));
+
+        let mut program = ProgramF32::multi(nodes, Vec::new()).unwrap();
+        assert_eq!(format!("{:?}", program), "(tuple (let l0 (* a0 a0) (+ l0 a1)) (- l0 a1) l0)");
+        assert_eq!(program.info().roots, vec![0, 7, 10]);
+        assert_eq!(program.info().unused_lets, Vec::<usize>::new());
+        assert_eq!(program.eval_multi(&[3.0, 1.0]).unwrap(), &[10.0, 8.0, 9.0]);
+        assert_eq!(program.eval(&[3.0, 1.0]).unwrap(), 10.0);
+        assert_eq!(program.single_output().unwrap_err().to_string(),
+            "program has more than one output (expected 1, got 3)");
+
+        // a later output may not bind what an earlier one reads
+        let error = ProgramF32::multi(vec![
+            Node::Local(0),
+            Node::Lettuce, Node::Input(0), Node::Local(0),
+        ], Vec::new()).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidLocal);
+        assert_eq!(error.position, Some(0));
+        assert!(ProgramF32::new(vec![Node::Input(0)]).unwrap().single_output().is_ok());
+    }
+
+    #[test]
+    fn intern_literal() {
+        let mut program = ProgramF64::with_literals(vec![Node::Literal(0)], vec![0.0]).unwrap();
+        assert_eq!(program.intern_literal(0.0), 0);
+        assert_eq!(program.intern_literal(-0.0), 1);
+        assert_eq!(program.intern_literal(f64::NAN), 2);
+        assert_eq!(program.intern_literal(f64::from_bits(f64::NAN.to_bits() | 1)), 3);
+        assert_eq!(program.intern_literal(f64::NAN), 2);
+        assert_eq!(program.intern_literal(-0.0), 1);
+        assert_eq!(program.literals().len(), 4);
+    }
+
+    #[test]
+    fn functions() {
+        use BinaryOpF32::*;
+        let square = Function { name: "sq".to_string(), arity: 1, nodes: vec![
+            Node::BinaryOp(Mul), Node::Input(0), Node::Input(0),
+        ] };
+        let norm = Function { name: "norm".to_string(), arity: 2, nodes: vec![
+            Node::Lettuce, Node::Call(0, 1), Node::Input(0),
+            Node::BinaryOp(Add), Node::Local(0), Node::Call(0, 1), Node::Input(1),
+        ] };
+        let functions = vec![square, norm];
+        let nodes = vec![
+            Node::Lettuce, Node::Input(0),
+            Node::BinaryOp(Sub), Node::Call(1, 2), Node::Local(0), Node::Input(1), Node::Literal(0),
+        ];
+        let mut program = ProgramF32::with_functions(nodes.clone(), vec![1.0], functions.clone()).unwrap();
+        assert_eq!(format!("{:?}", program),
+            "(defn sq (a0) (* a0 a0)) (defn norm (a0 a1) (let l0 (sq a0) (+ l0 (sq a1)))) \
+             (let l0 a0 (- (norm l0 a1) 1.0))");
+        assert_eq!(program.info().call_depth, 2);
+        assert_eq!(program.eval(&[3.0, 4.0]).unwrap(), 24.0);
+        assert_eq!(program.eval(&[1.0, 2.0]).unwrap(), 4.0);
+
+        // arguments and the body's own lets are bound in evaluation order
+        let mut inlined = program.inline_calls().unwrap();
+        assert_eq!(inlined.nodes, vec![
+            Node::Lettuce, Node::Input(0), Node::BinaryOp(Sub),
+            Node::Lettuce, Node::Local(0), Node::Lettuce, Node::Input(1),
+            Node::Lettuce, Node::Lettuce, Node::Local(1), Node::BinaryOp(Mul), Node::Local(3), Node::Local(3),
+            Node::BinaryOp(Add), Node::Local(4),
+            Node::Lettuce, Node::Local(2), Node::BinaryOp(Mul), Node::Local(5), Node::Local(5),
+            Node::Literal(0),
+        ]);
+        assert_eq!(inlined.eval(&[3.0, 4.0]).unwrap(), 24.0);
+
+        // the backends without calls inline them
+        let program = ProgramF32::with_functions(nodes.clone(), vec![1.0], functions.clone()).unwrap();
+        assert_eq!(program.verify().ok().unwrap().eval(&[3.0, 4.0]), 24.0);
+        let program = ProgramF32::with_functions(nodes.clone(), vec![1.0], functions.clone()).unwrap();
+        assert_eq!(program.lanes().unwrap().eval_columns(&[&[3.0], &[4.0]]).unwrap(), vec![24.0]);
+
+        let error = ProgramF32::with_functions(vec![Node::Call(2, 1), Node::Input(0)], Vec::new(), functions.clone())
+            .unwrap_err();
+        assert_eq!((error.kind, error.position), (ErrorKind::NonExistentFunction, Some(0)));
+        let error = ProgramF32::with_functions(vec![Node::Call(0, 2), Node::Input(0), Node::Input(1)], Vec::new(),
+            functions.clone()).unwrap_err();
+        assert_eq!(error.to_string(), "call has the wrong number of arguments at node 0, index 0 (expected 1, got 2)\n\
+             \x20 (sq a0 a1)\n\
+             \x20 ^");
+
+        let echo = |index| Function { name: format!("echo{}", index), arity: 1, nodes: vec![Node::Call(index, 1), Node::Input(0)] };
+        let error = ProgramF32::with_functions(vec![Node::Call(0, 1), Node::Input(0)], Vec::new(),
+            vec![echo(1), echo(0)]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::RecursionLimit);
+        let chain = |depth: usize| {
+            let functions = (0..depth)
+                .map(|index| match index {
+                    0 => Function { name: "id".to_string(), arity: 1, nodes: vec![Node::Input(0)] },
+                    _ => echo(index as Index - 1),
+                })
+                .collect();
+            ProgramF32::with_functions(vec![Node::Call(depth as Index - 1, 1), Node::Input(0)], Vec::new(), functions)
+        };
+        assert_eq!(chain(MAX_CALL_DEPTH).unwrap().info().call_depth, MAX_CALL_DEPTH);
+        assert_eq!(chain(MAX_CALL_DEPTH + 1).unwrap_err().kind, ErrorKind::RecursionLimit);
+
+        let huge = Function { name: "huge".to_string(), arity: 1 << 40, nodes: vec![Node::Input(0)] };
+        let error = ProgramF32::with_functions(vec![Node::Input(0)], Vec::new(), vec![huge]).unwrap_err();
+        assert_eq!((error.kind, error.index, error.actual), (ErrorKind::WrongArity, Some(0), Some(1 << 40)));
+
+        let wide = Function { name: "wide".to_string(), arity: 1, nodes: vec![Node::Input(1)] };
+        let error = ProgramF32::with_functions(vec![Node::Call(0, 1), Node::Input(0)], Vec::new(), vec![wide])
+            .unwrap_err();
+        assert_eq!(error.kind, ErrorKind::NonExistentInput);
+
+        // functions the program never calls are checked too
+        let error = ProgramF32::with_functions(vec![Node::Input(0)], Vec::new(), vec![echo(0)]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::RecursionLimit);
+
+        for name in ["", "2x", "a-b", "a0", "c12", "l3", "let", "min"] {
+            let bad = Function { name: name.to_string(), arity: 1, nodes: vec![Node::Input(0)] };
+            let error = ProgramF32::with_functions(vec![Node::Input(0)], Vec::new(), vec![bad]).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::InvalidName, "{:?}", name);
+        }
+        let fine = Function { name: "a_1".to_string(), arity: 1, nodes: vec![Node::Input(0)] };
+        assert!(ProgramF32::with_functions(vec![Node::Input(0)], Vec::new(), vec![fine.clone()]).is_ok());
+        let error = ProgramF32::with_functions(vec![Node::Input(0)], Vec::new(), vec![fine.clone(), fine])
+            .unwrap_err();
+        assert_eq!((error.kind, error.index), (ErrorKind::InvalidName, Some(1)));
+
+        // every level doubles the inlined size
+        let doubling = (0..24)
+            .map(|index| Function {
+                name: format!("f{}", index),
+                arity: 0,
+                nodes: match index {
+                    0 => vec![Node::Literal(0)],
+                    _ => vec![Node::BinaryOp(Add), Node::Call(index - 1, 0), Node::Call(index - 1, 0)],
+                },
+            })
+            .collect();
+        let program = ProgramF32::with_functions(vec![Node::Call(23, 0)], vec![1.0], doubling).unwrap();
+        assert_eq!(program.inline_calls().unwrap_err().kind, ErrorKind::InlineLimit);
+    }
+
     #[test]
     fn tree_format() {
         let program = ProgramF32::new(vec![
@@ -325,6 +1516,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn subtrees() {
+        let nodes = vec![
+            Node::Lettuce,
+            Node::BinaryOp(BinaryOpF32::Add),
+            Node::Input(1),
+            Node::Input(0),
+            Node::TernaryOp(TernaryOpF32::MulAdd),
+            Node::Local(0),
+            Node::Input(0),
+            Node::Constant(0),
+        ];
+        assert_eq!(subtree_end(&nodes, 0), nodes.len());
+        assert_eq!(subtree_end(&nodes, 1), 4);
+        assert_eq!(subtree_end(&nodes, 3), 4);
+        assert_eq!(subtree_end(&nodes, 4), 8);
+    }
+
     #[test]
     fn tree_format_i32() {
         let program = ProgramI32::new(vec![
@@ -369,4 +1578,84 @@ mod tests {
 
         assert_eq!(program.eval(&[2.0, 3.0, 4.0]).ok(), Some(14.0_f32));
     }
+
+    #[test]
+    fn wide_program() {
+        let width = 700;
+        let mut nodes = vec![Node::BinaryOp(BinaryOpI64::Add); width - 1];
+        nodes.extend((0..width).map(|ii| Node::Input(ii as Index)));
+        let mut program = ProgramI64::new(nodes).unwrap();
+
+        let inputs: Vec<i64> = (0..width as i64).collect();
+        assert_eq!(program.eval(&inputs), Ok(699 * 700 / 2));
+        assert_eq!(core::mem::size_of::<NodeI64>(), 4);
+    }
+
+    #[test]
+    fn literals() {
+        let mut program = ProgramF64::with_literals(vec![
+            Node::TernaryOp(TernaryOpF64::MulAdd),
+            Node::Input(0),
+            Node::Literal(0),
+            Node::BinaryOp(BinaryOpF64::Mul),
+            Node::Constant(0),
+            Node::Literal(1),
+        ], vec![0.5, 2.0]).unwrap();
+        program.set_constants(&[3.0]).unwrap();
+
+        assert_eq!(program.eval(&[4.0]), Ok(8.0));
+        assert_eq!(format!("{:?}", program), "(mul_add a0 0.5 (* c0 2.0))");
+
+        assert_eq!(
+            ProgramI32::with_literals(vec![ Node::Literal(1) ], vec![7]).err().map(|e| e.kind),
+            Some(ErrorKind::NonExistentLiteral)
+        );
+    }
+
+    #[test]
+    fn eval_piecewise() {
+        // |x| as (if_lt x (- 0 x) x), and a step via comparison + select
+        let mut abs = ProgramF64::new(vec![
+            Node::TernaryOp(TernaryOpF64::IfLt),
+            Node::Input(0),
+            Node::BinaryOp(BinaryOpF64::Sub),
+            Node::Constant(0),
+            Node::Input(0),
+            Node::Input(0),
+        ]).unwrap();
+        abs.set_constants(&[0.0]).unwrap();
+        assert_eq!(abs.eval(&[-2.5]), Ok(2.5));
+        assert_eq!(abs.eval(&[3.0]), Ok(3.0));
+        assert_eq!(format!("{:?}", abs), "(if_lt a0 (- c0 a0) a0)");
+
+        let mut step = ProgramI32::new(vec![
+            Node::TernaryOp(TernaryOpI32::Select),
+            Node::BinaryOp(BinaryOpI32::Ge),
+            Node::Input(0),
+            Node::Input(1),
+            Node::Constant(0),
+            Node::Constant(1),
+        ]).unwrap();
+        step.set_constants(&[10, 20]).unwrap();
+        assert_eq!(step.eval(&[5, 5]), Ok(10));
+        assert_eq!(step.eval(&[4, 5]), Ok(20));
+    }
+
+    #[test]
+    fn eval_integer_overflow() {
+        let mut wrapping = ProgramI32::new(vec![
+            Node::BinaryOp(BinaryOpI32::Add),
+            Node::Input(0),
+            Node::Input(1),
+        ]).unwrap();
+        assert_eq!(wrapping.eval(&[i32::MAX, 1]), Ok(i32::MIN));
+
+        let mut checked = ProgramU64::new(vec![
+            Node::BinaryOp(BinaryOpU64::CheckedSub),
+            Node::Input(0),
+            Node::Input(1),
+        ]).unwrap();
+        assert_eq!(checked.eval(&[3, 2]), Ok(1));
+        assert_eq!(checked.eval(&[2, 3]).map_err(|e| e.kind), Err(ErrorKind::ArithmeticError));
+    }
 }
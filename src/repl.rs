@@ -0,0 +1,326 @@
+//! A line-oriented session for building and evaluating `f64` programs.
+//!
+//! Each line is a command or an expression. Expressions are read as
+//! s-expressions when they start with `(` and parse as one, as infix
+//! otherwise, with inputs `a0, a1, ...` and constants `c0, c1, ...`.
+//!
+//! ```text
+//! NAME = EXPR               store a program
+//! NAME = simplify PROGRAM   store a simplified copy
+//! NAME = derive PROGRAM VAR store the derivative by `aN` or `cN`
+//! const NAME = V, V, ...    store a constant vector
+//! bind PROGRAM CONSTANTS    set constants from a vector name or a list
+//! eval PROGRAM V, V, ...    evaluate at the given inputs
+//! time PROGRAM V, V, ...    time repeated evaluation
+//! show | infix | simplify PROGRAM
+//! derive PROGRAM VAR
+//! list
+//! EXPR                      print the value, or the program if it has
+//!                           inputs or constants
+//! ```
+
+use core::fmt;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use crate::infix::{to_infix, LetStyle};
+use crate::parse::{parse_infix, parse_sexpr, ParseError, Symbols};
+use crate::program::{ProgramError, ProgramF64};
+use crate::symbolic::{derivative, simplify, Variable};
+
+pub const HELP: &str = "\
+NAME = EXPR | simplify P | derive P VAR   store a program
+const NAME = V, V, ...                    store a constant vector
+bind P CONSTANTS                          set constants of P
+eval P V, V, ...                          evaluate P
+time P V, V, ...                          time evaluation of P
+show P, infix P, simplify P, derive P VAR print forms of P
+list                                      list programs and constants
+quit                                      leave the session
+EXPR                                      evaluate or print an expression";
+
+/// Evaluations per `time` command.
+pub const TIME_COUNT: usize = 1_000_000;
+
+#[derive(Debug)]
+pub enum ReplError {
+    Parse(ParseError),
+    Program(ProgramError),
+    UnknownProgram(String),
+    InvalidNumber(String),
+    InvalidVariable(String),
+    Usage(&'static str),
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplError::Parse(error) => error.fmt(f),
+            ReplError::Program(error) => error.fmt(f),
+            ReplError::UnknownProgram(name) => write!(f, "no program named `{}`", name),
+            ReplError::InvalidNumber(text) => write!(f, "invalid number `{}`", text),
+            ReplError::InvalidVariable(text) => write!(f, "expected an input `aN` or constant `cN`, got `{}`", text),
+            ReplError::Usage(usage) => write!(f, "usage: {}", usage),
+        }
+    }
+}
+
+impl std::error::Error for ReplError {}
+
+impl From<ParseError> for ReplError {
+    fn from(error: ParseError) -> Self {
+        ReplError::Parse(error)
+    }
+}
+
+impl From<ProgramError> for ReplError {
+    fn from(error: ProgramError) -> Self {
+        ReplError::Program(error)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Session {
+    programs: BTreeMap<String, ProgramF64>,
+    constants: BTreeMap<String, Vec<f64>>,
+}
+
+fn is_name(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits off the first whitespace separated word.
+fn word(text: &str) -> (&str, &str) {
+    let text = text.trim();
+    match text.find(char::is_whitespace) {
+        Some(end) => (&text[..end], text[end..].trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn parse_list(text: &str) -> Result<Vec<f64>, ReplError> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().map_err(|_| ReplError::InvalidNumber(value.to_string())))
+        .collect()
+}
+
+fn parse_variable(text: &str) -> Result<Variable, ReplError> {
+    let invalid = || ReplError::InvalidVariable(text.to_string());
+    let index = text.get(1..).and_then(|index| index.parse().ok()).ok_or_else(invalid)?;
+    match text.as_bytes()[0] {
+        b'a' => Ok(Variable::Input(index)),
+        b'c' => Ok(Variable::Constant(index)),
+        _ => Err(invalid()),
+    }
+}
+
+pub fn parse_expr(text: &str) -> Result<ProgramF64, ParseError> {
+    let symbols = Symbols::default();
+    if text.trim_start().starts_with('(') {
+        if let Ok(program) = parse_sexpr(text, &symbols) {
+            return Ok(program);
+        }
+    }
+    parse_infix(text, &symbols)
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn program(&self, name: &str) -> Option<&ProgramF64> {
+        self.programs.get(name)
+    }
+
+    fn get(&mut self, name: &str) -> Result<&mut ProgramF64, ReplError> {
+        self.programs.get_mut(name).ok_or_else(|| ReplError::UnknownProgram(name.to_string()))
+    }
+
+    /// Programs made by `simplify P` and `derive P VAR`, or `None` for
+    /// other text.
+    fn transform(&mut self, text: &str) -> Result<Option<ProgramF64>, ReplError> {
+        let (command, rest) = word(text);
+        let (name, rest) = word(rest);
+        match command {
+            "simplify" if !name.is_empty() && rest.is_empty() => Ok(Some(simplify(self.get(name)?)?)),
+            "derive" if !name.is_empty() && !rest.is_empty() => {
+                let variable = parse_variable(rest)?;
+                Ok(Some(derivative(self.get(name)?, variable)?))
+            }
+            "simplify" => Err(ReplError::Usage("simplify PROGRAM")),
+            "derive" => Err(ReplError::Usage("derive PROGRAM aN|cN")),
+            _ => Ok(None),
+        }
+    }
+
+    /// Runs one line and returns what to print.
+    pub fn run_line(&mut self, line: &str) -> Result<String, ReplError> {
+        let line = line.trim();
+        let (command, rest) = word(line);
+        match command {
+            "" => return Ok(String::new()),
+            "help" => return Ok(HELP.to_string()),
+            "list" => {
+                let mut lines: Vec<String> = self.programs.iter()
+                    .map(|(name, program)| format!("{} = {:?}", name, program))
+                    .collect();
+                lines.extend(self.constants.iter().map(|(name, values)| format!("const {} = {:?}", name, values)));
+                return Ok(lines.join("\n"));
+            }
+            "const" => {
+                let (name, values) = rest.split_once('=').ok_or(ReplError::Usage("const NAME = V, V, ..."))?;
+                let name = name.trim();
+                if !is_name(name) {
+                    return Err(ReplError::Usage("const NAME = V, V, ..."));
+                }
+                let values = parse_list(values)?;
+                let text = format!("{:?}", values);
+                self.constants.insert(name.to_string(), values);
+                return Ok(text);
+            }
+            "bind" => {
+                let (name, values) = word(rest);
+                let values = match self.constants.get(values) {
+                    Some(values) => values.clone(),
+                    None if !values.is_empty() => parse_list(values)?,
+                    None => return Err(ReplError::Usage("bind PROGRAM CONSTANTS")),
+                };
+                self.get(name)?.set_constants(&values)?;
+                return Ok(String::new());
+            }
+            "eval" => {
+                let (name, inputs) = word(rest);
+                let inputs = parse_list(inputs)?;
+                let program = self.get(name)?;
+                return Ok(program.eval_multi(&inputs).map(outputs).map_err(|error| program.explain(error))?);
+            }
+            "time" => {
+                let (name, inputs) = word(rest);
+                let inputs = parse_list(inputs)?;
+                let program = self.get(name)?;
+                let sum = |program: &mut ProgramF64| program.eval_multi(&inputs)
+                    .map(|values| values.iter().sum::<f64>())
+                    .map_err(|error| program.explain(error));
+                let mut total = sum(program)?;
+                let start = Instant::now();
+                for _ in 1..TIME_COUNT {
+                    total += sum(program)?;
+                }
+                let elapsed = start.elapsed();
+                return Ok(format!("eval time: {:?} for {} v={}", elapsed, TIME_COUNT, total));
+            }
+            "show" => return Ok(format!("{:?}", self.get(rest)?)),
+            "infix" => {
+                let program = self.get(rest)?;
+                return Ok(to_infix(program, LetStyle::default_for(program)));
+            }
+            "simplify" | "derive" => {
+                let program = self.transform(line)?.expect("transform handles the command");
+                return Ok(format!("{:?}", program));
+            }
+            _ => {}
+        }
+
+        if let Some((name, value)) = line.split_once('=') {
+            let value = value.trim();
+            let name = name.trim();
+            if is_name(name) && !value.starts_with('=') {
+                let program = match self.transform(value)? {
+                    Some(program) => program,
+                    None => parse_expr(value)?,
+                };
+                let text = format!("{:?}", program);
+                self.programs.insert(name.to_string(), program);
+                return Ok(text);
+            }
+        }
+        if let Some(program) = self.programs.get(line) {
+            return Ok(format!("{:?}", program));
+        }
+        let mut program = parse_expr(line)?;
+        let info = program.info();
+        if info.input_count == 0 && info.constant_count == 0 {
+            Ok(program.eval_multi(&[]).map(outputs).map_err(|error| program.explain(error))?)
+        } else {
+            Ok(format!("{:?}", program))
+        }
+    }
+}
+
+/// One value per output, separated by commas.
+fn outputs(values: &[f64]) -> String {
+    values.iter().map(|value| format!("{:?}", value)).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(session: &mut Session, lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| session.run_line(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn session() {
+        let mut session = Session::new();
+        let output = run(&mut session, &[
+            "f = a0 * a0 * c0 + 1 * a1",
+            "g = (+ (* a0 1) a1)",
+            "const k = 3, 4",
+            "bind f k",
+            "eval f 2, 5",
+            "df = derive f a0",
+            "bind df k",
+            "eval df 2",
+            "simplify g",
+            "infix f",
+            "2 * (3 + 4)",
+            "a0 + 1",
+            "g",
+        ]);
+        assert_eq!(output, [
+            "(+ (* (* a0 a0) c0) (* 1.0 a1))",
+            "(+ (* a0 1.0) a1)",
+            "[3.0, 4.0]",
+            "",
+            "17.0",
+            "(* (+ a0 a0) c0)",
+            "",
+            "12.0",
+            "(+ a0 a1)",
+            "a0 * a0 * c0 + 1.0 * a1",
+            "14.0",
+            "(+ a0 1.0)",
+            "(+ (* a0 1.0) a1)",
+        ]);
+        assert_eq!(session.program("f").unwrap().constants(), &[3.0, 4.0]);
+        assert!(session.run_line("time g 1, 2").unwrap().starts_with("eval time: "));
+        assert!(session.run_line("list").unwrap().contains("const k = [3.0, 4.0]"));
+
+        let output = run(&mut session, &["h = (a0 + 1, a1 * 2)", "eval h 1, 2", "(1 + 2, 3)"]);
+        assert_eq!(&output[1..], ["2.0, 4.0", "3.0, 3.0"]);
+        assert!(session.run_line("time h 1, 2").unwrap().starts_with("eval time: "));
+    }
+
+    #[test]
+    fn errors() {
+        let mut session = Session::new();
+        session.run_line("f = a0 + c0").unwrap();
+        let cases = [
+            ("eval h 1", "no program named `h`"),
+            ("eval f 1", "too few constants (expected 1, got 0)"),
+            ("bind f 1, x", "invalid number `x`"),
+            ("derive f b1", "expected an input `aN` or constant `cN`, got `b1`"),
+            ("simplify", "usage: simplify PROGRAM"),
+            ("g = 1 +", "unexpected end of input at offset 3"),
+        ];
+        for (line, message) in cases.iter() {
+            assert_eq!(session.run_line(line).unwrap_err().to_string(), *message, "{}", line);
+        }
+    }
+}
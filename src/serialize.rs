@@ -0,0 +1,321 @@
+//! Serde support for programs, enabled with the `serde` feature.
+//!
+//! The JSON shape is stable: nodes are externally tagged (`"Lettuce"`,
+//! `{"Input": 0}`, `{"BinaryOp": "+"}`), ops are named by their `repr()`,
+//! and a program is `{"nodes": [...], "outputs": n, "constants": [...],
+//! "literals": [...]}`, where `outputs` is the number of trees in the
+//! nodes. Constants and literals may be omitted when empty, `outputs` when
+//! it is 1, and so may
+//! `"functions"`, a list of `{"name": ..., "arity": ..., "nodes": [...]}`
+//! that calls (`{"Call": [0, 2]}`) refer to by position. JSON has no
+//! numbers for NaN and the infinities, so float values write them as the
+//! strings `"NaN"`, `"inf"` and `"-inf"`.
+
+use core::fmt;
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use crate::binary_op::{BinaryOp, OpList};
+use crate::ternary_op::TernaryOp;
+use crate::program::{check_names, Function, Node, Program};
+
+/// Constant and literal types, written as plain numbers except for the
+/// non-finite floats.
+pub trait Value: Copy {
+    fn serialize_value<S: Serializer>(self, serializer: S) -> Result<S::Ok, S::Error>;
+    fn deserialize_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+macro_rules! integer_value {
+    ($($t:ty),*) => {
+        $(
+            impl Value for $t {
+                fn serialize_value<S: Serializer>(self, serializer: S) -> Result<S::Ok, S::Error> {
+                    self.serialize(serializer)
+                }
+                fn deserialize_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    <$t>::deserialize(deserializer)
+                }
+            }
+        )*
+    };
+}
+
+integer_value!(i32, u32, i64, u64);
+
+struct FloatVisitor;
+
+impl<'de> de::Visitor<'de> for FloatVisitor {
+    type Value = f64;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a number, \"NaN\", \"inf\" or \"-inf\"")
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<f64, E> {
+        Ok(value)
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<f64, E> {
+        Ok(value as f64)
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<f64, E> {
+        Ok(value as f64)
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<f64, E> {
+        match value {
+            "NaN" => Ok(f64::NAN),
+            "inf" => Ok(f64::INFINITY),
+            "-inf" => Ok(f64::NEG_INFINITY),
+            _ => Err(E::invalid_value(de::Unexpected::Str(value), &self)),
+        }
+    }
+}
+
+macro_rules! float_value {
+    ($($t:ty),*) => {
+        $(
+            impl Value for $t {
+                fn serialize_value<S: Serializer>(self, serializer: S) -> Result<S::Ok, S::Error> {
+                    if self.is_nan() {
+                        serializer.serialize_str("NaN")
+                    } else if self.is_infinite() {
+                        serializer.serialize_str(if self > 0.0 { "inf" } else { "-inf" })
+                    } else {
+                        self.serialize(serializer)
+                    }
+                }
+                fn deserialize_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    deserializer.deserialize_any(FloatVisitor).map(|value| value as $t)
+                }
+            }
+        )*
+    };
+}
+
+float_value!(f32, f64);
+
+struct SerializeValue<T>(T);
+
+impl<T: Value> Serialize for SerializeValue<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_value(serializer)
+    }
+}
+
+struct DeserializeValue<T>(T);
+
+impl<'de, T: Value> Deserialize<'de> for DeserializeValue<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize_value(deserializer).map(DeserializeValue)
+    }
+}
+
+fn serialize_values<T: Value, S: Serializer>(values: &&[T], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values.iter().map(|&value| SerializeValue(value)))
+}
+
+fn deserialize_values<'de, T: Value, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<T>, D::Error> {
+    let values = Vec::<DeserializeValue<T>>::deserialize(deserializer)?;
+    Ok(values.into_iter().map(|value| value.0).collect())
+}
+
+#[derive(serde::Serialize)]
+#[serde(bound(serialize = "T: Value, BOP: Serialize, TOP: Serialize"))]
+struct ProgramRef<'a, T, BOP, TOP> {
+    nodes: &'a [Node<BOP, TOP>],
+    outputs: usize,
+    #[serde(serialize_with = "serialize_values")]
+    constants: &'a [T],
+    #[serde(serialize_with = "serialize_values")]
+    literals: &'a [T],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    functions: &'a [Function<BOP, TOP>],
+}
+
+#[derive(serde::Deserialize)]
+#[serde(bound(deserialize = "T: Value, BOP: Deserialize<'de>, TOP: Deserialize<'de>"))]
+struct ProgramData<T, BOP, TOP> {
+    nodes: Vec<Node<BOP, TOP>>,
+    #[serde(default = "one")]
+    outputs: usize,
+    #[serde(default = "Vec::new", deserialize_with = "deserialize_values")]
+    constants: Vec<T>,
+    #[serde(default = "Vec::new", deserialize_with = "deserialize_values")]
+    literals: Vec<T>,
+    #[serde(default = "Vec::new")]
+    functions: Vec<Function<BOP, TOP>>,
+}
+
+impl<T, BOP, TOP> Serialize for Program<T, BOP, TOP>
+    where T: Value + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T> + Serialize,
+          TOP: Copy + PartialEq + TernaryOp<T> + Serialize,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        ProgramRef {
+            nodes: &self.nodes,
+            outputs: self.output_count(),
+            constants: self.constants(),
+            literals: self.literals(),
+            functions: self.functions(),
+        }.serialize(serializer)
+    }
+}
+
+fn one() -> usize {
+    1
+}
+
+/// Deserialized programs go through the checks of `Program::multi`,
+/// `Program::with_functions` and `set_constants`, and must have as many
+/// trees as `outputs` says, so invalid ones are rejected with the program
+/// error as the message.
+impl<'de, T, BOP, TOP> Deserialize<'de> for Program<T, BOP, TOP>
+    where T: Value + fmt::Debug,
+          BOP: Copy + OpList + BinaryOp<T> + Deserialize<'de>,
+          TOP: Copy + OpList + TernaryOp<T> + Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let data = ProgramData::<T, BOP, TOP>::deserialize(deserializer)?;
+        check_names(&data.functions).map_err(de::Error::custom)?;
+        let mut program = Program::build(data.nodes, data.literals, data.outputs != 1, data.functions)
+            .map_err(de::Error::custom)?;
+        if program.output_count() != data.outputs {
+            return Err(de::Error::custom(format_args!("expected {} outputs, found {}",
+                data.outputs, program.output_count())));
+        }
+        if !data.constants.is_empty() {
+            program.set_constants(&data.constants).map_err(de::Error::custom)?;
+        }
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::binary_op::{BinaryOpF64, BinaryOpI32};
+    use crate::program::{Node, NodeF64, ProgramF32, ProgramF64, ProgramI32};
+    use crate::ternary_op::{TernaryOpF64, TernaryOpI32};
+
+    #[test]
+    fn node_shape() {
+        let nodes: Vec<NodeF64> = vec![
+            Node::Lettuce,
+            Node::TernaryOp(TernaryOpF64::MulAdd),
+            Node::Input(0),
+            Node::Constant(1),
+            Node::Literal(0),
+            Node::BinaryOp(BinaryOpF64::Pow),
+            Node::Local(0),
+            Node::Local(0),
+        ];
+        let json = serde_json::to_string(&nodes).unwrap();
+        assert_eq!(
+            json,
+            r#"["Lettuce",{"TernaryOp":"mul_add"},{"Input":0},{"Constant":1},{"Literal":0},{"BinaryOp":"pow"},{"Local":0},{"Local":0}]"#
+        );
+        assert_eq!(serde_json::from_str::<Vec<NodeF64>>(&json).unwrap(), nodes);
+    }
+
+    #[test]
+    fn program_round_trip() {
+        let mut program = ProgramF64::with_literals(vec![
+            Node::BinaryOp(BinaryOpF64::Mul),
+            Node::Input(0),
+            Node::BinaryOp(BinaryOpF64::Add),
+            Node::Constant(0),
+            Node::Literal(0),
+        ], vec![0.5]).unwrap();
+        program.set_constants(&[-0.16666666666666632]).unwrap();
+
+        let json = serde_json::to_string(&program).unwrap();
+        assert_eq!(
+            json,
+            r#"{"nodes":[{"BinaryOp":"*"},{"Input":0},{"BinaryOp":"+"},{"Constant":0},{"Literal":0}],"outputs":1,"constants":[-0.16666666666666632],"literals":[0.5]}"#
+        );
+        let mut decoded: ProgramF64 = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.nodes, program.nodes);
+        assert_eq!(decoded.constants(), program.constants());
+        assert_eq!(decoded.eval(&[2.0]), program.eval(&[2.0]));
+    }
+
+    #[test]
+    fn non_finite_values() {
+        let mut program = ProgramF64::with_literals(vec![
+            Node::TernaryOp(TernaryOpF64::Clamp),
+            Node::BinaryOp(BinaryOpF64::Min),
+            Node::Input(0),
+            Node::Literal(0),
+            Node::Literal(1),
+            Node::Constant(0),
+        ], vec![f64::NAN, f64::NEG_INFINITY]).unwrap();
+        program.set_constants(&[f64::INFINITY]).unwrap();
+
+        let json = serde_json::to_string(&program).unwrap();
+        assert!(json.ends_with(r#""constants":["inf"],"literals":["NaN","-inf"]}"#), "{}", json);
+        let mut decoded: ProgramF64 = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.constants(), &[f64::INFINITY]);
+        assert!(decoded.literals()[0].is_nan());
+        assert_eq!(decoded.literals()[1], f64::NEG_INFINITY);
+        assert_eq!(decoded.eval(&[2.0]), program.eval(&[2.0]));
+
+        let decoded: ProgramF32 = serde_json::from_str(r#"{"nodes":[{"Literal":0}],"literals":["-inf",1]}"#)
+            .unwrap();
+        assert_eq!(decoded.literals(), &[f32::NEG_INFINITY, 1.0]);
+        let error = serde_json::from_str::<ProgramF64>(r#"{"nodes":[{"Literal":0}],"literals":["nan"]}"#)
+            .err().unwrap();
+        assert!(error.to_string().contains("invalid value: string \"nan\""), "{}", error);
+    }
+
+    #[test]
+    fn integer_ops_by_repr() {
+        let program = ProgramI32::new(vec![
+            Node::TernaryOp(TernaryOpI32::Select),
+            Node::BinaryOp(BinaryOpI32::Ge),
+            Node::Input(0),
+            Node::Input(1),
+            Node::BinaryOp(BinaryOpI32::LogicalShr),
+            Node::Input(0),
+            Node::Input(1),
+            Node::BinaryOp(BinaryOpI32::CheckedAdd),
+            Node::Input(0),
+            Node::Input(1),
+        ]).unwrap();
+
+        let json = serde_json::to_string(&program).unwrap();
+        assert!(json.contains(r#"{"BinaryOp":">="}"#));
+        assert!(json.contains(r#"{"BinaryOp":">>>"}"#));
+        assert!(json.contains(r#"{"BinaryOp":"checked_add"}"#));
+        let decoded: ProgramI32 = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.nodes, program.nodes);
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        let error = serde_json::from_str::<ProgramF64>(r#"{"nodes":[{"BinaryOp":"+"},{"Input":0}]}"#)
+            .err().unwrap();
+        assert!(error.to_string().starts_with("tree is incomplete"));
+
+        let error = serde_json::from_str::<ProgramF64>(r#"{"nodes":[{"BinaryOp":"sin"}]}"#)
+            .err().unwrap();
+        assert!(error.to_string().contains("unknown variant `sin`"));
+
+        let error = serde_json::from_str::<ProgramF64>(r#"{"nodes":[{"Constant":1}],"constants":[1.0]}"#)
+            .err().unwrap();
+        assert!(error.to_string().starts_with("too few constants"));
+
+        // trailing nodes are an error unless `outputs` asks for them
+        let error = serde_json::from_str::<ProgramF64>(r#"{"nodes":[{"Input":0},{"Input":1}]}"#)
+            .err().unwrap();
+        assert!(error.to_string().starts_with("nodes left over"), "{}", error);
+        let error = serde_json::from_str::<ProgramF64>(r#"{"nodes":[{"Input":0},{"Input":1}],"outputs":3}"#)
+            .err().unwrap();
+        assert!(error.to_string().starts_with("expected 3 outputs, found 2"), "{}", error);
+        let mut program: ProgramF64 = serde_json::from_str(r#"{"nodes":[{"Input":0},{"Input":1}],"outputs":2}"#)
+            .unwrap();
+        assert_eq!(program.eval_multi(&[1.0, 2.0]).unwrap(), &[1.0, 2.0]);
+        assert!(serde_json::to_string(&program).unwrap().contains(r#""outputs":2"#));
+    }
+}
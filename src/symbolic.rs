@@ -0,0 +1,498 @@
+//! Simplification and differentiation of `f64` programs.
+//!
+//! Both work on an owned expression tree in which each let has an id and
+//! locals refer to that id, so lets can be moved, copied and dropped
+//! without renumbering; slots are assigned again when the tree is turned
+//! back into nodes. Locals must refer to a let whose body they are in,
+//! and multi-output programs are rejected.
+//!
+//! `simplify` only applies rewrites that never change what `eval` returns,
+//! down to the sign of zero: constant folding, `x + -0`, `x - 0`, `x * 1`,
+//! `x / 1`, `pow(x, 1)`, `mul_add` with a unit factor or `-0` addend,
+//! conditionals on a literal, and inlining of lets that are unused, used
+//! once, or bound to a leaf.
+
+use crate::binary_op::{BinaryOp, BinaryOpF64};
+use crate::ternary_op::{TernaryOp, TernaryOpF64};
+use crate::program::{intern, ErrorKind, Index, Node, NodeF64, ProgramError, ProgramF64};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Input(Index),
+    Constant(Index),
+    Literal(f64),
+    Local(usize),
+    Let(usize, Box<Expr>, Box<Expr>),
+    Binary(BinaryOpF64, Box<Expr>, Box<Expr>),
+    Ternary(TernaryOpF64, Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+use Expr::*;
+
+fn binary(op: BinaryOpF64, a: Expr, b: Expr) -> Expr {
+    Binary(op, Box::new(a), Box::new(b))
+}
+
+fn ternary(op: TernaryOpF64, a: Expr, b: Expr, c: Expr) -> Expr {
+    Ternary(op, Box::new(a), Box::new(b), Box::new(c))
+}
+
+struct Reader<'a> {
+    nodes: &'a [NodeF64],
+    literals: &'a [f64],
+    position: usize,
+    /// Slot bound by each let so far, in runtime order.
+    slots: usize,
+    /// Lets whose body is being read, as `(slot, id)`.
+    scope: Vec<(usize, usize)>,
+}
+
+impl Reader<'_> {
+    fn read(&mut self) -> Result<Expr, ProgramError> {
+        let at = self.position;
+        self.position += 1;
+        Ok(match self.nodes[at] {
+            Node::Input(index) => Input(index),
+            Node::Constant(index) => Constant(index),
+            Node::Literal(index) => Literal(self.literals[index as usize]),
+            Node::Local(index) => {
+                let &(_, id) = self.scope.iter().rev().find(|(slot, _)| *slot == index as usize)
+                    .ok_or_else(|| ProgramError::new(ErrorKind::InvalidLocal).at(at).index(index as usize))?;
+                Local(id)
+            }
+            Node::Lettuce => {
+                let value = self.read()?;
+                let slot = self.slots;
+                self.slots += 1;
+                self.scope.push((slot, slot));
+                let body = self.read();
+                self.scope.pop();
+                Let(slot, Box::new(value), Box::new(body?))
+            }
+            Node::BinaryOp(op) => binary(op, self.read()?, self.read()?),
+            Node::TernaryOp(op) => ternary(op, self.read()?, self.read()?, self.read()?),
+            Node::Call(..) => unreachable!("calls are inlined first"),
+        })
+    }
+}
+
+fn read(program: &ProgramF64) -> Result<Expr, ProgramError> {
+    program.single_output()?;
+    if !program.functions().is_empty() {
+        return read(&program.inline_calls()?);
+    }
+    Reader { nodes: &program.nodes, literals: program.literals(), position: 0, slots: 0, scope: Vec::new() }
+        .read()
+        .map_err(|error| error.with_source(&program.nodes, program.literals()))
+}
+
+#[derive(Default)]
+struct Writer {
+    nodes: Vec<NodeF64>,
+    literals: Vec<f64>,
+    slots: usize,
+    /// `(id, slot)` of the lets in scope.
+    scope: Vec<(usize, usize)>,
+}
+
+impl Writer {
+    fn write(&mut self, expr: &Expr) {
+        match expr {
+            Input(index) => self.nodes.push(Node::Input(*index)),
+            Constant(index) => self.nodes.push(Node::Constant(*index)),
+            Literal(value) => {
+                let index = intern(&mut self.literals, *value);
+                self.nodes.push(Node::Literal(index as Index));
+            }
+            Local(id) => {
+                let &(_, slot) = self.scope.iter().rev().find(|(known, _)| known == id)
+                    .expect("locals refer to an enclosing let");
+                self.nodes.push(Node::Local(slot as Index));
+            }
+            Let(id, value, body) => {
+                self.nodes.push(Node::Lettuce);
+                self.write(value);
+                self.scope.push((*id, self.slots));
+                self.slots += 1;
+                self.write(body);
+                self.scope.pop();
+            }
+            Binary(op, a, b) => {
+                self.nodes.push(Node::BinaryOp(*op));
+                self.write(a);
+                self.write(b);
+            }
+            Ternary(op, a, b, c) => {
+                self.nodes.push(Node::TernaryOp(*op));
+                self.write(a);
+                self.write(b);
+                self.write(c);
+            }
+        }
+    }
+}
+
+/// Turns the tree back into a program, keeping the constants of `original`.
+fn write(expr: &Expr, original: &ProgramF64) -> Result<ProgramF64, ProgramError> {
+    let mut writer = Writer::default();
+    writer.write(expr);
+    let mut program = ProgramF64::with_literals(writer.nodes, writer.literals)?;
+    if program.info().constant_count <= original.constants().len() {
+        program.set_constants(original.constants())?;
+    }
+    Ok(program)
+}
+
+/// Number of reads of let `id` in `expr`, minding shadowing.
+fn uses(expr: &Expr, id: usize) -> usize {
+    match expr {
+        Local(known) => (*known == id) as usize,
+        Let(known, value, body) => uses(value, id) + if *known == id { 0 } else { uses(body, id) },
+        Binary(_, a, b) => uses(a, id) + uses(b, id),
+        Ternary(_, a, b, c) => uses(a, id) + uses(b, id) + uses(c, id),
+        Input(_) | Constant(_) | Literal(_) => 0,
+    }
+}
+
+fn binds_any(expr: &Expr, ids: &[usize]) -> bool {
+    match expr {
+        Let(id, value, body) => ids.contains(id) || binds_any(value, ids) || binds_any(body, ids),
+        Binary(_, a, b) => binds_any(a, ids) || binds_any(b, ids),
+        Ternary(_, a, b, c) => binds_any(a, ids) || binds_any(b, ids) || binds_any(c, ids),
+        Input(_) | Constant(_) | Literal(_) | Local(_) => false,
+    }
+}
+
+fn free_locals(expr: &Expr, bound: &mut Vec<usize>, free: &mut Vec<usize>) {
+    match expr {
+        Local(id) if !bound.contains(id) => free.push(*id),
+        Let(id, value, body) => {
+            free_locals(value, bound, free);
+            bound.push(*id);
+            free_locals(body, bound, free);
+            bound.pop();
+        }
+        Binary(_, a, b) => {
+            free_locals(a, bound, free);
+            free_locals(b, bound, free);
+        }
+        Ternary(_, a, b, c) => {
+            free_locals(a, bound, free);
+            free_locals(b, bound, free);
+            free_locals(c, bound, free);
+        }
+        Input(_) | Constant(_) | Literal(_) | Local(_) => {}
+    }
+}
+
+/// Replaces reads of let `id` in `expr` with `value`.
+fn substitute(expr: Expr, id: usize, value: &Expr) -> Expr {
+    match expr {
+        Local(known) if known == id => value.clone(),
+        Let(known, let_value, body) => {
+            let let_value = substitute(*let_value, id, value);
+            let body = if known == id { *body } else { substitute(*body, id, value) };
+            Let(known, Box::new(let_value), Box::new(body))
+        }
+        Binary(op, a, b) => binary(op, substitute(*a, id, value), substitute(*b, id, value)),
+        Ternary(op, a, b, c) => {
+            ternary(op, substitute(*a, id, value), substitute(*b, id, value), substitute(*c, id, value))
+        }
+        leaf => leaf,
+    }
+}
+
+fn is_literal(expr: &Expr, expected: f64) -> bool {
+    matches!(expr, Literal(value) if value.to_bits() == expected.to_bits())
+}
+
+fn simplify_expr(expr: Expr) -> Expr {
+    match expr {
+        Let(id, value, body) => {
+            let value = simplify_expr(*value);
+            let body = simplify_expr(*body);
+            let count = uses(&body, id);
+            let leaf = matches!(value, Input(_) | Constant(_) | Literal(_) | Local(_));
+            let mut free = Vec::new();
+            free_locals(&value, &mut Vec::new(), &mut free);
+            if count == 0 {
+                body
+            } else if (leaf || count == 1) && !binds_any(&body, &free) {
+                simplify_expr(substitute(body, id, &value))
+            } else {
+                Let(id, Box::new(value), Box::new(body))
+            }
+        }
+        Binary(op, a, b) => {
+            let (a, b) = (simplify_expr(*a), simplify_expr(*b));
+            if let (Literal(a), Literal(b)) = (&a, &b) {
+                return Literal(op.run(*a, *b));
+            }
+            match op {
+                BinaryOpF64::Add if is_literal(&b, -0.0) => a,
+                BinaryOpF64::Add if is_literal(&a, -0.0) => b,
+                BinaryOpF64::Sub if is_literal(&b, 0.0) => a,
+                BinaryOpF64::Mul if is_literal(&b, 1.0) => a,
+                BinaryOpF64::Mul if is_literal(&a, 1.0) => b,
+                BinaryOpF64::Div | BinaryOpF64::Pow if is_literal(&b, 1.0) => a,
+                _ => binary(op, a, b),
+            }
+        }
+        Ternary(op, a, b, c) => {
+            let (a, b, c) = (simplify_expr(*a), simplify_expr(*b), simplify_expr(*c));
+            if let (Literal(a), Literal(b), Literal(c)) = (&a, &b, &c) {
+                return Literal(op.run(*a, *b, *c));
+            }
+            match (op, &a) {
+                (TernaryOpF64::MulAdd, _) if is_literal(&a, 1.0) => simplify_expr(binary(BinaryOpF64::Add, b, c)),
+                (TernaryOpF64::MulAdd, _) if is_literal(&b, 1.0) => simplify_expr(binary(BinaryOpF64::Add, a, c)),
+                (TernaryOpF64::MulAdd, _) if is_literal(&c, -0.0) => binary(BinaryOpF64::Mul, a, b),
+                (TernaryOpF64::Select, &Literal(cond)) => if cond != 0.0 { b } else { c },
+                (TernaryOpF64::IfLt, &Literal(cond)) => if cond < 0.0 { b } else { c },
+                (TernaryOpF64::IfGe, &Literal(cond)) => if cond >= 0.0 { b } else { c },
+                _ => ternary(op, a, b, c),
+            }
+        }
+        leaf => leaf,
+    }
+}
+
+/// Returns an equivalent program with the rewrites listed in the module
+/// docs applied until none is left. Constants are kept.
+pub fn simplify(program: &ProgramF64) -> Result<ProgramF64, ProgramError> {
+    write(&simplify_expr(read(program)?), program)
+}
+
+/// What to differentiate by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    Input(Index),
+    Constant(Index),
+}
+
+/// Derivative of an expression, `None` where it is zero everywhere.
+type Derivative = Option<Expr>;
+
+fn add(a: Derivative, b: Derivative) -> Derivative {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(binary(BinaryOpF64::Add, a, b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+fn sub(a: Derivative, b: Derivative) -> Derivative {
+    match (a, b) {
+        (a, None) => a,
+        (a, Some(b)) => Some(binary(BinaryOpF64::Sub, a.unwrap_or(Literal(0.0)), b)),
+    }
+}
+
+fn mul(a: Derivative, b: &Expr) -> Derivative {
+    a.map(|a| binary(BinaryOpF64::Mul, a, b.clone()))
+}
+
+fn or_zero(derivative: Derivative) -> Expr {
+    derivative.unwrap_or(Literal(0.0))
+}
+
+struct Differentiator {
+    variable: Variable,
+    /// `(let id, id of the let holding its derivative)` for the lets in
+    /// scope; `None` where the derivative is zero.
+    scope: Vec<(usize, Option<usize>)>,
+    next_id: usize,
+}
+
+impl Differentiator {
+    fn derive(&mut self, expr: &Expr) -> Result<Derivative, ProgramError> {
+        Ok(match expr {
+            Input(index) => (self.variable == Variable::Input(*index)).then_some(Literal(1.0)),
+            Constant(index) => (self.variable == Variable::Constant(*index)).then_some(Literal(1.0)),
+            Literal(_) => None,
+            Local(id) => {
+                let &(_, derivative) = self.scope.iter().rev().find(|(known, _)| known == id)
+                    .expect("locals refer to an enclosing let");
+                derivative.map(Local)
+            }
+            Let(id, value, body) => {
+                let value_derivative = self.derive(value)?;
+                let derivative_id = value_derivative.as_ref().map(|_| {
+                    self.next_id += 1;
+                    self.next_id - 1
+                });
+                self.scope.push((*id, derivative_id));
+                let body_derivative = self.derive(body);
+                self.scope.pop();
+                body_derivative?.map(|body_derivative| {
+                    let inner = match (derivative_id, value_derivative) {
+                        (Some(derivative_id), Some(value_derivative)) => {
+                            Let(derivative_id, Box::new(value_derivative), Box::new(body_derivative))
+                        }
+                        _ => body_derivative,
+                    };
+                    Let(*id, value.clone(), Box::new(inner))
+                })
+            }
+            Binary(op, a, b) => {
+                let (da, db) = (self.derive(a)?, self.derive(b)?);
+                let (a, b) = (&**a, &**b);
+                if da.is_none() && db.is_none() {
+                    return Ok(None);
+                }
+                match op {
+                    BinaryOpF64::Add => add(da, db),
+                    BinaryOpF64::Sub => sub(da, db),
+                    BinaryOpF64::Mul => add(mul(da, b), mul(db, a)),
+                    BinaryOpF64::Div => match db {
+                        None => da.map(|da| binary(BinaryOpF64::Div, da, b.clone())),
+                        db => sub(mul(da, b), mul(db, a))
+                            .map(|top| binary(BinaryOpF64::Div, top, binary(BinaryOpF64::Mul, b.clone(), b.clone()))),
+                    },
+                    BinaryOpF64::Min | BinaryOpF64::Max => {
+                        let (lower, higher) = if *op == BinaryOpF64::Min { (da, db) } else { (db, da) };
+                        let diff = binary(BinaryOpF64::Sub, a.clone(), b.clone());
+                        Some(ternary(TernaryOpF64::IfLt, diff, or_zero(lower), or_zero(higher)))
+                    }
+                    BinaryOpF64::Pow => {
+                        if db.is_some() {
+                            // would need a logarithm
+                            return Err(ProgramError::new(ErrorKind::UnsupportedOp));
+                        }
+                        let power = binary(BinaryOpF64::Pow, a.clone(), binary(BinaryOpF64::Sub, b.clone(), Literal(1.0)));
+                        mul(da, &binary(BinaryOpF64::Mul, b.clone(), power))
+                    }
+                    BinaryOpF64::Hypot => add(mul(da, a), mul(db, b))
+                        .map(|top| binary(BinaryOpF64::Div, top, binary(BinaryOpF64::Hypot, a.clone(), b.clone()))),
+                }
+            }
+            Ternary(op, a, b, c) => {
+                let (da, db, dc) = (self.derive(a)?, self.derive(b)?, self.derive(c)?);
+                let (a, b, c) = (&**a, &**b, &**c);
+                match op {
+                    TernaryOpF64::MulAdd => add(add(mul(da, b), mul(db, a)), dc),
+                    TernaryOpF64::Clamp => {
+                        if da.is_none() && db.is_none() && dc.is_none() {
+                            return Ok(None);
+                        }
+                        // raised to `b` first, then lowered to `c`
+                        let below = binary(BinaryOpF64::Sub, a.clone(), b.clone());
+                        let raised = ternary(TernaryOpF64::IfLt, below.clone(), b.clone(), a.clone());
+                        let draised = ternary(TernaryOpF64::IfLt, below, or_zero(db), or_zero(da));
+                        Some(ternary(TernaryOpF64::IfLt,
+                            binary(BinaryOpF64::Sub, c.clone(), raised), or_zero(dc), draised))
+                    }
+                    TernaryOpF64::Select | TernaryOpF64::IfLt | TernaryOpF64::IfGe => {
+                        if db.is_none() && dc.is_none() {
+                            return Ok(None);
+                        }
+                        Some(ternary(*op, a.clone(), or_zero(db), or_zero(dc)))
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn max_id(expr: &Expr) -> usize {
+    match expr {
+        Let(id, value, body) => (*id + 1).max(max_id(value)).max(max_id(body)),
+        Binary(_, a, b) => max_id(a).max(max_id(b)),
+        Ternary(_, a, b, c) => max_id(a).max(max_id(b)).max(max_id(c)),
+        Input(_) | Constant(_) | Literal(_) | Local(_) => 0,
+    }
+}
+
+/// The derivative by one input or constant, simplified. Conditionals
+/// are differentiated piecewise, so at the points where `min`, `max`,
+/// `clamp` or a condition switch sides the result is one of the one-sided
+/// derivatives. `pow` is only supported with an exponent that does not
+/// depend on `variable`.
+pub fn derivative(program: &ProgramF64, variable: Variable) -> Result<ProgramF64, ProgramError> {
+    let expr = read(program)?;
+    let mut differentiator = Differentiator { variable, scope: Vec::new(), next_id: max_id(&expr) };
+    let derivative = differentiator.derive(&expr)?;
+    write(&simplify_expr(or_zero(derivative)), program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse::{parse_infix, Symbols};
+    use crate::rng::Rng;
+    use crate::corpus;
+
+    fn parse(source: &str) -> ProgramF64 {
+        parse_infix(source, &Symbols::new(&["x", "y"], &["k"])).unwrap()
+    }
+
+    #[test]
+    fn simplify_keeps_values() {
+        let cases = [
+            ("x * 1 - 0", "x"),
+            ("(2 * 3 + (-0.0 + x)) / 1", "6.0 + x"),
+            ("mul_add(x, 1, y) + pow(y, 1)", "x + y + y"),
+            ("mul_add(x, y, -0.0)", "x * y"),
+            ("select(0, x, if_lt(-1, y, x))", "y"),
+            ("let t = x in let u = t * t in u + let v = y * y in v * v", "x * x + let v = y * y in v * v"),
+            ("x + 0", "x + 0.0"),
+            ("0 * x", "0.0 * x"),
+        ];
+        let mut rng = Rng::new(11);
+        for (source, expected) in cases.iter() {
+            let mut program = parse(source);
+            let mut simplified = simplify(&program).unwrap();
+            assert_eq!(format!("{:?}", simplified), format!("{:?}", parse(expected)), "{}", source);
+            for _ in 0..100 {
+                let inputs = [rng.next_f64() * 4.0 - 2.0, rng.next_f64() * 4.0 - 2.0];
+                assert_eq!(simplified.eval(&inputs).unwrap().to_bits(), program.eval(&inputs).unwrap().to_bits());
+            }
+            for inputs in [[0.0, -0.0], [-0.0, 0.0], [f64::NAN, f64::INFINITY]].iter() {
+                assert_eq!(simplified.eval(inputs).unwrap().to_bits(), program.eval(inputs).unwrap().to_bits(),
+                    "{} at {:?}", source, inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn derivatives() {
+        let cases = [
+            ("x * x", Variable::Input(0), "x + x"),
+            ("x * y + k", Variable::Input(1), "x"),
+            ("x * y + k", Variable::Constant(0), "1.0"),
+            ("pow(x, 3)", Variable::Input(0), "3.0 * pow(x, 2.0)"),
+            ("let t = x * x in t * y", Variable::Input(0), "(x + x) * y"),
+            ("let t = x * x in t * t", Variable::Input(0), "let t = x * x in let dt = x + x in dt * t + dt * t"),
+            ("max(x, y) / y", Variable::Input(0), "if_lt(x - y, 0.0, 1.0) / y"),
+        ];
+        for (source, variable, expected) in cases.iter() {
+            let program = parse(source);
+            let derived = derivative(&program, *variable).unwrap();
+            assert_eq!(format!("{:?}", derived), format!("{:?}", parse(expected)), "{}", source);
+        }
+        assert_eq!(derivative(&parse("pow(2, x)"), Variable::Input(0)).unwrap_err().kind, ErrorKind::UnsupportedOp);
+    }
+
+    #[test]
+    fn sin_derivative_is_cos() {
+        let sin = corpus::sin();
+        let mut cos = derivative(&sin, Variable::Input(0)).unwrap();
+        for &x in [-0.7, -0.2, 0.0, 0.1, 0.5, 0.78].iter() {
+            let value: f64 = cos.eval(&[x]).unwrap();
+            assert!((value - f64::cos(x)).abs() < 1e-9, "{} {}", x, value);
+        }
+    }
+
+    #[test]
+    fn non_lexical_locals() {
+        let program = ProgramF64::new(vec![
+            Node::BinaryOp(BinaryOpF64::Add),
+            Node::Lettuce, Node::Input(0), Node::Local(0),
+            Node::Local(0),
+        ]).unwrap();
+        let error = simplify(&program).unwrap_err();
+        assert_eq!((error.kind, error.position), (ErrorKind::InvalidLocal, Some(4)));
+        let error = simplify(&crate::corpus::sin_cos()).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::MultipleOutputs);
+    }
+}
@@ -1,67 +1,168 @@
+use crate::binary_op::OpList;
+
+/// The conditional ops pick `b` or `c` depending on `a`: `Select` takes `b`
+/// when `a` is non-zero, `IfLt` when `a < 0` and `IfGe` when `a >= 0`.
+/// A NaN condition counts as non-zero but fails both comparisons.
+///
+/// `Clamp` raises `a` to `b` when it is below, then lowers the result to
+/// `c` when it is above, i.e. `min(max(a, b), c)` for every type. Reversed
+/// bounds give `c`, and a comparison with NaN leaves the value as it is,
+/// so a NaN `a` stays NaN and a NaN bound is ignored. Every backend
+/// follows this rule.
 pub trait TernaryOp<T> {
     fn run(&self, a: T, b: T, c: T) -> T;
     fn repr(&self) -> &'static str;
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TernaryOpF32 {
+    #[cfg_attr(feature = "serde", serde(rename = "mul_add"))]
     MulAdd,
+    #[cfg_attr(feature = "serde", serde(rename = "clamp"))]
     Clamp,
+    #[cfg_attr(feature = "serde", serde(rename = "select"))]
+    Select,
+    #[cfg_attr(feature = "serde", serde(rename = "if_lt"))]
+    IfLt,
+    #[cfg_attr(feature = "serde", serde(rename = "if_ge"))]
+    IfGe,
+}
+
+impl OpList for TernaryOpF32 {
+    const ALL: &'static [Self] = &[
+        TernaryOpF32::MulAdd,
+        TernaryOpF32::Clamp,
+        TernaryOpF32::Select,
+        TernaryOpF32::IfLt,
+        TernaryOpF32::IfGe,
+    ];
 }
 
 impl TernaryOp<f32> for TernaryOpF32 {
     fn run(&self, a: f32, b: f32, c: f32) -> f32 {
         match self {
             TernaryOpF32::MulAdd => a.mul_add(b, c),
-            TernaryOpF32::Clamp => a.clamp(b, c),
+            TernaryOpF32::Clamp => {
+                let a = if a < b { b } else { a };
+                if a > c { c } else { a }
+            }
+            TernaryOpF32::Select => if a != 0.0 { b } else { c },
+            TernaryOpF32::IfLt => if a < 0.0 { b } else { c },
+            TernaryOpF32::IfGe => if a >= 0.0 { b } else { c },
         }
     }
     fn repr(&self) -> &'static str {
         match self {
             TernaryOpF32::MulAdd => "mul_add",
             TernaryOpF32::Clamp => "clamp",
+            TernaryOpF32::Select => "select",
+            TernaryOpF32::IfLt => "if_lt",
+            TernaryOpF32::IfGe => "if_ge",
         }
     }
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TernaryOpF64 {
+    #[cfg_attr(feature = "serde", serde(rename = "mul_add"))]
     MulAdd,
+    #[cfg_attr(feature = "serde", serde(rename = "clamp"))]
     Clamp,
+    #[cfg_attr(feature = "serde", serde(rename = "select"))]
+    Select,
+    #[cfg_attr(feature = "serde", serde(rename = "if_lt"))]
+    IfLt,
+    #[cfg_attr(feature = "serde", serde(rename = "if_ge"))]
+    IfGe,
+}
+
+impl OpList for TernaryOpF64 {
+    const ALL: &'static [Self] = &[
+        TernaryOpF64::MulAdd,
+        TernaryOpF64::Clamp,
+        TernaryOpF64::Select,
+        TernaryOpF64::IfLt,
+        TernaryOpF64::IfGe,
+    ];
 }
 
 impl TernaryOp<f64> for TernaryOpF64 {
     fn run(&self, a: f64, b: f64, c: f64) -> f64 {
         match self {
             TernaryOpF64::MulAdd => a.mul_add(b, c),
-            TernaryOpF64::Clamp => a.clamp(b, c),
+            TernaryOpF64::Clamp => {
+                let a = if a < b { b } else { a };
+                if a > c { c } else { a }
+            }
+            TernaryOpF64::Select => if a != 0.0 { b } else { c },
+            TernaryOpF64::IfLt => if a < 0.0 { b } else { c },
+            TernaryOpF64::IfGe => if a >= 0.0 { b } else { c },
         }
     }
     fn repr(&self) -> &'static str {
         match self {
             TernaryOpF64::MulAdd => "mul_add",
             TernaryOpF64::Clamp => "clamp",
+            TernaryOpF64::Select => "select",
+            TernaryOpF64::IfLt => "if_lt",
+            TernaryOpF64::IfGe => "if_ge",
         }
     }
 }
 
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy)]
-pub enum TernaryOpI32 {
-    Clamp,
-}
+macro_rules! integer_ternary_op {
+    ($name:ident, $t:ty) => {
+        #[allow(dead_code)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum $name {
+            #[cfg_attr(feature = "serde", serde(rename = "clamp"))]
+            Clamp,
+            #[cfg_attr(feature = "serde", serde(rename = "select"))]
+            Select,
+            #[cfg_attr(feature = "serde", serde(rename = "if_lt"))]
+            IfLt,
+            #[cfg_attr(feature = "serde", serde(rename = "if_ge"))]
+            IfGe,
+        }
 
-impl TernaryOp<i32> for TernaryOpI32 {
-    fn run(&self, a: i32, b: i32, c: i32) -> i32 {
-        match self {
-            TernaryOpI32::Clamp => a.max(b).min(c),
+        impl OpList for $name {
+            const ALL: &'static [Self] = &[
+                $name::Clamp,
+                $name::Select,
+                $name::IfLt,
+                $name::IfGe,
+            ];
         }
-    }
-    fn repr(&self) -> &'static str {
-        match self {
-            TernaryOpI32::Clamp => "clamp",
+
+        impl TernaryOp<$t> for $name {
+            fn run(&self, a: $t, b: $t, c: $t) -> $t {
+                match self {
+                    $name::Clamp => a.max(b).min(c),
+                    $name::Select => if a != 0 { b } else { c },
+                    #[allow(unused_comparisons)]
+                    $name::IfLt => if a < 0 { b } else { c },
+                    #[allow(unused_comparisons)]
+                    $name::IfGe => if a >= 0 { b } else { c },
+                }
+            }
+            fn repr(&self) -> &'static str {
+                match self {
+                    $name::Clamp => "clamp",
+                    $name::Select => "select",
+                    $name::IfLt => "if_lt",
+                    $name::IfGe => "if_ge",
+                }
+            }
         }
-    }
+    };
 }
+
+integer_ternary_op!(TernaryOpI32, i32);
+integer_ternary_op!(TernaryOpU32, u32);
+integer_ternary_op!(TernaryOpI64, i64);
+integer_ternary_op!(TernaryOpU64, u64);
@@ -0,0 +1,289 @@
+use core::fmt;
+
+use crate::binary_op::BinaryOp;
+use crate::ternary_op::TernaryOp;
+use crate::program::{check_reads, validate_trees, ErrorKind, Node, Program, ProgramError, ProgramInfo};
+
+/// A program whose constants are known to cover every `Constant` node and
+/// which contains no fallible ops or calls. Evaluation skips the per-node bounds
+/// checks of `Program::eval` and returns the value directly; in debug
+/// builds every access is still asserted.
+pub struct VerifiedProgram<T, BOP, TOP>
+    where BOP: BinaryOp<T> + Copy,
+          TOP: TernaryOp<T> + Copy
+{
+    program: Program<T, BOP, TOP>,
+}
+
+impl<T, BOP, TOP> Program<T, BOP, TOP>
+    where T: Copy + fmt::Debug,
+          BOP: Copy + PartialEq + BinaryOp<T>,
+          TOP: Copy + PartialEq + TernaryOp<T>
+{
+    /// `nodes` is public and may have changed since the program was built,
+    /// so the checks `Program::new` ran are repeated here and `info` is
+    /// replaced with what they find. Calls are inlined first, see
+    /// `inline_calls`. A program that fails is handed back with the error.
+    // both variants hold the program, boxing the error would not shrink
+    // the result
+    #[allow(clippy::result_large_err)]
+    pub fn verify(mut self) -> Result<VerifiedProgram<T, BOP, TOP>, (Self, ProgramError)> {
+        if !self.functions.is_empty() {
+            return match self.inline_calls() {
+                Ok(inlined) => inlined.verify().map_err(|(_, error)| (self, error)),
+                Err(error) => Err((self, error)),
+            };
+        }
+        match self.verified_info() {
+            Ok(info) => {
+                self.info = info;
+                Ok(VerifiedProgram { program: self })
+            }
+            Err(error) => Err((self, error)),
+        }
+    }
+
+    fn verified_info(&self) -> Result<ProgramInfo<BOP, TOP>, ProgramError> {
+        let mut info = validate_trees(&self.nodes, true)?;
+        check_reads(&self.nodes, self.literals.len(), &self.functions)?;
+        let constant_count = self.constants.len();
+        info.unset_constants.retain(|&index| index >= constant_count);
+        if !info.unset_constants.is_empty() {
+            return Err(ProgramError::new(ErrorKind::TooFewConstants)
+                .counts(info.constant_count, constant_count));
+        }
+        for (position, node) in self.nodes.iter().enumerate() {
+            if let Node::BinaryOp(op) = node {
+                if op.is_fallible() {
+                    return Err(ProgramError::new(ErrorKind::FallibleOp).at(position));
+                }
+            }
+        }
+        Ok(info)
+    }
+}
+
+impl<T, BOP, TOP> VerifiedProgram<T, BOP, TOP>
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>
+{
+    pub fn program(&self) -> &Program<T, BOP, TOP> {
+        &self.program
+    }
+
+    pub fn into_inner(self) -> Program<T, BOP, TOP> {
+        self.program
+    }
+
+    pub fn set_constants(&mut self, constants: &[T]) -> Result<(), ProgramError> {
+        if constants.len() < self.program.info.constant_count {
+            return Err(ProgramError::new(ErrorKind::TooFewConstants)
+                .counts(self.program.info.constant_count, constants.len()));
+        }
+        self.program.constants.clear();
+        self.program.constants.extend(constants.iter().copied());
+        self.program.info.unset_constants.clear();
+        Ok(())
+    }
+
+    /// Evaluates the first output. Panics if `inputs` is shorter than
+    /// `ProgramInfo::input_count`.
+    pub fn eval(&mut self, inputs: &[T]) -> T {
+        assert!(inputs.len() >= self.program.info.input_count,
+            "expected {} inputs, got {}", self.program.info.input_count, inputs.len());
+
+        let program = &mut self.program;
+        program.position = 0;
+        program.locals.clear();
+        program.locals.reserve(program.info.local_count);
+        // SAFETY: `verify` validated the nodes, so they start with a complete
+        // tree, locals are only read after they are bound, literals exist and
+        // there are no calls; it also checked the constants, `set_constants`
+        // keeps them covering `constant_count`, and the assert above covers
+        // inputs.
+        unsafe { eval_unchecked(program, inputs) }
+    }
+}
+
+unsafe fn eval_unchecked<T, BOP, TOP>(program: &mut Program<T, BOP, TOP>, inputs: &[T]) -> T
+    where T: Copy,
+          BOP: Copy + BinaryOp<T>,
+          TOP: Copy + TernaryOp<T>
+{
+    debug_assert!(program.position < program.nodes.len());
+    let node = *program.nodes.get_unchecked(program.position);
+    program.position += 1;
+
+    match node {
+        Node::Input(index) => {
+            debug_assert!((index as usize) < inputs.len());
+            *inputs.get_unchecked(index as usize)
+        }
+        Node::Local(index) => {
+            debug_assert!((index as usize) < program.locals.len());
+            *program.locals.get_unchecked(index as usize)
+        }
+        Node::Constant(index) => {
+            debug_assert!((index as usize) < program.constants.len());
+            *program.constants.get_unchecked(index as usize)
+        }
+        Node::Literal(index) => {
+            debug_assert!((index as usize) < program.literals.len());
+            *program.literals.get_unchecked(index as usize)
+        }
+        Node::Lettuce => {
+            let value = eval_unchecked(program, inputs);
+            program.locals.push(value);
+            eval_unchecked(program, inputs)
+        }
+        Node::BinaryOp(op) => {
+            let lhs = eval_unchecked(program, inputs);
+            let rhs = eval_unchecked(program, inputs);
+            op.run(lhs, rhs)
+        }
+        Node::TernaryOp(op) => {
+            let a = eval_unchecked(program, inputs);
+            let b = eval_unchecked(program, inputs);
+            let c = eval_unchecked(program, inputs);
+            op.run(a, b, c)
+        }
+        Node::Call(..) => unreachable!("`verify` inlines calls"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_op::BinaryOpI32;
+    use crate::program::{Index, NodeI32, ProgramI32};
+    use crate::rng::Rng;
+    use crate::ternary_op::TernaryOpI32;
+
+    const BINARY: &[BinaryOpI32] = &[
+        BinaryOpI32::Add, BinaryOpI32::Sub, BinaryOpI32::Mul, BinaryOpI32::Div,
+        BinaryOpI32::Shl, BinaryOpI32::Shr, BinaryOpI32::Lt, BinaryOpI32::CheckedMul,
+    ];
+    const TERNARY: &[TernaryOpI32] = &[TernaryOpI32::Clamp, TernaryOpI32::Select];
+
+    fn random_leaf(rng: &mut Rng, lets: usize) -> NodeI32 {
+        let index = rng.below(4) as Index;
+        match rng.below(4) {
+            0 => Node::Input(index),
+            1 => Node::Constant(index),
+            2 => Node::Literal(index),
+            _ => Node::Local(rng.below(lets + 1) as Index),
+        }
+    }
+
+    fn random_tree(rng: &mut Rng, depth: usize, lets: &mut usize, nodes: &mut Vec<NodeI32>) {
+        if depth == 0 || rng.below(3) == 0 {
+            nodes.push(random_leaf(rng, *lets));
+            return;
+        }
+        let arity = match rng.below(4) {
+            0 => {
+                nodes.push(Node::Lettuce);
+                random_tree(rng, depth - 1, lets, nodes);
+                *lets += 1;
+                1
+            }
+            1 => {
+                nodes.push(Node::TernaryOp(TERNARY[rng.below(TERNARY.len())]));
+                3
+            }
+            _ => {
+                nodes.push(Node::BinaryOp(BINARY[rng.below(BINARY.len())]));
+                2
+            }
+        };
+        for _ in 0..arity {
+            random_tree(rng, depth - 1, lets, nodes);
+        }
+    }
+
+    /// Feeds random and corrupted node vectors through `Program::new`; every
+    /// accepted program must evaluate in the verified evaluator (whose debug
+    /// asserts catch any out of bounds access) to the same value as
+    /// `Program::eval`.
+    #[test]
+    fn fuzz_verified_eval() {
+        let mut rng = Rng::new(0x5eed);
+        let mut accepted = 0;
+
+        for _ in 0..20_000 {
+            let mut nodes = Vec::new();
+            random_tree(&mut rng, 6, &mut 0, &mut nodes);
+            for _ in 0..rng.below(3) {
+                if nodes.is_empty() {
+                    break;
+                }
+                let at = rng.below(nodes.len());
+                let leaf = random_leaf(&mut rng, 4);
+                match rng.below(3) {
+                    0 => nodes[at] = leaf,
+                    1 => nodes.insert(at, leaf),
+                    _ => { nodes.remove(at); }
+                }
+            }
+
+            let literals: Vec<i32> = (0..rng.below(5)).map(|_| rng.next_u64() as i32).collect();
+            let constants: Vec<i32> = (0..rng.below(5)).map(|_| rng.next_u64() as i32).collect();
+            let inputs: Vec<i32> = (0..4).map(|_| rng.next_u64() as i32).collect();
+
+            let mut program = match ProgramI32::with_literals(nodes, literals) {
+                Ok(program) => program,
+                Err(_) => continue,
+            };
+            let enough_constants = constants.len() >= program.info().constant_count;
+            program.set_constants(&constants).ok();
+            let expected = program.eval(&inputs);
+
+            let mut verified = match program.verify() {
+                Ok(verified) => verified,
+                Err((_, error)) => {
+                    assert!(!enough_constants || error.kind == ErrorKind::FallibleOp);
+                    continue;
+                }
+            };
+            accepted += 1;
+            assert_eq!(expected.ok(), Some(verified.eval(&inputs)));
+        }
+
+        assert!(accepted > 1000, "only {} programs accepted", accepted);
+    }
+
+    #[test]
+    fn rejects_fallible() {
+        let program = ProgramI32::new(vec![
+            Node::BinaryOp(BinaryOpI32::CheckedAdd),
+            Node::Input(0),
+            Node::Input(1),
+        ]).unwrap();
+        let (_, error) = program.verify().err().unwrap();
+        assert_eq!(error.kind, ErrorKind::FallibleOp);
+        assert_eq!(error.position, Some(0));
+    }
+
+    #[test]
+    fn revalidates_nodes() {
+        let mut program = ProgramI32::new(vec![ Node::Input(0) ]).unwrap();
+        program.nodes = vec![ Node::Local(0) ];
+        assert_eq!(program.verify().err().unwrap().1.kind, ErrorKind::InvalidLocal);
+
+        let mut program = ProgramI32::new(vec![ Node::Input(0) ]).unwrap();
+        program.nodes = vec![ Node::Literal(0) ];
+        assert_eq!(program.verify().err().unwrap().1.kind, ErrorKind::NonExistentLiteral);
+
+        let mut program = ProgramI32::new(vec![ Node::Constant(0) ]).unwrap();
+        program.set_constants(&[5]).unwrap();
+        program.nodes = vec![ Node::BinaryOp(BinaryOpI32::Add), Node::Constant(0), Node::Constant(1) ];
+        assert_eq!(program.verify().err().unwrap().1.kind, ErrorKind::TooFewConstants);
+
+        let mut program = ProgramI32::new(vec![ Node::Input(0) ]).unwrap();
+        program.nodes = vec![ Node::BinaryOp(BinaryOpI32::Add), Node::Input(0), Node::Input(1) ];
+        let mut verified = program.verify().unwrap();
+        assert_eq!(verified.program().info().input_count, 2);
+        assert_eq!(verified.eval(&[2, 3]), 5);
+    }
+}